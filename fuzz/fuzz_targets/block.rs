@@ -0,0 +1,14 @@
+#![no_main]
+
+use handshaker::messages::{block::Block, transaction::Transaction};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|bytes: &[u8]| {
+    if let Ok(transaction) = Transaction::try_from(bytes) {
+        assert_eq!(transaction.serialize(), bytes);
+    }
+    if let Ok(block) = Block::try_from(bytes) {
+        block.check_merkle_root();
+        block.check_witness_commitment();
+    }
+});
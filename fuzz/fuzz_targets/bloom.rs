@@ -0,0 +1,15 @@
+#![no_main]
+
+use handshaker::messages::{bloom::BloomFilter, merkle_block::MerkleBlock};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|bytes: &[u8]| {
+    if let Ok(filter) = BloomFilter::try_from(bytes) {
+        assert!(bytes.starts_with(&filter.payload()));
+        let _ = filter.contains(bytes);
+    }
+    if let Ok(merkle_block) = MerkleBlock::try_from(bytes) {
+        assert!(bytes.starts_with(&merkle_block.payload()));
+        let _ = merkle_block.matched_txids();
+    }
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+use handshaker::messages::message::{command_name, MessageCommand};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|command: [u8; 12]| {
+    let name = command_name(&command);
+    if let Ok(parsed) = MessageCommand::try_from(command) {
+        // Recognized commands are ASCII and round-trip
+        assert!(name.is_ascii());
+        assert_eq!(<[u8; 12]>::from(parsed), command);
+    }
+});
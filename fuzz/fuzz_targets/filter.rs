@@ -0,0 +1,17 @@
+#![no_main]
+
+use handshaker::{
+    block_filter::BlockFilter,
+    messages::filter::{CFCheckpt, CFHeaders, CFilter},
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|bytes: &[u8]| {
+    if let Ok(cfilter) = CFilter::try_from(bytes) {
+        assert!(bytes.starts_with(&cfilter.payload()));
+        let filter = BlockFilter::new(cfilter.filter);
+        let _ = filter.match_any(&cfilter.block_hash, &[[0x51], [0x52]]);
+    }
+    let _ = CFHeaders::try_from(bytes);
+    let _ = CFCheckpt::try_from(bytes);
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+use handshaker::messages::message::MessageHeader;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(header) = MessageHeader::try_from(data) {
+        // Every header that parses serializes back to the bytes it was parsed from
+        let bytes = bincode::serialize(&header).unwrap();
+        assert_eq!(bytes, data[..24]);
+        let _ = header.checksum_matches(&data[24..]);
+    }
+});
@@ -0,0 +1,15 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use handshaker::{chain_params::MAIN, decode::decode_stream, message_reader::MessageReader};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Reads frames until the stream ends or is rejected
+    let mut reader = MessageReader::new(Box::new(Cursor::new(data.to_vec())), &MAIN);
+    while reader.read_message().is_ok() {
+        assert!(reader.payload().len() <= MessageReader::MAX_PAYLOAD_SIZE as usize);
+    }
+    let _ = decode_stream(data);
+});
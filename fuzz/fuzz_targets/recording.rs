@@ -0,0 +1,10 @@
+#![no_main]
+
+use handshaker::capture::recording::{read_recording, MAX_FRAME_LEN};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(records) = read_recording(data) {
+        assert!(records.iter().all(|record| record.frame.len() <= MAX_FRAME_LEN));
+    }
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use handshaker::messages::version::PeerVersion;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|payload: &[u8]| {
+    let _ = PeerVersion::try_from(payload);
+});
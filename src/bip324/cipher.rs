@@ -0,0 +1,466 @@
+use std::io;
+
+use chacha20::{
+    cipher::{KeyIvInit, StreamCipher, StreamCipherSeek},
+    ChaCha20,
+};
+use chacha20poly1305::{aead::AeadInPlace, ChaCha20Poly1305, KeyInit, Tag};
+use hkdf::Hkdf;
+use secp256k1::{
+    ellswift::{ElligatorSwift, ElligatorSwiftParty},
+    SecretKey,
+};
+use sha2::Sha256;
+
+/// Number of chunks (lengths or packets) after which both ciphers switch to a new key.
+const REKEY_INTERVAL: u32 = 224;
+/// Size of the encrypted length field of a packet.
+pub const LENGTH_FIELD_LEN: usize = 3;
+/// Size of the header byte in front of the packet contents.
+pub const HEADER_LEN: usize = 1;
+/// Size of the Poly1305 authentication tag.
+pub const TAG_LEN: usize = 16;
+/// Size of a garbage terminator.
+pub const GARBAGE_TERMINATOR_LEN: usize = 16;
+/// Size of a ChaCha20 keystream block.
+const CHACHA20_BLOCK_LEN: u64 = 64;
+/// Header bit marking a packet that must be ignored by the receiver (decoy).
+const IGNORE_BIT: u8 = 0x80;
+
+/// Keys derived from the ECDH shared secret of a v2 connection.
+pub struct SessionKeys {
+    /// Identifies the session, both peers compute the same value.
+    pub session_id: [u8; 32],
+    initiator_l: [u8; 32],
+    initiator_p: [u8; 32],
+    responder_l: [u8; 32],
+    responder_p: [u8; 32],
+    initiator_garbage_terminator: [u8; GARBAGE_TERMINATOR_LEN],
+    responder_garbage_terminator: [u8; GARBAGE_TERMINATOR_LEN],
+}
+
+impl SessionKeys {
+    /// Derives the session keys from both ElligatorSwift encoded public keys and our secret key.
+    ///
+    /// # Arguments
+    ///
+    /// * `ellswift_ours` - Our encoded public key.
+    /// * `ellswift_theirs` - The peer's encoded public key.
+    /// * `secret_key` - Our secret key.
+    /// * `initiating` - Whether we opened the connection.
+    /// * `magic` - Magic bytes of the network, they salt the key derivation.
+    pub fn derive(
+        ellswift_ours: ElligatorSwift,
+        ellswift_theirs: ElligatorSwift,
+        secret_key: SecretKey,
+        initiating: bool,
+        magic: [u8; 4],
+    ) -> Self {
+        let shared_secret = if initiating {
+            ElligatorSwift::shared_secret(
+                ellswift_ours,
+                ellswift_theirs,
+                secret_key,
+                ElligatorSwiftParty::A,
+                None,
+            )
+        } else {
+            ElligatorSwift::shared_secret(
+                ellswift_theirs,
+                ellswift_ours,
+                secret_key,
+                ElligatorSwiftParty::B,
+                None,
+            )
+        };
+
+        let salt = [&b"bitcoin_v2_shared_secret"[..], &magic].concat();
+        let hkdf = Hkdf::<Sha256>::new(Some(&salt), shared_secret.as_secret_bytes());
+        let expand = |info: &[u8]| {
+            let mut key = [0u8; 32];
+            hkdf.expand(info, &mut key)
+                .expect("32 bytes is a valid HKDF-SHA256 output length");
+            key
+        };
+        let garbage_terminators = expand(b"garbage_terminators");
+        let mut initiator_garbage_terminator = [0u8; GARBAGE_TERMINATOR_LEN];
+        initiator_garbage_terminator.copy_from_slice(&garbage_terminators[..16]);
+        let mut responder_garbage_terminator = [0u8; GARBAGE_TERMINATOR_LEN];
+        responder_garbage_terminator.copy_from_slice(&garbage_terminators[16..]);
+
+        Self {
+            session_id: expand(b"session_id"),
+            initiator_l: expand(b"initiator_L"),
+            initiator_p: expand(b"initiator_P"),
+            responder_l: expand(b"responder_L"),
+            responder_p: expand(b"responder_P"),
+            initiator_garbage_terminator,
+            responder_garbage_terminator,
+        }
+    }
+}
+
+/// Everything needed to send and receive packets in one session.
+pub struct Session {
+    /// Encrypts the packets we send.
+    pub encoder: PacketEncoder,
+    /// Decrypts the packets we receive.
+    pub decoder: PacketDecoder,
+    /// Terminator we send after our garbage.
+    pub send_garbage_terminator: [u8; GARBAGE_TERMINATOR_LEN],
+    /// Terminator the peer sends after its garbage.
+    pub recv_garbage_terminator: [u8; GARBAGE_TERMINATOR_LEN],
+}
+
+impl Session {
+    /// Sets up the ciphers of both directions.
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - The derived session keys.
+    /// * `initiating` - Whether we opened the connection.
+    pub fn new(keys: &SessionKeys, initiating: bool) -> Self {
+        let (send_l, send_p, recv_l, recv_p) = if initiating {
+            (
+                keys.initiator_l,
+                keys.initiator_p,
+                keys.responder_l,
+                keys.responder_p,
+            )
+        } else {
+            (
+                keys.responder_l,
+                keys.responder_p,
+                keys.initiator_l,
+                keys.initiator_p,
+            )
+        };
+        let (send_garbage_terminator, recv_garbage_terminator) = if initiating {
+            (
+                keys.initiator_garbage_terminator,
+                keys.responder_garbage_terminator,
+            )
+        } else {
+            (
+                keys.responder_garbage_terminator,
+                keys.initiator_garbage_terminator,
+            )
+        };
+        Self {
+            encoder: PacketEncoder {
+                length: FsChaCha20::new(send_l),
+                aead: FsChaCha20Poly1305::new(send_p),
+            },
+            decoder: PacketDecoder {
+                length: FsChaCha20::new(recv_l),
+                aead: FsChaCha20Poly1305::new(recv_p),
+            },
+            send_garbage_terminator,
+            recv_garbage_terminator,
+        }
+    }
+}
+
+/// Encrypts outgoing packets.
+pub struct PacketEncoder {
+    length: FsChaCha20,
+    aead: FsChaCha20Poly1305,
+}
+
+impl PacketEncoder {
+    /// Encrypts `contents` into a packet: encrypted length, encrypted header and contents,
+    /// authentication tag.
+    ///
+    /// # Arguments
+    ///
+    /// * `contents` - The packet contents.
+    /// * `aad` - Additional authenticated data, the sent garbage for the first packet.
+    /// * `ignore` - Whether the packet is a decoy the peer must ignore.
+    pub fn encrypt(&mut self, contents: &[u8], aad: &[u8], ignore: bool) -> Vec<u8> {
+        let mut length = (contents.len() as u32).to_le_bytes();
+        self.length.crypt(&mut length[..LENGTH_FIELD_LEN]);
+
+        let mut packet = Vec::with_capacity(HEADER_LEN + contents.len() + TAG_LEN);
+        packet.push(if ignore { IGNORE_BIT } else { 0 });
+        packet.extend_from_slice(contents);
+        self.aead.encrypt(aad, &mut packet);
+
+        [&length[..LENGTH_FIELD_LEN], &packet[..]].concat()
+    }
+}
+
+/// Decrypts incoming packets.
+pub struct PacketDecoder {
+    length: FsChaCha20,
+    aead: FsChaCha20Poly1305,
+}
+
+impl PacketDecoder {
+    /// Decrypts the length field of a packet and returns how many bytes of the packet are left
+    /// to read: header, contents and tag.
+    ///
+    /// # Arguments
+    ///
+    /// * `length` - The encrypted length field.
+    pub fn decrypt_length(&mut self, mut length: [u8; LENGTH_FIELD_LEN]) -> usize {
+        self.length.crypt(&mut length);
+        let contents_len = u32::from_le_bytes([length[0], length[1], length[2], 0]) as usize;
+        HEADER_LEN + contents_len + TAG_LEN
+    }
+
+    /// Decrypts and authenticates the rest of a packet. Returns whether the packet is a decoy,
+    /// and its contents.
+    ///
+    /// # Arguments
+    ///
+    /// * `packet` - Encrypted header, contents and tag, as many bytes as `decrypt_length` said.
+    /// * `aad` - Additional authenticated data, the received garbage for the first packet.
+    pub fn decrypt(&mut self, mut packet: Vec<u8>, aad: &[u8]) -> io::Result<(bool, Vec<u8>)> {
+        self.aead.decrypt(aad, &mut packet)?;
+        let ignore = packet[0] & IGNORE_BIT != 0;
+        packet.remove(0);
+        Ok((ignore, packet))
+    }
+}
+
+/// ChaCha20 stream cipher used for the packet lengths. The keystream continues from one
+/// chunk to the next and the key is replaced by keystream every `REKEY_INTERVAL` chunks.
+struct FsChaCha20 {
+    cipher: ChaCha20,
+    chunk_counter: u32,
+    rekey_counter: u64,
+}
+
+impl FsChaCha20 {
+    fn new(key: [u8; 32]) -> Self {
+        Self {
+            cipher: ChaCha20::new(&key.into(), &nonce(0, 0).into()),
+            chunk_counter: 0,
+            rekey_counter: 0,
+        }
+    }
+
+    fn crypt(&mut self, chunk: &mut [u8]) {
+        self.cipher.apply_keystream(chunk);
+        self.chunk_counter += 1;
+        if self.chunk_counter == REKEY_INTERVAL {
+            let mut key = [0u8; 32];
+            self.cipher.apply_keystream(&mut key);
+            self.chunk_counter = 0;
+            self.rekey_counter += 1;
+            self.cipher = ChaCha20::new(&key.into(), &nonce(0, self.rekey_counter).into());
+        }
+    }
+}
+
+/// ChaCha20-Poly1305 AEAD used for the packet contents. Every packet uses its own nonce and the
+/// key is replaced every `REKEY_INTERVAL` packets.
+struct FsChaCha20Poly1305 {
+    key: [u8; 32],
+    packet_counter: u32,
+    rekey_counter: u64,
+}
+
+impl FsChaCha20Poly1305 {
+    fn new(key: [u8; 32]) -> Self {
+        Self {
+            key,
+            packet_counter: 0,
+            rekey_counter: 0,
+        }
+    }
+
+    fn encrypt(&mut self, aad: &[u8], buffer: &mut Vec<u8>) {
+        let nonce = nonce(self.packet_counter, self.rekey_counter);
+        let tag = ChaCha20Poly1305::new(&self.key.into())
+            .encrypt_in_place_detached(&nonce.into(), aad, buffer)
+            .expect("packet contents are at most 2^24 bytes");
+        buffer.extend_from_slice(&tag);
+        self.next_packet();
+    }
+
+    fn decrypt(&mut self, aad: &[u8], buffer: &mut Vec<u8>) -> io::Result<()> {
+        if buffer.len() < HEADER_LEN + TAG_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "BIP324 packet too short",
+            ));
+        }
+        let nonce = nonce(self.packet_counter, self.rekey_counter);
+        let tag = Tag::clone_from_slice(&buffer.split_off(buffer.len() - TAG_LEN));
+        ChaCha20Poly1305::new(&self.key.into())
+            .decrypt_in_place_detached(&nonce.into(), aad, buffer, &tag)
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "BIP324 packet failed authentication",
+                )
+            })?;
+        self.next_packet();
+        Ok(())
+    }
+
+    fn next_packet(&mut self) {
+        self.packet_counter += 1;
+        if self.packet_counter == REKEY_INTERVAL {
+            // The new key is what the AEAD would encrypt 32 zero bytes to, under a nonce no
+            // packet ever uses. AEAD encryption skips the first block, it keys Poly1305.
+            let mut key = [0u8; 32];
+            let mut cipher = ChaCha20::new(
+                &self.key.into(),
+                &nonce(u32::MAX, self.rekey_counter).into(),
+            );
+            cipher.seek(CHACHA20_BLOCK_LEN);
+            cipher.apply_keystream(&mut key);
+            self.key = key;
+            self.packet_counter = 0;
+            self.rekey_counter += 1;
+        }
+    }
+}
+
+/// Builds a 96-bit ChaCha20 nonce from a 32-bit and a 64-bit counter, both little-endian.
+fn nonce(first: u32, second: u64) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[..4].copy_from_slice(&first.to_le_bytes());
+    nonce[4..].copy_from_slice(&second.to_le_bytes());
+    nonce
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Derives the session of one of the BIP324 packet encoding test vectors.
+    fn vector_session(
+        secret_key: &str,
+        ellswift_ours: &str,
+        ellswift_theirs: &str,
+        initiating: bool,
+    ) -> (SessionKeys, Session) {
+        let secret_key = SecretKey::from_slice(&hex::decode(secret_key).unwrap()).unwrap();
+        let decode = |ellswift: &str| {
+            let mut bytes = [0u8; 64];
+            hex::decode_to_slice(ellswift, &mut bytes).unwrap();
+            ElligatorSwift::from_array(bytes)
+        };
+        let keys = SessionKeys::derive(
+            decode(ellswift_ours),
+            decode(ellswift_theirs),
+            secret_key,
+            initiating,
+            [0xF9, 0xBE, 0xB4, 0xD9],
+        );
+        let session = Session::new(&keys, initiating);
+        (keys, session)
+    }
+
+    #[test]
+    fn test_vector_initiator() {
+        let (_, mut session) = vector_session(
+            "61062ea5071d800bbfd59e2e8b53d47d194b095ae5a4df04936b49772ef0d4d7",
+            "ec0adff257bbfe500c188c80b4fdd640f6b45a482bbc15fc7cef5931deff0aa186f6eb9bba7b85dc4dcc28b28722de1e3d9108b985e2967045668f66098e475b",
+            "a4a94dfce69b4a2a0a099313d10f9f7e7d649d60501c9e1d274c300e0d89aafaffffffffffffffffffffffffffffffffffffffffffffffffffffffff8faf88d5",
+            true,
+        );
+        session.encoder.encrypt(&[0u8; 100], &[], false);
+        let packet = session.encoder.encrypt(&[0x8e], &[], false);
+        assert_eq!(
+            hex::encode(packet),
+            "7530d2a18720162ac09c25329a60d75adf36eda3c3"
+        );
+    }
+
+    #[test]
+    fn test_vector_responder() {
+        let (keys, mut session) = vector_session(
+            "1f9c581b35231838f0f17cf0c979835baccb7f3abbbb96ffcc318ab71e6e126f",
+            "a1855e10e94e00baa23041d916e259f7044e491da6171269694763f018c7e63693d29575dcb464ac816baa1be353ba12e3876cba7628bd0bd8e755e721eb0140",
+            "fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f0000000000000000000000000000000000000000000000000000000000000000",
+            false,
+        );
+        assert_eq!(
+            hex::encode(keys.session_id),
+            "9267c54560607de73f18c563b76a2442718879c52dd39852885d4a3c9912c9ea"
+        );
+        for _ in 0..999 {
+            session.encoder.encrypt(&[], &[], false);
+        }
+        let contents = hex::decode("3eb1d4e98035cfd8eeb29bac969ed3824a").unwrap();
+        let packet = session.encoder.encrypt(&contents, &[], false);
+        assert_eq!(
+            hex::encode(packet),
+            "1da1bcf589f9b61872f45b7fa5371dd3f8bdf5d515b0c5f9fe9f0044afb8dc0aa1cd39a8c4"
+        );
+    }
+
+    /// Decrypts `packet` with the decoder of the other side of the session.
+    fn decrypt(decoder: &mut PacketDecoder, packet: &[u8]) -> (bool, Vec<u8>) {
+        let length = packet[..LENGTH_FIELD_LEN].try_into().unwrap();
+        let len = decoder.decrypt_length(length);
+        assert_eq!(len, packet.len() - LENGTH_FIELD_LEN);
+        decoder
+            .decrypt(packet[LENGTH_FIELD_LEN..].to_vec(), &[])
+            .unwrap()
+    }
+
+    #[test]
+    fn test_vector_decrypt() {
+        // The initiator vector, received by the responder
+        let (keys, mut session) = vector_session(
+            "61062ea5071d800bbfd59e2e8b53d47d194b095ae5a4df04936b49772ef0d4d7",
+            "ec0adff257bbfe500c188c80b4fdd640f6b45a482bbc15fc7cef5931deff0aa186f6eb9bba7b85dc4dcc28b28722de1e3d9108b985e2967045668f66098e475b",
+            "a4a94dfce69b4a2a0a099313d10f9f7e7d649d60501c9e1d274c300e0d89aafaffffffffffffffffffffffffffffffffffffffffffffffffffffffff8faf88d5",
+            true,
+        );
+        let mut decoder = Session::new(&keys, false).decoder;
+        let first = session.encoder.encrypt(&[0u8; 100], &[], false);
+        assert_eq!(decrypt(&mut decoder, &first), (false, vec![0u8; 100]));
+        let packet = hex::decode("7530d2a18720162ac09c25329a60d75adf36eda3c3").unwrap();
+        assert_eq!(decrypt(&mut decoder, &packet), (false, vec![0x8e]));
+
+        // The responder vector, received by the initiator after the keys were replaced 4 times
+        let (keys, mut session) = vector_session(
+            "1f9c581b35231838f0f17cf0c979835baccb7f3abbbb96ffcc318ab71e6e126f",
+            "a1855e10e94e00baa23041d916e259f7044e491da6171269694763f018c7e63693d29575dcb464ac816baa1be353ba12e3876cba7628bd0bd8e755e721eb0140",
+            "fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f0000000000000000000000000000000000000000000000000000000000000000",
+            false,
+        );
+        let mut decoder = Session::new(&keys, true).decoder;
+        for _ in 0..999 {
+            let packet = session.encoder.encrypt(&[], &[], false);
+            assert_eq!(decrypt(&mut decoder, &packet), (false, vec![]));
+        }
+        let packet = hex::decode(
+            "1da1bcf589f9b61872f45b7fa5371dd3f8bdf5d515b0c5f9fe9f0044afb8dc0aa1cd39a8c4",
+        )
+        .unwrap();
+        assert_eq!(
+            decrypt(&mut decoder, &packet),
+            (
+                false,
+                hex::decode("3eb1d4e98035cfd8eeb29bac969ed3824a").unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn test_vector_rekey_decoy() {
+        let (keys, mut session) = vector_session(
+            "6c77432d1fda31e9f942f8af44607e10f3ad38a65f8a4bddae823e5eff90dc38",
+            "d2685070c1e6376e633e825296634fd461fa9e5bdf2109bcebd735e5a91f3e587c5cb782abb797fbf6bb5074fd1542a474f2a45b673763ec2db7fb99b737bbb9",
+            "56bd0c06f10352c3a1a9f4b4c92f6fa2b26df124b57878353c1fc691c51abea77c8817daeeb9fa546b77c8daf79d89b22b0e1b87574ece42371f00237aa9d83a",
+            false,
+        );
+        assert_eq!(
+            hex::encode(keys.session_id),
+            "7ec02fea8c1484e3d0875f978c5f36d63545e2e4acf56311394422f4b66af612"
+        );
+        for _ in 0..223 {
+            session.encoder.encrypt(&[], &[], true);
+        }
+        let contents = hex::decode("7e0e78eb6990b059e6cf0ded66ea93ef82e72aa2f18ac24f2fc6ebab561ae557420729da103f64cecfa20527e15f9fb669a49bbbf274ef0389b3e43c8c44e5f60bf2ac38e2b55e7ec4273dba15ba41d21f8f5b3ee1688b3c29951218caf847a97fb50d75a86515d445699497d968164bf740012679b8962de573be941c62b7ef").unwrap();
+        let packet = session.encoder.encrypt(&contents, &[], true);
+        assert!(hex::encode(packet).ends_with("729847a3e9eba7a5bff454b5de3b393431ee360736b6c030d7a5bd01d1203d2e98f528543fd2bf886ccaa1ada5e215a730a36b3f4abfc4e252c89eb01d9512f94916dae8a76bf16e4da28986ffe159090fe5267ee3394300b7ccf4dfad389a26321b3a3423e4594a82ccfbad16d6561ecb8772b0cb040280ff999a29e3d9d4fd"));
+    }
+}
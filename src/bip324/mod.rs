@@ -0,0 +1,7 @@
+//! BIP324 v2 encrypted transport.
+//!
+//! The key exchange produces a `V2Reader`/`V2Writer` pair that translates between v1 frames
+//! and encrypted v2 packets, so everything built on v1 framing runs over v2 unchanged.
+
+pub mod cipher;
+pub mod transport;
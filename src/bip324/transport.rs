@@ -0,0 +1,436 @@
+use std::io::{self, Read, Write};
+
+use rand::{Rng, RngCore};
+use secp256k1::{ellswift::ElligatorSwift, Secp256k1, SecretKey};
+
+use thiserror::Error;
+
+use crate::{message_reader::MessageReader, messages::message::double_sha256};
+
+use super::cipher::{
+    PacketDecoder, PacketEncoder, Session, SessionKeys, GARBAGE_TERMINATOR_LEN, HEADER_LEN,
+    LENGTH_FIELD_LEN, TAG_LEN,
+};
+
+/// Maximum amount of garbage either side may send after its public key.
+const MAX_GARBAGE_LEN: usize = 4095;
+/// Size of an ElligatorSwift encoded public key.
+const ELLSWIFT_LEN: usize = 64;
+/// Size of a v1 message header.
+const V1_HEADER_LEN: usize = 24;
+/// Size of a message command.
+const COMMAND_LEN: usize = 12;
+/// The largest packet contents accepted: a long command and the largest v1 payload.
+pub const MAX_CONTENTS_LEN: u32 = 1 + COMMAND_LEN as u32 + MessageReader::MAX_PAYLOAD_SIZE;
+
+/// A packet announcing more contents than `MAX_CONTENTS_LEN`, carried by the `io::Error` the
+/// reader returns.
+#[derive(Error, Debug)]
+#[error("BIP324 packet of {size} bytes, more than the {MAX_CONTENTS_LEN} allowed")]
+pub struct OversizedPacket {
+    /// The size of the contents the packet announced.
+    pub size: u32,
+}
+
+/// Commands with a one byte short message ID, the ID is the index plus one.
+const SHORT_IDS: [&str; 28] = [
+    "addr",
+    "block",
+    "blocktxn",
+    "cmpctblock",
+    "feefilter",
+    "filteradd",
+    "filterclear",
+    "filterload",
+    "getblocks",
+    "getblocktxn",
+    "getdata",
+    "getheaders",
+    "headers",
+    "inv",
+    "mempool",
+    "merkleblock",
+    "notfound",
+    "ping",
+    "pong",
+    "sendcmpct",
+    "tx",
+    "getcfilters",
+    "cfilter",
+    "getcfheaders",
+    "cfheaders",
+    "getcfcheckpt",
+    "cfcheckpt",
+    "addrv2",
+];
+
+/// Performs the BIP324 key exchange as the initiator of the connection.
+///
+/// Returns `Ok(None)` if the peer closed the connection before sending its public key, which
+/// is how a v1-only peer reacts to our key; the caller should reconnect using v1.
+///
+/// # Arguments
+///
+/// * `reader` - The reading half of the connection.
+/// * `writer` - The writing half of the connection.
+/// * `magic` - Magic bytes of the network.
+pub fn initiate<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    magic: [u8; 4],
+) -> io::Result<Option<(V2Reader<R>, V2Writer<W>)>> {
+    match key_exchange(reader, writer, magic, true) {
+        Ok(transport) => Ok(Some(transport)),
+        Err(e) => match e.kind() {
+            io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe => Ok(None),
+            _ => Err(e),
+        },
+    }
+}
+
+/// Performs the BIP324 key exchange as the side that accepted the connection.
+///
+/// # Arguments
+///
+/// * `reader` - The reading half of the connection.
+/// * `writer` - The writing half of the connection.
+/// * `magic` - Magic bytes of the network.
+pub fn respond<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    magic: [u8; 4],
+) -> io::Result<(V2Reader<R>, V2Writer<W>)> {
+    key_exchange(reader, writer, magic, false)
+}
+
+fn key_exchange<R: Read, W: Write>(
+    mut reader: R,
+    mut writer: W,
+    magic: [u8; 4],
+    initiating: bool,
+) -> io::Result<(V2Reader<R>, V2Writer<W>)> {
+    let mut rng = rand::thread_rng();
+    let secp = Secp256k1::new();
+    let secret_key = loop {
+        let mut bytes = [0u8; 32];
+        rng.fill_bytes(&mut bytes);
+        if let Ok(secret_key) = SecretKey::from_slice(&bytes) {
+            break secret_key;
+        }
+    };
+    let ellswift_ours = ElligatorSwift::from_seckey(&secp, secret_key, Some(rng.gen()));
+    let mut garbage = vec![0u8; rng.gen_range(0..=MAX_GARBAGE_LEN)];
+    rng.fill_bytes(&mut garbage);
+    let key_and_garbage = [&ellswift_ours.to_array()[..], &garbage[..]].concat();
+
+    // The initiator speaks first, the responder only answers once it got a key
+    if initiating {
+        writer.write_all(&key_and_garbage)?;
+    }
+    let mut ellswift_theirs = [0u8; ELLSWIFT_LEN];
+    reader.read_exact(&mut ellswift_theirs)?;
+    if !initiating {
+        writer.write_all(&key_and_garbage)?;
+    }
+
+    let keys = SessionKeys::derive(
+        ellswift_ours,
+        ElligatorSwift::from_array(ellswift_theirs),
+        secret_key,
+        initiating,
+        magic,
+    );
+    let Session {
+        mut encoder,
+        mut decoder,
+        send_garbage_terminator,
+        recv_garbage_terminator,
+    } = Session::new(&keys, initiating);
+
+    // Garbage terminator followed by the version packet, which authenticates our garbage
+    let version_packet = encoder.encrypt(&[], &garbage, false);
+    writer.write_all(&[&send_garbage_terminator[..], &version_packet[..]].concat())?;
+
+    let received_garbage = read_garbage(&mut reader, &recv_garbage_terminator)?;
+    // The peer may send decoys before its version packet, only the first packet is
+    // authenticated with the garbage. The contents of the version packet are reserved for
+    // future extensions and ignored.
+    let mut aad = received_garbage;
+    loop {
+        let (ignore, _) = read_packet(&mut reader, &mut decoder, &aad)?;
+        aad.clear();
+        if !ignore {
+            break;
+        }
+    }
+
+    Ok((
+        V2Reader {
+            reader,
+            decoder,
+            magic,
+            pending: Vec::new(),
+        },
+        V2Writer {
+            writer,
+            encoder,
+            buffer: Vec::new(),
+        },
+    ))
+}
+
+/// Reads the peer's garbage up to and excluding the garbage terminator.
+fn read_garbage<R: Read>(
+    reader: &mut R,
+    terminator: &[u8; GARBAGE_TERMINATOR_LEN],
+) -> io::Result<Vec<u8>> {
+    let mut garbage = Vec::new();
+    let mut byte = [0u8; 1];
+    while !garbage.ends_with(terminator) {
+        if garbage.len() == MAX_GARBAGE_LEN + GARBAGE_TERMINATOR_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "BIP324 garbage terminator not found",
+            ));
+        }
+        reader.read_exact(&mut byte)?;
+        garbage.push(byte[0]);
+    }
+    garbage.truncate(garbage.len() - GARBAGE_TERMINATOR_LEN);
+    Ok(garbage)
+}
+
+/// Reads and decrypts one packet. Returns whether it is a decoy, and its contents.
+fn read_packet<R: Read>(
+    reader: &mut R,
+    decoder: &mut PacketDecoder,
+    aad: &[u8],
+) -> io::Result<(bool, Vec<u8>)> {
+    let mut length = [0u8; LENGTH_FIELD_LEN];
+    reader.read_exact(&mut length)?;
+    // The length is not authenticated yet, check it before allocating the packet
+    let len = decoder.decrypt_length(length);
+    let size = (len - HEADER_LEN - TAG_LEN) as u32;
+    if size > MAX_CONTENTS_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            OversizedPacket { size },
+        ));
+    }
+    let mut packet = vec![0u8; len];
+    reader.read_exact(&mut packet)?;
+    decoder.decrypt(packet, aad)
+}
+
+/// Reading half of a v2 connection. Decrypts packets and hands them out as v1 frames, so
+/// the v1 message handling works unchanged on top of it.
+pub struct V2Reader<R: Read> {
+    reader: R,
+    decoder: PacketDecoder,
+    magic: [u8; 4],
+    pending: Vec<u8>,
+}
+
+impl<R: Read> Read for V2Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pending.is_empty() {
+            let (ignore, contents) = read_packet(&mut self.reader, &mut self.decoder, &[])?;
+            if !ignore {
+                self.pending = to_v1_frame(&contents, self.magic)?;
+            }
+        }
+        let len = buf.len().min(self.pending.len());
+        buf[..len].copy_from_slice(&self.pending[..len]);
+        self.pending.drain(..len);
+        Ok(len)
+    }
+}
+
+/// Writing half of a v2 connection. Takes v1 frames and sends them as encrypted packets,
+/// so the v1 message builders work unchanged on top of it.
+pub struct V2Writer<W: Write> {
+    writer: W,
+    encoder: PacketEncoder,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> Write for V2Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        while self.buffer.len() >= V1_HEADER_LEN {
+            let payload_len = u32::from_le_bytes([
+                self.buffer[16],
+                self.buffer[17],
+                self.buffer[18],
+                self.buffer[19],
+            ]) as usize;
+            if self.buffer.len() < V1_HEADER_LEN + payload_len {
+                break;
+            }
+            let frame: Vec<u8> = self.buffer.drain(..V1_HEADER_LEN + payload_len).collect();
+            let contents = to_v2_contents(&frame);
+            self.writer
+                .write_all(&self.encoder.encrypt(&contents, &[], false))?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Converts a v1 frame into v2 packet contents: the message type (a short ID, or a zero byte
+/// followed by the 12 byte command) and the payload. Magic and checksum are dropped.
+fn to_v2_contents(frame: &[u8]) -> Vec<u8> {
+    let command = &frame[4..4 + COMMAND_LEN];
+    let payload = &frame[V1_HEADER_LEN..];
+    let name = command.split(|byte| *byte == 0).next().unwrap_or_default();
+    match SHORT_IDS.iter().position(|id| id.as_bytes() == name) {
+        Some(idx) => [&[idx as u8 + 1][..], payload].concat(),
+        None => [&[0][..], command, payload].concat(),
+    }
+}
+
+/// Converts v2 packet contents back into a v1 frame.
+fn to_v1_frame(contents: &[u8], magic: [u8; 4]) -> io::Result<Vec<u8>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_owned());
+    let (command, payload) = match contents.first() {
+        None => return Err(invalid("BIP324 packet without message type")),
+        Some(0) => {
+            if contents.len() < 1 + COMMAND_LEN {
+                return Err(invalid("BIP324 packet with truncated command"));
+            }
+            let mut command = [0u8; COMMAND_LEN];
+            command.copy_from_slice(&contents[1..1 + COMMAND_LEN]);
+            (command, &contents[1 + COMMAND_LEN..])
+        }
+        Some(id) => {
+            let name = SHORT_IDS
+                .get(*id as usize - 1)
+                .ok_or_else(|| invalid("BIP324 packet with unknown short message ID"))?;
+            let mut command = [0u8; COMMAND_LEN];
+            command[..name.len()].copy_from_slice(name.as_bytes());
+            (command, &contents[1..])
+        }
+    };
+    Ok([
+        &magic[..],
+        &command[..],
+        &(payload.len() as u32).to_le_bytes(),
+        &double_sha256(payload)[..4],
+        payload,
+    ]
+    .concat())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::{TcpListener, TcpStream},
+        thread,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_key_exchange_and_messages() {
+        let magic = [0xFA, 0xBF, 0xB5, 0xDA];
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let responder = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let (mut reader, mut writer) =
+                respond(stream.try_clone().unwrap(), stream, magic).unwrap();
+            let mut frame = [0u8; V1_HEADER_LEN];
+            reader.read_exact(&mut frame).unwrap();
+            // Echo the frame back, split in two writes
+            writer.write_all(&frame[..10]).unwrap();
+            writer.write_all(&frame[10..]).unwrap();
+        });
+
+        let stream = TcpStream::connect(address).unwrap();
+        let (mut reader, mut writer) = initiate(stream.try_clone().unwrap(), stream, magic)
+            .unwrap()
+            .unwrap();
+        let verack = to_v1_frame(&[&[0][..], b"verack\0\0\0\0\0\0"].concat(), magic).unwrap();
+        writer.write_all(&verack).unwrap();
+        let mut echoed = [0u8; V1_HEADER_LEN];
+        reader.read_exact(&mut echoed).unwrap();
+        assert_eq!(echoed.to_vec(), verack);
+        responder.join().unwrap();
+    }
+
+    #[test]
+    fn test_rejects_oversized_packet() {
+        let magic = [0xFA, 0xBF, 0xB5, 0xDA];
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let (_, mut writer) = respond(stream.try_clone().unwrap(), stream, magic).unwrap();
+            // Only the length field of a packet one byte too large
+            let contents = vec![0u8; MAX_CONTENTS_LEN as usize + 1];
+            let packet = writer.encoder.encrypt(&contents, &[], false);
+            writer
+                .writer
+                .write_all(&packet[..LENGTH_FIELD_LEN])
+                .unwrap();
+        });
+
+        let stream = TcpStream::connect(address).unwrap();
+        let (mut reader, _writer) = initiate(stream.try_clone().unwrap(), stream, magic)
+            .unwrap()
+            .unwrap();
+        let error = reader.read(&mut [0u8; V1_HEADER_LEN]).unwrap_err();
+        let packet = error
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<OversizedPacket>())
+            .unwrap();
+        assert_eq!(packet.size, MAX_CONTENTS_LEN + 1);
+        let error = crate::error::Error::io(address.to_string(), error);
+        assert_eq!(error.kind(), "oversized_message");
+    }
+
+    #[test]
+    fn test_v1_peer_detected() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            // A v1 node reads the header, sees a foreign magic and disconnects
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut header = [0u8; V1_HEADER_LEN];
+            stream.read_exact(&mut header).unwrap();
+        });
+
+        let stream = TcpStream::connect(address).unwrap();
+        let transport = initiate(
+            stream.try_clone().unwrap(),
+            stream,
+            [0xF9, 0xBE, 0xB4, 0xD9],
+        );
+        assert!(transport.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_short_id_round_trip() {
+        let magic = [0xF9, 0xBE, 0xB4, 0xD9];
+        let verack = to_v1_frame(&[&[0][..], b"verack\0\0\0\0\0\0"].concat(), magic).unwrap();
+        assert_eq!(
+            verack,
+            vec![
+                0xF9, 0xBE, 0xB4, 0xD9, 0x76, 0x65, 0x72, 0x61, 0x63, 0x6B, 0x00, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x5D, 0xF6, 0xE0, 0xE2,
+            ]
+        );
+        assert_eq!(to_v2_contents(&verack)[0], 0);
+
+        let ping = to_v1_frame(&[18, 1, 2, 3, 4, 5, 6, 7, 8], magic).unwrap();
+        assert_eq!(&ping[4..8], b"ping");
+        assert_eq!(to_v2_contents(&ping), vec![18, 1, 2, 3, 4, 5, 6, 7, 8]);
+        assert!(to_v1_frame(&[29], magic).is_err());
+    }
+}
@@ -0,0 +1,175 @@
+use std::fmt;
+
+use serde::Serialize;
+use tracing::{debug, info};
+
+use crate::{
+    error::Error,
+    handshake::Connection,
+    messages::{
+        block::Block,
+        inventory::{inventory_payload, parse_inventory, Inventory, MSG_BLOCK, MSG_WITNESS_BLOCK},
+        message::hash_to_hex,
+        version::{NODE_NETWORK, NODE_NETWORK_LIMITED, NODE_WITNESS},
+    },
+    pow::check_proof_of_work,
+};
+
+/// Summary of a downloaded block, printed by `handshaker block`.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct BlockSummary {
+    /// Address of the peer.
+    pub peer: String,
+    /// Hash of the block, as displayed by block explorers.
+    pub hash: String,
+    /// The height committed to in the coinbase (BIP34), absent before version 2 blocks.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    /// The block time, in seconds since the Unix epoch.
+    pub time: u32,
+    /// Number of transactions, the coinbase included.
+    pub transactions: usize,
+    /// Size of the serialized block, witnesses included if requested.
+    pub size: usize,
+    /// Weight (BIP141).
+    pub weight: usize,
+    /// Whether the block was requested with the witnesses of its transactions.
+    pub witness: bool,
+}
+
+impl BlockSummary {
+    /// Creates a new instance of `BlockSummary`.
+    ///
+    /// # Arguments
+    ///
+    /// * `peer` - Address of the peer that served the block.
+    /// * `block` - The block.
+    /// * `witness` - Whether the block was requested with its witnesses.
+    pub fn new(peer: impl Into<String>, block: &Block, witness: bool) -> Self {
+        Self {
+            peer: peer.into(),
+            hash: hash_to_hex(&block.header.hash()),
+            height: block.coinbase_height(),
+            time: block.header.time,
+            transactions: block.txdata.len(),
+            size: block.size(),
+            weight: block.weight(),
+            witness,
+        }
+    }
+}
+
+impl fmt::Display for BlockSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: block {}", self.peer, self.hash)?;
+        if let Some(height) = self.height {
+            write!(f, " at height {height}")?;
+        }
+        write!(
+            f,
+            ", {} transactions, {} bytes, weight {}",
+            self.transactions, self.size, self.weight
+        )
+    }
+}
+
+/// Downloads the block `hash` with `getdata` and checks its proof of work and merkle root, and
+/// its witness commitment when requested with witnesses.
+///
+/// A ping follows the `getdata`: the peer answers `getdata` before the messages after it, so a
+/// pong without the block means the peer does not have it. Pruned peers disconnect instead.
+///
+/// # Arguments
+///
+/// * `connection` - A connection whose handshake completed.
+/// * `hash` - The hash of the block, in internal byte order.
+/// * `services` - The services the peer announced in its version message.
+pub fn download_block(
+    connection: &mut Connection,
+    hash: [u8; 32],
+    services: u64,
+) -> Result<Block, Error> {
+    let peer = connection.reader.peer().to_owned();
+    let not_found = |reason: &str| Error::BlockNotFound {
+        peer: peer.clone(),
+        hash: hash_to_hex(&hash),
+        reason: reason.to_owned(),
+    };
+    let violation = |reason: String| Error::ProtocolViolation {
+        peer: peer.clone(),
+        reason,
+    };
+    let witness = services & NODE_WITNESS != 0;
+    let inv_type = if witness {
+        MSG_WITNESS_BLOCK
+    } else {
+        MSG_BLOCK
+    };
+    connection.send_raw(
+        "getdata",
+        &inventory_payload(&[Inventory::new(inv_type, hash)]),
+    )?;
+    let nonce: u64 = rand::random();
+    connection.send_raw("ping", &nonce.to_le_bytes())?;
+    info!(hash = %hash_to_hex(&hash), witness, "block requested");
+
+    loop {
+        match connection.reader.read_message() {
+            Ok(_) => {}
+            Err(Error::PeerDisconnected { .. })
+                if services & NODE_NETWORK == 0 && services & NODE_NETWORK_LIMITED != 0 =>
+            {
+                return Err(not_found(
+                    "disconnected, it is pruned and only serves the last 288 blocks",
+                ));
+            }
+            Err(e) => return Err(e),
+        }
+        let payload = connection.reader.payload();
+        match connection.reader.command_name().as_str() {
+            "block" => {
+                let block = Block::try_from(payload)
+                    .map_err(|e| violation(format!("malformed block message: {e}")))?;
+                if block.header.hash() != hash {
+                    // Announced as a new block, not the one requested
+                    debug!(hash = %hash_to_hex(&block.header.hash()), "unrequested block ignored");
+                    continue;
+                }
+                if !check_proof_of_work(hash, block.header.bits, &connection.network.params()) {
+                    return Err(violation(format!(
+                        "block {} does not meet its proof of work target",
+                        hash_to_hex(&hash)
+                    )));
+                }
+                if !block.check_merkle_root() {
+                    return Err(violation(format!(
+                        "transactions of block {} do not match its merkle root",
+                        hash_to_hex(&hash)
+                    )));
+                }
+                if witness && !block.check_witness_commitment() {
+                    return Err(violation(format!(
+                        "witnesses of block {} do not match its commitment",
+                        hash_to_hex(&hash)
+                    )));
+                }
+                info!(transactions = block.txdata.len(), "block validated");
+                return Ok(block);
+            }
+            "notfound" => {
+                let items = parse_inventory(payload).unwrap_or_default();
+                if items.iter().any(|item| item.hash == hash) {
+                    return Err(not_found("notfound"));
+                }
+            }
+            "ping" => {
+                let nonce = payload.to_vec();
+                connection.send_raw("pong", &nonce)?;
+            }
+            "pong" if payload == nonce.to_le_bytes() => {
+                return Err(not_found("the peer does not have it"));
+            }
+            command => debug!(command, "ignored"),
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use std::hash::Hasher;
+
+use siphasher::sip::SipHasher24;
+
+use crate::{
+    error::Error,
+    messages::message::{compact_size, double_sha256, PayloadCursor},
+};
+
+/// The Golomb-Rice parameter of basic filters (BIP158): the remainders are 19 bits.
+pub const FILTER_P: u8 = 19;
+
+/// The inverse false positive rate of basic filters (BIP158).
+pub const FILTER_M: u64 = 784_931;
+
+/// A block filter (BIP158): the number of elements, then a Golomb-coded set of their hashes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockFilter {
+    /// The serialized filter, as in a `cfilter` message.
+    pub content: Vec<u8>,
+}
+
+impl BlockFilter {
+    /// Creates a new instance of `BlockFilter` from a serialized filter.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The serialized filter.
+    pub fn new(content: Vec<u8>) -> Self {
+        Self { content }
+    }
+
+    /// Builds the filter of the block `block_hash` holding `elements`. For a basic filter
+    /// those are the scriptPubKeys of the outputs, but `OP_RETURN` ones, and of the outputs
+    /// spent by the inputs.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_hash` - The hash of the block, in internal byte order, which keys the hashes.
+    /// * `elements` - The elements, duplicates and empty ones are skipped.
+    pub fn build<T: AsRef<[u8]>>(block_hash: &[u8; 32], elements: &[T]) -> Self {
+        let mut elements: Vec<&[u8]> = elements
+            .iter()
+            .map(AsRef::as_ref)
+            .filter(|element| !element.is_empty())
+            .collect();
+        elements.sort_unstable();
+        elements.dedup();
+        let n = elements.len() as u64;
+        let mut hashes: Vec<u64> = elements
+            .iter()
+            .map(|element| hash_to_range(block_hash, element, n * FILTER_M))
+            .collect();
+        hashes.sort_unstable();
+
+        let mut writer = BitWriter::default();
+        let mut last = 0;
+        for hash in hashes {
+            let delta = hash - last;
+            // The quotient in unary, then the remainder
+            for _ in 0..delta >> FILTER_P {
+                writer.write_bit(true);
+            }
+            writer.write_bit(false);
+            writer.write_bits(delta, FILTER_P);
+            last = hash;
+        }
+        let mut content = compact_size(n);
+        content.extend(writer.bytes);
+        Self { content }
+    }
+
+    /// Returns the hash of the filter, committed to by its filter header.
+    pub fn hash(&self) -> [u8; 32] {
+        double_sha256(&self.content)
+    }
+
+    /// Returns the filter header of the filter, which commits to the previous ones.
+    ///
+    /// # Arguments
+    ///
+    /// * `previous_header` - The filter header of the previous block, all zeros for genesis.
+    pub fn header(&self, previous_header: &[u8; 32]) -> [u8; 32] {
+        filter_header(&self.hash(), previous_header)
+    }
+
+    /// Returns whether any of `scripts` is in the filter. False positives happen once in
+    /// 784,931 queried elements.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_hash` - The hash of the block, in internal byte order, which keys the hashes.
+    /// * `scripts` - The elements looked for, e.g. the scriptPubKeys of a wallet.
+    pub fn match_any<T: AsRef<[u8]>>(
+        &self,
+        block_hash: &[u8; 32],
+        scripts: &[T],
+    ) -> Result<bool, Error> {
+        let mut cursor = PayloadCursor(&self.content);
+        let n = cursor.compact_size()? as u64;
+        // Every element takes at least the remainder and the end of the quotient
+        if n > cursor.0.len() as u64 * 8 / (u64::from(FILTER_P) + 1) {
+            return Err(anyhow::anyhow!("filter of {n} elements truncated").into());
+        }
+        let f = n * FILTER_M;
+        let mut queries: Vec<u64> = scripts
+            .iter()
+            .map(AsRef::as_ref)
+            .filter(|script| !script.is_empty())
+            .map(|script| hash_to_range(block_hash, script, f))
+            .collect();
+        queries.sort_unstable();
+        let mut queries = queries.into_iter().peekable();
+
+        // Both lists are sorted, walk them together
+        let mut reader = BitReader::new(cursor.0);
+        let mut value = 0;
+        for _ in 0..n {
+            let mut quotient = 0;
+            while reader.read_bit()? {
+                quotient += 1;
+            }
+            value += (quotient << FILTER_P) + reader.read_bits(FILTER_P)?;
+            while queries.next_if(|query| *query < value).is_some() {}
+            match queries.peek() {
+                Some(query) if *query == value => return Ok(true),
+                Some(_) => {}
+                None => return Ok(false),
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// Returns the filter header of a block from the hash of its filter and the previous header.
+///
+/// # Arguments
+///
+/// * `filter_hash` - The hash of the filter of the block.
+/// * `previous_header` - The filter header of the previous block, all zeros for genesis.
+pub fn filter_header(filter_hash: &[u8; 32], previous_header: &[u8; 32]) -> [u8; 32] {
+    double_sha256(&[&filter_hash[..], previous_header].concat())
+}
+
+/// Maps `element` uniformly to `[0, f)` with SipHash-2-4, keyed with the first 16 bytes of
+/// the block hash.
+fn hash_to_range(block_hash: &[u8; 32], element: &[u8], f: u64) -> u64 {
+    let k0 = u64::from_le_bytes(block_hash[0..8].try_into().expect("8 bytes"));
+    let k1 = u64::from_le_bytes(block_hash[8..16].try_into().expect("8 bytes"));
+    let mut hasher = SipHasher24::new_with_keys(k0, k1);
+    hasher.write(element);
+    ((u128::from(hasher.finish()) * u128::from(f)) >> 64) as u64
+}
+
+/// Reads bits most significant first.
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    fn read_bit(&mut self) -> Result<bool, Error> {
+        let byte = self
+            .bytes
+            .get(self.position / 8)
+            .ok_or_else(|| anyhow::anyhow!("filter truncated"))?;
+        let bit = byte >> (7 - self.position % 8) & 1 == 1;
+        self.position += 1;
+        Ok(bit)
+    }
+
+    fn read_bits(&mut self, count: u8) -> Result<u64, Error> {
+        (0..count).try_fold(0, |value, _| Ok(value << 1 | u64::from(self.read_bit()?)))
+    }
+}
+
+/// Writes bits most significant first, the last byte padded with zeros.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    position: usize,
+}
+
+impl BitWriter {
+    fn write_bit(&mut self, bit: bool) {
+        if self.position.is_multiple_of(8) {
+            self.bytes.push(0);
+        }
+        if bit {
+            let last = self.bytes.len() - 1;
+            self.bytes[last] |= 0x80 >> (self.position % 8);
+        }
+        self.position += 1;
+    }
+
+    fn write_bits(&mut self, value: u64, count: u8) {
+        for i in (0..count).rev() {
+            self.write_bit(value >> i & 1 == 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::messages::message::hash_from_hex;
+
+    /// The scriptPubKey of the coinbase output of the genesis block.
+    const GENESIS_OUTPUT_SCRIPT: &str = "4104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac";
+
+    #[test]
+    fn test_testnet_genesis_filter() {
+        // The first test vector of BIP158
+        let block_hash =
+            hash_from_hex("000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943")
+                .unwrap();
+        let script = hex::decode(GENESIS_OUTPUT_SCRIPT).unwrap();
+        let filter = BlockFilter::build(&block_hash, &[&script]);
+        assert_eq!(hex::encode(&filter.content), "019dfca8");
+        assert_eq!(
+            filter.header(&[0; 32]),
+            hash_from_hex("21584579b7eb08997773e5aeff3a7f932700042d0ed2a6129012b7d7ae81b750")
+                .unwrap()
+        );
+        assert!(filter.match_any(&block_hash, &[&script]).unwrap());
+        assert!(!filter.match_any(&block_hash, &[[0x51]]).unwrap());
+    }
+
+    #[test]
+    fn test_match_any() {
+        let block_hash = [7; 32];
+        let elements: Vec<Vec<u8>> = (0..100u32).map(|i| i.to_le_bytes().to_vec()).collect();
+        let filter = BlockFilter::build(&block_hash, &elements);
+        for element in &elements {
+            assert!(filter.match_any(&block_hash, &[element]).unwrap());
+        }
+        let absent: Vec<Vec<u8>> = (100..200u32).map(|i| i.to_le_bytes().to_vec()).collect();
+        assert!(!filter.match_any(&block_hash, &absent).unwrap());
+        assert!(filter
+            .match_any(&block_hash, &[absent[0].clone(), elements[50].clone()])
+            .unwrap());
+        // Keyed by the block hash
+        assert!(!filter.match_any(&[8; 32], &elements[..1]).unwrap());
+
+        let empty = BlockFilter::build(&block_hash, &[[0u8; 0]]);
+        assert_eq!(empty.content, [0]);
+        assert!(!empty.match_any(&block_hash, &elements).unwrap());
+        let truncated = BlockFilter::new(filter.content[..10].to_vec());
+        assert!(truncated.match_any(&block_hash, &absent).is_err());
+    }
+}
@@ -0,0 +1,207 @@
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs},
+    time::Duration,
+};
+
+use tracing::{info, warn};
+
+use crate::{
+    error::Error,
+    handshake::{handshake, ConnectOptions, Connection},
+    messages::message::MessageMagicNumber,
+    report::HandshakeReport,
+};
+
+/// Timeout used for every candidate in bootstrap mode, so a dead peer doesn't stall the search.
+const CANDIDATE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Resolves DNS seed host names into peer addresses.
+pub trait SeedResolver {
+    /// Resolves `host` and returns the addresses found, each with `port` attached.
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - The seed host name.
+    /// * `port` - The port to attach to every resolved address.
+    fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>>;
+}
+
+/// A `SeedResolver` backed by the system DNS resolver.
+pub struct DnsSeedResolver;
+
+impl SeedResolver for DnsSeedResolver {
+    fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        Ok((host, port).to_socket_addrs()?.collect())
+    }
+}
+
+/// Discovers peers through the DNS seeds of a network.
+pub struct Bootstrap<R: SeedResolver> {
+    resolver: R,
+    network: MessageMagicNumber,
+    options: ConnectOptions,
+}
+
+impl<R: SeedResolver> Bootstrap<R> {
+    /// Creates a new instance of `Bootstrap`.
+    ///
+    /// # Arguments
+    ///
+    /// * `resolver` - The resolver used to look up the seeds.
+    /// * `network` - The network whose seeds are queried.
+    pub fn new(resolver: R, network: MessageMagicNumber) -> Self {
+        Self {
+            resolver,
+            network,
+            options: ConnectOptions::default(),
+        }
+    }
+
+    /// Sets how the handshakes with the candidates connect. Without a timeout, every
+    /// candidate gets 5 seconds.
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - Proxy, timeout and transport settings.
+    pub fn with_options(mut self, options: ConnectOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Resolves every seed of the network and returns the unique candidate addresses, in the
+    /// order the seeds returned them. Seeds that fail to resolve are skipped. If none of the
+    /// seeds answered, the fixed seeds of the network are returned instead.
+    pub fn candidates(&self) -> Vec<SocketAddr> {
+        let params = self.network.params();
+        let port = params.default_port;
+        let mut candidates: Vec<SocketAddr> = Vec::new();
+        for seed in params.dns_seeds {
+            let addresses = match self.resolver.resolve(seed, port) {
+                Ok(addresses) => addresses,
+                Err(e) => {
+                    warn!(seed, error = %e, "failed to resolve seed");
+                    continue;
+                }
+            };
+            for address in addresses {
+                if !candidates.contains(&address) {
+                    candidates.push(address);
+                }
+            }
+        }
+        if candidates.is_empty() {
+            candidates = params
+                .fixed_seeds
+                .iter()
+                .filter_map(|seed| seed.parse().ok())
+                .collect();
+        }
+        candidates
+    }
+
+    /// Picks the first candidate without connecting to it.
+    pub fn pick(&self) -> Result<SocketAddr, Error> {
+        self.candidates()
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("DNS seeds returned no peers").into())
+    }
+
+    /// Tries to handshake with the candidates one by one and returns the report of, and the
+    /// connection to, the first peer that completed the handshake.
+    pub fn connect(&self) -> Result<(HandshakeReport, Connection), Error> {
+        let candidates = self.candidates();
+        for candidate in &candidates {
+            let (report, result) = self.try_candidate(*candidate);
+            match result {
+                Ok(connection) => return Ok((report, connection)),
+                Err(e) => warn!(%candidate, error = %e, "handshake failed"),
+            }
+        }
+        Err(anyhow::anyhow!("no reachable peer among {} candidates", candidates.len()).into())
+    }
+
+    /// Handshakes with every candidate and returns the reports, failed handshakes included.
+    pub fn crawl(&self) -> Vec<HandshakeReport> {
+        self.candidates()
+            .into_iter()
+            .map(|candidate| self.try_candidate(candidate).0)
+            .collect()
+    }
+
+    fn try_candidate(&self, candidate: SocketAddr) -> (HandshakeReport, Result<Connection, Error>) {
+        info!(%candidate, "trying candidate");
+        let options = ConnectOptions {
+            timeout: self.options.timeout.or(Some(CANDIDATE_TIMEOUT)),
+            ..self.options.clone()
+        };
+        let host = candidate.ip().to_string();
+        let mut report = HandshakeReport::new(candidate.to_string(), self.network.clone());
+        let result = handshake(
+            &self.network,
+            &host,
+            candidate.port(),
+            &options,
+            &mut report,
+        );
+        report.finish(&result);
+        (report, result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::*;
+    use crate::chain_params::MAIN;
+
+    struct StubResolver;
+
+    impl SeedResolver for StubResolver {
+        fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+            let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+            match host {
+                "seed.signet.bitcoin.sprovoost.nl" => Ok(vec![
+                    SocketAddr::new(localhost, port),
+                    SocketAddr::new(localhost, port + 1),
+                ]),
+                "seed.signet.achownodes.xyz" => Ok(vec![SocketAddr::new(localhost, port + 1)]),
+                _ => Err(io::Error::new(io::ErrorKind::NotFound, "unknown seed")),
+            }
+        }
+    }
+
+    #[test]
+    fn test_candidates_deduplicated() {
+        let bootstrap = Bootstrap::new(StubResolver, MessageMagicNumber::Signet);
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        assert_eq!(
+            bootstrap.candidates(),
+            vec![
+                SocketAddr::new(localhost, 38333),
+                SocketAddr::new(localhost, 38334)
+            ]
+        );
+        assert_eq!(bootstrap.pick().unwrap(), SocketAddr::new(localhost, 38333));
+    }
+
+    #[test]
+    fn test_falls_back_to_fixed_seeds() {
+        // None of the mainnet seeds resolve
+        let bootstrap = Bootstrap::new(StubResolver, MessageMagicNumber::Main);
+        let candidates = bootstrap.candidates();
+        assert_eq!(candidates.len(), MAIN.fixed_seeds.len());
+        assert_eq!(candidates[0], MAIN.fixed_seeds[0].parse().unwrap());
+    }
+
+    #[test]
+    fn test_no_candidates() {
+        let bootstrap = Bootstrap::new(StubResolver, MessageMagicNumber::Regtest);
+        assert!(bootstrap.candidates().is_empty());
+        assert!(bootstrap.pick().is_err());
+        assert!(bootstrap.connect().is_err());
+        assert!(bootstrap.crawl().is_empty());
+    }
+}
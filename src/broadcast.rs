@@ -0,0 +1,180 @@
+use std::{
+    fmt, thread,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use tracing::{debug, info};
+
+use crate::{
+    error::Error,
+    handshake::Connection,
+    messages::{
+        inventory::{
+            inventory_payload, parse_inventory, Inventory, MSG_TX, MSG_WITNESS_FLAG, MSG_WTX,
+        },
+        message::hash_to_hex,
+        reject::Reject,
+        transaction::Transaction,
+    },
+};
+
+/// How long `handshaker broadcast` waits for the peer to ask for the transaction.
+pub const DEFAULT_BROADCAST_WAIT: Duration = Duration::from_secs(30);
+
+/// How often the peer is pinged while waiting, so a quiet peer does not block the wait.
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Outcome of a transaction broadcast, printed by `handshaker broadcast`.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct BroadcastReport {
+    /// Address of the peer.
+    pub peer: String,
+    /// The txid, as displayed by block explorers.
+    pub txid: String,
+    /// The wtxid, as displayed by block explorers.
+    pub wtxid: String,
+    /// Whether the peer asked for the transaction with `getdata`, and got it.
+    pub requested: bool,
+    /// The `reject` the peer answered the transaction with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reject: Option<Reject>,
+    /// Whether the peer disconnected before the broadcast was over.
+    pub disconnected: bool,
+}
+
+impl BroadcastReport {
+    /// Turns a rejection or a disconnection into an error.
+    pub fn into_result(self) -> Result<(), Error> {
+        if let Some(reject) = self.reject {
+            return Err(Error::TransactionRejected {
+                peer: self.peer,
+                reason: format!("{} (code {:#04x})", reject.reason, reject.code),
+            });
+        }
+        if self.disconnected {
+            return Err(Error::PeerDisconnected { peer: self.peer });
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for BroadcastReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let requested = if self.requested {
+            "requested and sent"
+        } else {
+            "not requested"
+        };
+        write!(f, "{}: {} {}", self.peer, self.txid, requested)?;
+        if let Some(reject) = &self.reject {
+            write!(f, ", rejected: {} ({:#04x})", reject.reason, reject.code)?;
+        }
+        if self.disconnected {
+            write!(f, ", then disconnected")?;
+        }
+        Ok(())
+    }
+}
+
+/// Announces `transaction` to the peer with `inv` and serves it when the peer asks for it with
+/// `getdata`.
+///
+/// Once served, a ping is sent: the peer processes messages in order, so a `reject` of the
+/// transaction comes before the pong, which ends the broadcast. Gives up waiting for the
+/// `getdata` after `wait`.
+///
+/// The connection must have been made with `relay`, Bitcoin Core disconnects peers that
+/// announce transactions after asking not to get any.
+///
+/// # Arguments
+///
+/// * `connection` - A connection whose handshake completed.
+/// * `transaction` - The transaction to broadcast.
+/// * `wait` - How long to wait for the peer to ask for the transaction.
+pub fn broadcast(
+    connection: &mut Connection,
+    transaction: &Transaction,
+    wait: Duration,
+) -> Result<BroadcastReport, Error> {
+    let (txid, wtxid) = (transaction.txid(), transaction.wtxid());
+    let mut report = BroadcastReport {
+        peer: connection.reader.peer().to_owned(),
+        txid: hash_to_hex(&txid),
+        wtxid: hash_to_hex(&wtxid),
+        requested: false,
+        reject: None,
+        disconnected: false,
+    };
+    // Peers that sent wtxidrelay expect transactions announced by wtxid
+    let announcement = if connection.features.wtxid_relay {
+        Inventory::new(MSG_WTX, wtxid)
+    } else {
+        Inventory::new(MSG_TX, txid)
+    };
+    connection.send_raw("inv", &inventory_payload(&[announcement]))?;
+    info!(txid = %report.txid, "transaction announced");
+
+    let deadline = Instant::now() + wait;
+    let mut nonce: u64 = rand::random();
+    connection.send_raw("ping", &nonce.to_le_bytes())?;
+    loop {
+        match connection.reader.read_message() {
+            Ok(_) => {}
+            Err(Error::PeerDisconnected { .. }) => {
+                info!("peer disconnected");
+                report.disconnected = true;
+                return Ok(report);
+            }
+            Err(e) => return Err(e),
+        }
+        let payload = connection.reader.payload();
+        match connection.reader.command_name().as_str() {
+            "getdata" if !report.requested => {
+                let items = parse_inventory(payload).unwrap_or_default();
+                let Some(item) = items.iter().find(|item| {
+                    matches!(item.inv_type & !MSG_WITNESS_FLAG, MSG_TX | MSG_WTX)
+                        && (item.hash == txid || item.hash == wtxid)
+                }) else {
+                    continue;
+                };
+                let serialized = if item.inv_type == MSG_TX {
+                    transaction.serialize_without_witness()
+                } else {
+                    transaction.serialize()
+                };
+                connection.send_raw("tx", &serialized)?;
+                report.requested = true;
+                info!("transaction sent");
+                // A new nonce, so only the pong of this ping ends the broadcast
+                nonce = rand::random();
+                connection.send_raw("ping", &nonce.to_le_bytes())?;
+            }
+            "reject" => {
+                let reject = Reject::try_from(payload).map_err(|e| Error::ProtocolViolation {
+                    peer: report.peer.clone(),
+                    reason: format!("malformed reject message: {e}"),
+                })?;
+                if reject.hash.is_none_or(|hash| hash == txid || hash == wtxid) {
+                    info!(reason = %reject.reason, "transaction rejected");
+                    report.reject = Some(reject);
+                    return Ok(report);
+                }
+            }
+            "ping" => {
+                let nonce = payload.to_vec();
+                connection.send_raw("pong", &nonce)?;
+            }
+            "pong" if payload == nonce.to_le_bytes() => {
+                if report.requested || Instant::now() >= deadline {
+                    return Ok(report);
+                }
+                // The peer may delay its getdata by a few seconds, ping again in a while
+                thread::sleep(PING_INTERVAL);
+                nonce = rand::random();
+                connection.send_raw("ping", &nonce.to_le_bytes())?;
+            }
+            command => debug!(command, "ignored"),
+        }
+    }
+}
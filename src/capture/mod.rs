@@ -0,0 +1,170 @@
+//! Recording of the frames exchanged with a peer.
+
+use std::{
+    io::{self, Read, Write},
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+pub mod pcapng;
+pub mod recording;
+
+/// Size of a v1 message header.
+const HEADER_LEN: usize = 24;
+
+/// Direction of a recorded frame.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// Sent by us to the peer.
+    Sent,
+    /// Received by us from the peer.
+    Received,
+}
+
+/// Destination of recorded frames.
+pub trait FrameSink: Send {
+    /// Records one complete v1 frame (header and payload).
+    ///
+    /// # Arguments
+    ///
+    /// * `direction` - Whether the frame was sent or received.
+    /// * `timestamp` - When the frame was sent or received.
+    /// * `frame` - The raw frame.
+    fn record(
+        &mut self,
+        direction: Direction,
+        timestamp: DateTime<Utc>,
+        frame: &[u8],
+    ) -> io::Result<()>;
+}
+
+/// Records every frame into each of the sinks.
+impl FrameSink for Vec<Box<dyn FrameSink>> {
+    fn record(
+        &mut self,
+        direction: Direction,
+        timestamp: DateTime<Utc>,
+        frame: &[u8],
+    ) -> io::Result<()> {
+        for sink in self.iter_mut() {
+            sink.record(direction, timestamp, frame)?;
+        }
+        Ok(())
+    }
+}
+
+/// A sink shared by the reading and the writing half of a connection.
+pub type SharedSink = Arc<Mutex<dyn FrameSink>>;
+
+/// Wraps one half of a connection and records every complete frame passing through it.
+pub struct Tap<T> {
+    inner: T,
+    direction: Direction,
+    sink: SharedSink,
+    buffer: Vec<u8>,
+}
+
+impl<T> Tap<T> {
+    /// Creates a new instance of `Tap`.
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - The reader or writer to wrap.
+    /// * `direction` - The direction of the frames passing through `inner`.
+    /// * `sink` - Where the frames are recorded.
+    pub fn new(inner: T, direction: Direction, sink: SharedSink) -> Self {
+        Self {
+            inner,
+            direction,
+            sink,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Collects `bytes` and records every frame they complete.
+    fn observe(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.buffer.extend_from_slice(bytes);
+        while self.buffer.len() >= HEADER_LEN {
+            let payload_len = u32::from_le_bytes([
+                self.buffer[16],
+                self.buffer[17],
+                self.buffer[18],
+                self.buffer[19],
+            ]) as usize;
+            if self.buffer.len() < HEADER_LEN + payload_len {
+                break;
+            }
+            let frame: Vec<u8> = self.buffer.drain(..HEADER_LEN + payload_len).collect();
+            self.sink
+                .lock()
+                .map_err(|_| io::Error::other("capture sink poisoned"))?
+                .record(self.direction, Utc::now(), &frame)?;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Tap<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.observe(&buf[..read])?;
+        Ok(read)
+    }
+}
+
+impl<W: Write> Write for Tap<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.observe(&buf[..written])?;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct VecSink(Vec<(Direction, Vec<u8>)>);
+
+    impl FrameSink for VecSink {
+        fn record(
+            &mut self,
+            direction: Direction,
+            _: DateTime<Utc>,
+            frame: &[u8],
+        ) -> io::Result<()> {
+            self.0.push((direction, frame.to_vec()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_tap_splits_frames() {
+        let verack: Vec<u8> = vec![
+            0xF9, 0xBE, 0xB4, 0xD9, 0x76, 0x65, 0x72, 0x61, 0x63, 0x6B, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x5D, 0xF6, 0xE0, 0xE2,
+        ];
+        let sink = Arc::new(Mutex::new(VecSink::default()));
+        let mut tap = Tap::new(
+            Cursor::new([verack.clone(), verack.clone()].concat()),
+            Direction::Received,
+            sink.clone(),
+        );
+        let mut buf = [0u8; 10];
+        while tap.read(&mut buf).unwrap() > 0 {}
+
+        let frames = &sink.lock().unwrap().0;
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1], (Direction::Received, verack));
+    }
+}
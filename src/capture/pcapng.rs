@@ -0,0 +1,293 @@
+//! Writer of pcapng files that Wireshark's Bitcoin dissector decodes directly.
+//!
+//! Frames are wrapped in synthesised IP and TCP headers, with sequence numbers tracking each
+//! direction of the connection, so Wireshark reassembles them like a live capture.
+
+use std::{
+    io::{self, Write},
+    net::{IpAddr, SocketAddr},
+};
+
+use chrono::{DateTime, Utc};
+
+use super::{Direction, FrameSink};
+
+const BLOCK_SECTION_HEADER: u32 = 0x0A0D_0D0A;
+const BLOCK_INTERFACE_DESCRIPTION: u32 = 0x0000_0001;
+const BLOCK_ENHANCED_PACKET: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+/// Raw IP packets, the version is read from the first nibble.
+const LINKTYPE_RAW: u16 = 101;
+
+const TCP_SYN: u8 = 0x02;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+const TCP_WINDOW: u16 = 0xFFFF;
+const IP_PROTOCOL_TCP: u8 = 6;
+const IP_TTL: u8 = 64;
+/// Largest TCP payload per packet, keeping the IPv4 total length within 16 bits.
+const MAX_SEGMENT: usize = 65_000;
+
+/// Writes a connection as a pcapng file.
+pub struct PcapngWriter<W: Write> {
+    out: W,
+    local: SocketAddr,
+    peer: SocketAddr,
+    /// Next sequence number we send.
+    local_seq: u32,
+    /// Next sequence number the peer sends.
+    peer_seq: u32,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    /// Starts a capture file with a synthesised TCP handshake between `local` and `peer`.
+    ///
+    /// # Arguments
+    ///
+    /// * `out` - Where the capture file is written.
+    /// * `local` - Our end of the connection.
+    /// * `peer` - The peer's end of the connection.
+    pub fn new(out: W, local: SocketAddr, peer: SocketAddr) -> io::Result<Self> {
+        // Both ends of a packet must share an IP version
+        let (local, peer) = match (local.ip(), peer.ip()) {
+            (IpAddr::V4(local_ip), IpAddr::V6(_)) => (
+                SocketAddr::new(IpAddr::V6(local_ip.to_ipv6_mapped()), local.port()),
+                peer,
+            ),
+            (IpAddr::V6(_), IpAddr::V4(peer_ip)) => (
+                local,
+                SocketAddr::new(IpAddr::V6(peer_ip.to_ipv6_mapped()), peer.port()),
+            ),
+            _ => (local, peer),
+        };
+        let mut writer = Self {
+            out,
+            local,
+            peer,
+            local_seq: 0,
+            peer_seq: 0,
+        };
+        writer.write_section_header()?;
+        writer.write_interface_description()?;
+
+        let now = Utc::now();
+        writer.write_segment(Direction::Sent, now, TCP_SYN, &[])?;
+        writer.local_seq += 1;
+        writer.write_segment(Direction::Received, now, TCP_SYN | TCP_ACK, &[])?;
+        writer.peer_seq += 1;
+        writer.write_segment(Direction::Sent, now, TCP_ACK, &[])?;
+        writer.out.flush()?;
+        Ok(writer)
+    }
+
+    fn write_section_header(&mut self) -> io::Result<()> {
+        let mut body = Vec::with_capacity(16);
+        body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        body.extend_from_slice(&1u16.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        // Section length not specified
+        body.extend_from_slice(&(-1i64).to_le_bytes());
+        self.write_block(BLOCK_SECTION_HEADER, &body)
+    }
+
+    fn write_interface_description(&mut self) -> io::Result<()> {
+        let mut body = Vec::with_capacity(8);
+        body.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        // No snapshot length limit; timestamps use the default microsecond resolution
+        body.extend_from_slice(&0u32.to_le_bytes());
+        self.write_block(BLOCK_INTERFACE_DESCRIPTION, &body)
+    }
+
+    fn write_packet(&mut self, timestamp: DateTime<Utc>, packet: &[u8]) -> io::Result<()> {
+        let micros = timestamp.timestamp_micros() as u64;
+        let mut body = Vec::with_capacity(20 + packet.len() + 3);
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(micros as u32).to_le_bytes());
+        body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        body.extend_from_slice(packet);
+        body.resize(body.len().next_multiple_of(4), 0);
+        self.write_block(BLOCK_ENHANCED_PACKET, &body)
+    }
+
+    fn write_block(&mut self, block_type: u32, body: &[u8]) -> io::Result<()> {
+        let total_len = (12 + body.len()) as u32;
+        self.out.write_all(&block_type.to_le_bytes())?;
+        self.out.write_all(&total_len.to_le_bytes())?;
+        self.out.write_all(body)?;
+        self.out.write_all(&total_len.to_le_bytes())
+    }
+
+    /// Writes one TCP segment, advancing the sequence number of the sender by its payload.
+    fn write_segment(
+        &mut self,
+        direction: Direction,
+        timestamp: DateTime<Utc>,
+        flags: u8,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let (source, destination, seq, ack) = match direction {
+            Direction::Sent => (self.local, self.peer, self.local_seq, self.peer_seq),
+            Direction::Received => (self.peer, self.local, self.peer_seq, self.local_seq),
+        };
+        // The initial SYN acknowledges nothing
+        let ack = if flags & TCP_ACK == 0 { 0 } else { ack };
+
+        let mut tcp = Vec::with_capacity(20 + payload.len());
+        tcp.extend_from_slice(&source.port().to_be_bytes());
+        tcp.extend_from_slice(&destination.port().to_be_bytes());
+        tcp.extend_from_slice(&seq.to_be_bytes());
+        tcp.extend_from_slice(&ack.to_be_bytes());
+        tcp.push(5 << 4);
+        tcp.push(flags);
+        tcp.extend_from_slice(&TCP_WINDOW.to_be_bytes());
+        tcp.extend_from_slice(&[0, 0, 0, 0]);
+        tcp.extend_from_slice(payload);
+
+        let packet = match (source.ip(), destination.ip()) {
+            (IpAddr::V4(source), IpAddr::V4(destination)) => {
+                let mut pseudo = Vec::with_capacity(12 + tcp.len());
+                pseudo.extend_from_slice(&source.octets());
+                pseudo.extend_from_slice(&destination.octets());
+                pseudo.extend_from_slice(&[0, IP_PROTOCOL_TCP]);
+                pseudo.extend_from_slice(&(tcp.len() as u16).to_be_bytes());
+                pseudo.extend_from_slice(&tcp);
+                tcp[16..18].copy_from_slice(&internet_checksum(&pseudo).to_be_bytes());
+
+                let mut ip = Vec::with_capacity(20 + tcp.len());
+                ip.push(0x45);
+                ip.push(0);
+                ip.extend_from_slice(&((20 + tcp.len()) as u16).to_be_bytes());
+                // Identification, then "don't fragment" with offset 0
+                ip.extend_from_slice(&[0, 0, 0x40, 0]);
+                ip.push(IP_TTL);
+                ip.push(IP_PROTOCOL_TCP);
+                ip.extend_from_slice(&[0, 0]);
+                ip.extend_from_slice(&source.octets());
+                ip.extend_from_slice(&destination.octets());
+                let checksum = internet_checksum(&ip);
+                ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+                ip.extend_from_slice(&tcp);
+                ip
+            }
+            (IpAddr::V6(source), IpAddr::V6(destination)) => {
+                let mut pseudo = Vec::with_capacity(40 + tcp.len());
+                pseudo.extend_from_slice(&source.octets());
+                pseudo.extend_from_slice(&destination.octets());
+                pseudo.extend_from_slice(&(tcp.len() as u32).to_be_bytes());
+                pseudo.extend_from_slice(&[0, 0, 0, IP_PROTOCOL_TCP]);
+                pseudo.extend_from_slice(&tcp);
+                tcp[16..18].copy_from_slice(&internet_checksum(&pseudo).to_be_bytes());
+
+                let mut ip = Vec::with_capacity(40 + tcp.len());
+                ip.extend_from_slice(&[0x60, 0, 0, 0]);
+                ip.extend_from_slice(&(tcp.len() as u16).to_be_bytes());
+                ip.push(IP_PROTOCOL_TCP);
+                ip.push(IP_TTL);
+                ip.extend_from_slice(&source.octets());
+                ip.extend_from_slice(&destination.octets());
+                ip.extend_from_slice(&tcp);
+                ip
+            }
+            _ => unreachable!("addresses are normalised to one IP version in new"),
+        };
+        self.write_packet(timestamp, &packet)?;
+
+        let advance = payload.len() as u32;
+        match direction {
+            Direction::Sent => self.local_seq = self.local_seq.wrapping_add(advance),
+            Direction::Received => self.peer_seq = self.peer_seq.wrapping_add(advance),
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write + Send> FrameSink for PcapngWriter<W> {
+    fn record(
+        &mut self,
+        direction: Direction,
+        timestamp: DateTime<Utc>,
+        frame: &[u8],
+    ) -> io::Result<()> {
+        for segment in frame.chunks(MAX_SEGMENT) {
+            self.write_segment(direction, timestamp, TCP_PSH | TCP_ACK, segment)?;
+        }
+        self.out.flush()
+    }
+}
+
+/// RFC 1071 checksum: ones' complement of the ones' complement sum of 16-bit words.
+fn internet_checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|word| u32::from(word[0]) << 8 | u32::from(*word.get(1).unwrap_or(&0)))
+        .sum();
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Splits a pcapng file into (block type, body) pairs.
+    fn blocks(mut data: &[u8]) -> Vec<(u32, Vec<u8>)> {
+        let mut blocks = Vec::new();
+        while !data.is_empty() {
+            let block_type = u32::from_le_bytes(data[0..4].try_into().unwrap());
+            let len = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+            assert_eq!(len % 4, 0);
+            assert_eq!(&data[len - 4..len], &data[4..8]);
+            blocks.push((block_type, data[8..len - 4].to_vec()));
+            data = &data[len..];
+        }
+        blocks
+    }
+
+    #[test]
+    fn test_capture_layout() {
+        let local = "10.0.0.1:50000".parse().unwrap();
+        let peer = "10.0.0.2:8333".parse().unwrap();
+        let mut file = Vec::new();
+        let mut writer = PcapngWriter::new(&mut file, local, peer).unwrap();
+        for (direction, frame) in [
+            (Direction::Sent, &b"first frame"[..]),
+            (Direction::Received, b"reply"),
+            (Direction::Sent, b"second"),
+        ] {
+            writer.record(direction, Utc::now(), frame).unwrap();
+        }
+
+        let blocks = blocks(&file);
+        let types: Vec<u32> = blocks.iter().map(|(block_type, _)| *block_type).collect();
+        assert_eq!(
+            types[..2],
+            [BLOCK_SECTION_HEADER, BLOCK_INTERFACE_DESCRIPTION]
+        );
+        assert!(types[2..].iter().all(|t| *t == BLOCK_ENHANCED_PACKET));
+        // SYN, SYN-ACK, ACK and three frames
+        assert_eq!(types.len(), 8);
+
+        let packets: Vec<&[u8]> = blocks[2..]
+            .iter()
+            .map(|(_, body)| {
+                let len = u32::from_le_bytes(body[12..16].try_into().unwrap()) as usize;
+                &body[20..20 + len]
+            })
+            .collect();
+        for packet in &packets {
+            assert_eq!(internet_checksum(&packet[..20]), 0);
+        }
+
+        let second = packets[5];
+        assert_eq!(&second[12..16], &[10, 0, 0, 1]);
+        assert_eq!(&second[40..], b"second");
+        // Sequence number after the SYN and "first frame", acknowledging the SYN and "reply"
+        assert_eq!(&second[24..28], &12u32.to_be_bytes());
+        assert_eq!(&second[28..32], &6u32.to_be_bytes());
+    }
+}
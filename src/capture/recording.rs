@@ -0,0 +1,278 @@
+//! Compact native recording of a session, replayable without a network.
+//!
+//! A recording starts with [`RECORDING_MAGIC`], followed by one record per frame:
+//!
+//! | Field     | Size | Description                                      |
+//! |-----------|------|--------------------------------------------------|
+//! | direction | 1    | `0` sent by us, `1` received from the peer       |
+//! | timestamp | 8    | microseconds since the Unix epoch, little endian |
+//! | length    | 4    | length of the frame, little endian               |
+//! | frame     | var  | the raw v1 frame, header included                |
+
+use std::{
+    fs::File,
+    io::{self, BufReader, Cursor, Read, Write},
+    net::{Ipv4Addr, SocketAddr},
+    path::Path,
+};
+
+use chrono::{DateTime, Utc};
+use tracing::{info, info_span};
+
+use super::{Direction, FrameSink};
+use crate::{
+    error::Error,
+    handshake::{handshake_transport, ConnectOptions},
+    message_reader::MessageReader,
+    messages::message::MessageMagicNumber,
+    report::HandshakeReport,
+};
+
+/// Identifies a recording file and its format version.
+pub const RECORDING_MAGIC: [u8; 8] = *b"HSKREC\x00\x01";
+
+/// Longest frame a record may hold: a header and the largest payload a peer may send.
+pub const MAX_FRAME_LEN: usize =
+    MessageReader::HEADER_SIZE + MessageReader::MAX_PAYLOAD_SIZE as usize;
+
+/// One frame of a recording.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// Whether the frame was sent or received.
+    pub direction: Direction,
+    /// When the frame was sent or received.
+    pub timestamp: DateTime<Utc>,
+    /// The raw v1 frame.
+    pub frame: Vec<u8>,
+}
+
+/// Writes frames in the native recording format.
+pub struct RecordingWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> RecordingWriter<W> {
+    /// Starts a recording by writing its magic to `out`.
+    ///
+    /// # Arguments
+    ///
+    /// * `out` - Where the recording is written.
+    pub fn new(mut out: W) -> io::Result<Self> {
+        out.write_all(&RECORDING_MAGIC)?;
+        out.flush()?;
+        Ok(Self { out })
+    }
+}
+
+impl<W: Write + Send> FrameSink for RecordingWriter<W> {
+    fn record(
+        &mut self,
+        direction: Direction,
+        timestamp: DateTime<Utc>,
+        frame: &[u8],
+    ) -> io::Result<()> {
+        let direction = match direction {
+            Direction::Sent => 0u8,
+            Direction::Received => 1u8,
+        };
+        self.out.write_all(&[direction])?;
+        self.out
+            .write_all(&timestamp.timestamp_micros().to_le_bytes())?;
+        self.out.write_all(&(frame.len() as u32).to_le_bytes())?;
+        self.out.write_all(frame)?;
+        self.out.flush()
+    }
+}
+
+/// Reads all records of a recording. Records longer than `MAX_FRAME_LEN` are rejected before
+/// anything is allocated for them, as the recording may be corrupt or hostile.
+///
+/// # Arguments
+///
+/// * `input` - The recording, starting with its magic.
+pub fn read_recording<R: Read>(mut input: R) -> io::Result<Vec<Record>> {
+    let mut magic = [0u8; 8];
+    input.read_exact(&mut magic)?;
+    if magic != RECORDING_MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a handshaker recording",
+        ));
+    }
+
+    let mut records = Vec::new();
+    loop {
+        let mut direction = [0u8; 1];
+        if input.read(&mut direction)? == 0 {
+            return Ok(records);
+        }
+        let direction = match direction[0] {
+            0 => Direction::Sent,
+            1 => Direction::Received,
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid direction {other} in recording"),
+                ))
+            }
+        };
+        let mut timestamp = [0u8; 8];
+        input.read_exact(&mut timestamp)?;
+        let timestamp = DateTime::from_timestamp_micros(i64::from_le_bytes(timestamp))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid timestamp"))?;
+        let mut len = [0u8; 4];
+        input.read_exact(&mut len)?;
+        let len = u32::from_le_bytes(len) as usize;
+        if len > MAX_FRAME_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("frame of {len} bytes in recording, the maximum is {MAX_FRAME_LEN}"),
+            ));
+        }
+        let mut frame = vec![0u8; len];
+        input.read_exact(&mut frame)?;
+        records.push(Record {
+            direction,
+            timestamp,
+            frame,
+        });
+    }
+}
+
+/// Returns the frames received from the peer as a single stream, as they came off the wire.
+///
+/// # Arguments
+///
+/// * `records` - The records of a recording.
+pub fn received_stream(records: &[Record]) -> Cursor<Vec<u8>> {
+    Cursor::new(
+        records
+            .iter()
+            .filter(|record| record.direction == Direction::Received)
+            .flat_map(|record| record.frame.iter().copied())
+            .collect(),
+    )
+}
+
+/// Replays the handshake recorded in the file at `path`, feeding the received frames to the
+/// handshake engine and discarding what it sends.
+///
+/// The network is taken from the magic of the first received frame.
+///
+/// # Arguments
+///
+/// * `path` - Path of the recording.
+pub fn replay(path: &Path) -> Result<HandshakeReport, Error> {
+    let records = read_recording(BufReader::new(File::open(path)?))?;
+    let first = records
+        .iter()
+        .find(|record| record.direction == Direction::Received && record.frame.len() >= 4)
+        .ok_or_else(|| anyhow::anyhow!("recording contains no received frames"))?;
+    let magic = [
+        first.frame[0],
+        first.frame[1],
+        first.frame[2],
+        first.frame[3],
+    ];
+    let network = MessageMagicNumber::try_from(magic)
+        .unwrap_or(MessageMagicNumber::Custom { magic, port: 0 });
+
+    let _span = info_span!("replay", path = %path.display(), ?network).entered();
+    info!(frames = records.len(), "replaying");
+    let mut report = HandshakeReport::new(path.display().to_string(), network.clone());
+    let result = handshake_transport(
+        Box::new(received_stream(&records)),
+        Box::new(io::sink()),
+        &network,
+        SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+        &ConnectOptions::default(),
+        &mut report,
+    );
+    report.finish(&result);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::messages::{
+        message::Message, verack::VerackMessageBuilder, version::VersionMessageBuilder,
+        ToNetworkMessage,
+    };
+
+    #[test]
+    fn test_record_and_replay() {
+        let network = MessageMagicNumber::Regtest;
+        let address = "127.0.0.1:18444".parse().unwrap();
+        let version = Message::Version(VersionMessageBuilder::new(network.clone(), address, 0, 1))
+            .to_network_message()
+            .unwrap();
+        let verack = Message::Verack(VerackMessageBuilder::new(network.clone()))
+            .to_network_message()
+            .unwrap();
+
+        let mut file = Vec::new();
+        let mut writer = RecordingWriter::new(&mut file).unwrap();
+        let now = DateTime::from_timestamp_micros(Utc::now().timestamp_micros()).unwrap();
+        for (direction, frame) in [
+            (Direction::Sent, &version),
+            (Direction::Received, &version),
+            (Direction::Sent, &verack),
+            (Direction::Received, &verack),
+        ] {
+            writer.record(direction, now, frame).unwrap();
+        }
+
+        let records = read_recording(file.as_slice()).unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(
+            records[3],
+            Record {
+                direction: Direction::Received,
+                timestamp: now,
+                frame: verack.clone(),
+            }
+        );
+        let mut report = HandshakeReport::new(address.to_string(), network.clone());
+        handshake_transport(
+            Box::new(received_stream(&records)),
+            Box::new(io::sink()),
+            &network,
+            address,
+            &ConnectOptions::default(),
+            &mut report,
+        )
+        .unwrap();
+        assert_eq!(report.steps.len(), 4);
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["user_agent"], "emil-handshake");
+        assert_eq!(json["our_address"], "127.0.0.1:18444");
+    }
+
+    #[test]
+    fn test_truncated_recording() {
+        assert!(read_recording(&b"HSKREC"[..]).is_err());
+        let mut file = RECORDING_MAGIC.to_vec();
+        file.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0, 0, 24, 0, 0, 0, 0xFA]);
+        assert!(read_recording(file.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_fuzz_corpus() {
+        let handshake =
+            read_recording(&include_bytes!("../../fuzz/corpus/recording/handshake")[..]);
+        let handshake = handshake.unwrap();
+        assert_eq!(handshake.len(), 4);
+        assert_eq!(handshake[3].direction, Direction::Received);
+
+        let truncated =
+            read_recording(&include_bytes!("../../fuzz/corpus/recording/truncated_frame")[..]);
+        assert_eq!(truncated.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+
+        let oversized =
+            read_recording(&include_bytes!("../../fuzz/corpus/recording/oversized_length")[..]);
+        let error = oversized.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().starts_with("frame of 4294967295 bytes"));
+    }
+}
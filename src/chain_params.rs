@@ -0,0 +1,263 @@
+use crate::messages::message::{compact_size, double_sha256, MessageMagicNumber};
+
+/// Everything the handshaker needs to know about a Bitcoin network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainParams {
+    /// The magic bytes that start every message on the network.
+    pub magic: [u8; 4],
+    /// The default P2P port.
+    pub default_port: u16,
+    /// Host names of the DNS seeds.
+    pub dns_seeds: &'static [&'static str],
+    /// Hard-coded peer addresses (`ip:port`), used when no DNS seed answers.
+    pub fixed_seeds: &'static [&'static str],
+    /// Hash of the genesis block, in internal (wire) byte order.
+    pub genesis_hash: [u8; 32],
+    /// Whether nodes on the network understand BIP155 `addrv2` messages.
+    pub supports_bip155: bool,
+    /// The lowest protocol version a peer may announce.
+    pub min_protocol_version: i32,
+    /// Timestamp of the genesis block.
+    pub genesis_time: u32,
+    /// Compact target of the genesis block.
+    pub genesis_bits: u32,
+    /// The easiest target a block may have, as compact bits.
+    pub pow_limit: u32,
+    /// Whether a block more than 20 minutes after its parent may have the easiest target.
+    pub pow_allow_min_difficulty_blocks: bool,
+    /// Whether the target never changes.
+    pub pow_no_retargeting: bool,
+    /// Whether the retargeting and timewarp rules of BIP94 apply.
+    pub enforce_bip94: bool,
+}
+
+/// Lowest protocol version Bitcoin Core still accepts from its peers.
+const MIN_PEER_PROTO_VERSION: i32 = 31800;
+
+pub const MAIN: ChainParams = ChainParams {
+    magic: [0xF9, 0xBE, 0xB4, 0xD9],
+    default_port: 8333,
+    dns_seeds: &[
+        "seed.bitcoin.sipa.be",
+        "dnsseed.bluematt.me",
+        "dnsseed.bitcoin.dashjr-list-of-p2p-nodes.us",
+        "seed.bitcoinstats.com",
+        "seed.bitcoin.jonasschnelli.ch",
+        "seed.btc.petertodd.net",
+        "seed.bitcoin.sprovoost.nl",
+        "dnsseed.emzy.de",
+        "seed.bitcoin.wiz.biz",
+        "seed.mainnet.achownodes.xyz",
+    ],
+    fixed_seeds: &[
+        "5.9.2.145:8333",
+        "23.175.0.212:8333",
+        "51.75.144.201:8333",
+        "82.197.215.125:8333",
+        "91.199.41.103:8333",
+        "136.243.139.96:8333",
+        "144.76.43.87:8333",
+        "176.9.150.253:8333",
+    ],
+    genesis_hash: hash_from_hex("000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f"),
+    supports_bip155: true,
+    min_protocol_version: MIN_PEER_PROTO_VERSION,
+    genesis_time: 1231006505,
+    genesis_bits: 0x1d00ffff,
+    pow_limit: 0x1d00ffff,
+    pow_allow_min_difficulty_blocks: false,
+    pow_no_retargeting: false,
+    enforce_bip94: false,
+};
+
+pub const TESTNET: ChainParams = ChainParams {
+    magic: [0x0B, 0x11, 0x09, 0x07],
+    default_port: 18333,
+    dns_seeds: &[
+        "testnet-seed.bitcoin.jonasschnelli.ch",
+        "seed.tbtc.petertodd.net",
+        "seed.testnet.bitcoin.sprovoost.nl",
+        "testnet-seed.bluematt.me",
+        "seed.testnet.achownodes.xyz",
+    ],
+    fixed_seeds: &[
+        "5.9.138.70:18333",
+        "18.189.156.253:18333",
+        "37.16.104.108:18333",
+        "65.21.114.214:18333",
+        "95.217.73.162:18333",
+        "178.128.39.172:18333",
+    ],
+    genesis_hash: hash_from_hex("000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943"),
+    supports_bip155: true,
+    min_protocol_version: MIN_PEER_PROTO_VERSION,
+    genesis_time: 1296688602,
+    genesis_bits: 0x1d00ffff,
+    pow_limit: 0x1d00ffff,
+    pow_allow_min_difficulty_blocks: true,
+    pow_no_retargeting: false,
+    enforce_bip94: false,
+};
+
+pub const TESTNET4: ChainParams = ChainParams {
+    magic: [0x1C, 0x16, 0x3F, 0x28],
+    default_port: 48333,
+    dns_seeds: &[
+        "seed.testnet4.bitcoin.sprovoost.nl",
+        "seed.testnet4.wiz.biz",
+    ],
+    fixed_seeds: &[
+        "5.161.66.241:48333",
+        "45.79.52.207:48333",
+        "65.109.62.59:48333",
+        "103.165.192.204:48333",
+    ],
+    genesis_hash: hash_from_hex("00000000da84f2bafbbc53dee25a72ae507ff4914b867c565be350b0da8bf043"),
+    supports_bip155: true,
+    min_protocol_version: MIN_PEER_PROTO_VERSION,
+    genesis_time: 1714777860,
+    genesis_bits: 0x1d00ffff,
+    pow_limit: 0x1d00ffff,
+    pow_allow_min_difficulty_blocks: true,
+    pow_no_retargeting: false,
+    enforce_bip94: true,
+};
+
+pub const SIGNET: ChainParams = ChainParams {
+    magic: [0x0A, 0x03, 0xCF, 0x40],
+    default_port: 38333,
+    dns_seeds: &[
+        "seed.signet.bitcoin.sprovoost.nl",
+        "seed.signet.achownodes.xyz",
+    ],
+    fixed_seeds: &[
+        "178.128.221.177:38333",
+        "103.16.128.63:38333",
+        "153.126.143.201:38333",
+        "195.201.47.134:38333",
+    ],
+    genesis_hash: hash_from_hex("00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef6"),
+    supports_bip155: true,
+    min_protocol_version: MIN_PEER_PROTO_VERSION,
+    genesis_time: 1598918400,
+    genesis_bits: 0x1e0377ae,
+    pow_limit: 0x1e0377ae,
+    pow_allow_min_difficulty_blocks: false,
+    pow_no_retargeting: false,
+    enforce_bip94: false,
+};
+
+pub const REGTEST: ChainParams = ChainParams {
+    magic: [0xFA, 0xBF, 0xB5, 0xDA],
+    default_port: 18444,
+    dns_seeds: &[],
+    fixed_seeds: &[],
+    genesis_hash: hash_from_hex("0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206"),
+    supports_bip155: true,
+    min_protocol_version: MIN_PEER_PROTO_VERSION,
+    genesis_time: 1296688602,
+    genesis_bits: 0x207fffff,
+    pow_limit: 0x207fffff,
+    pow_allow_min_difficulty_blocks: true,
+    pow_no_retargeting: true,
+    enforce_bip94: false,
+};
+
+impl MessageMagicNumber {
+    /// Returns the chain parameters of the network.
+    ///
+    /// A custom network is treated as a regtest fork and a custom signet as the default signet,
+    /// with the magic and port replaced and no seeds.
+    pub fn params(&self) -> ChainParams {
+        match self {
+            MessageMagicNumber::Main => MAIN,
+            MessageMagicNumber::Testnet => TESTNET,
+            MessageMagicNumber::Testnet4 => TESTNET4,
+            MessageMagicNumber::Signet => SIGNET,
+            MessageMagicNumber::Regtest => REGTEST,
+            MessageMagicNumber::Custom { magic, port } => ChainParams {
+                magic: *magic,
+                default_port: *port,
+                ..REGTEST
+            },
+            MessageMagicNumber::CustomSignet { challenge, port } => ChainParams {
+                magic: signet_magic(challenge),
+                default_port: port.unwrap_or(SIGNET.default_port),
+                dns_seeds: &[],
+                fixed_seeds: &[],
+                ..SIGNET
+            },
+        }
+    }
+}
+
+/// Derives the magic of a signet the way Bitcoin Core does: the first 4 bytes of the double
+/// SHA256 of the serialized challenge script (length prefix followed by the script).
+pub fn signet_magic(challenge: &[u8]) -> [u8; 4] {
+    let mut serialized = compact_size(challenge.len() as u64);
+    serialized.extend_from_slice(challenge);
+    let hash = double_sha256(&serialized);
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+/// Converts a hash as displayed by block explorers (big-endian hex) into internal byte order.
+const fn hash_from_hex(hex: &str) -> [u8; 32] {
+    const fn nibble(c: u8) -> u8 {
+        match c {
+            b'0'..=b'9' => c - b'0',
+            b'a'..=b'f' => c - b'a' + 10,
+            _ => panic!("invalid hex digit"),
+        }
+    }
+
+    let hex = hex.as_bytes();
+    assert!(hex.len() == 64, "hash must be 64 hex digits");
+    let mut hash = [0u8; 32];
+    let mut i = 0;
+    while i < 32 {
+        hash[31 - i] = (nibble(hex[2 * i]) << 4) | nibble(hex[2 * i + 1]);
+        i += 1;
+    }
+    hash
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_genesis_hash_byte_order() {
+        // Last bytes of the hash are the leading zeros of the displayed hash
+        assert_eq!(MAIN.genesis_hash[0], 0x6f);
+        assert_eq!(MAIN.genesis_hash[31], 0x00);
+        assert_eq!(MessageMagicNumber::Main.params(), MAIN);
+        // Sanity check that the magic numbers did not change while moving them here
+        let magic: [u8; 4] = MessageMagicNumber::Regtest.into();
+        assert_eq!(magic, [0xFA, 0xBF, 0xB5, 0xDA]);
+    }
+
+    #[test]
+    fn test_fixed_seeds_parse() {
+        for params in [MAIN, TESTNET, TESTNET4, SIGNET] {
+            assert!(!params.fixed_seeds.is_empty());
+            for seed in params.fixed_seeds {
+                let address: std::net::SocketAddr = seed.parse().unwrap();
+                assert_eq!(address.port(), params.default_port);
+            }
+        }
+    }
+
+    #[test]
+    fn test_default_signet_challenge_magic() {
+        let challenge = hex::decode(
+            "512103ad5e0edad18cb1f0fc0d28a3d4f1f3e445640337489abb10404f2d1e086be430210359ef5021964fe22d6f8e05b2463c9540ce96883fe3b278760f048f5189f2e6c452ae",
+        )
+        .unwrap();
+        let network = MessageMagicNumber::CustomSignet {
+            challenge,
+            port: None,
+        };
+        assert_eq!(network.params().magic, SIGNET.magic);
+        assert_eq!(network.params().default_port, SIGNET.default_port);
+    }
+}
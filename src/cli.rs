@@ -0,0 +1,266 @@
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, Subcommand};
+
+use crate::{
+    broadcast::DEFAULT_BROADCAST_WAIT,
+    config::{Config, OutputFormat},
+    error::Error,
+    logging::LogFormat,
+    messages::message::MessageMagicNumber,
+};
+
+/// The configuration file loaded when `--config` is not given, if it exists.
+const DEFAULT_CONFIG: &str = "config.json";
+
+/// Bitcoin P2P handshake and diagnostics tool.
+#[derive(Parser, Debug)]
+#[command(name = "handshaker", version)]
+pub struct Cli {
+    /// Configuration file in JSON, TOML or YAML. Defaults to `config.json` if it exists.
+    #[arg(long, short, global = true)]
+    pub config: Option<PathBuf>,
+    /// Network to use: main, testnet, testnet4, signet or regtest. Overrides `network_type`.
+    #[arg(long, global = true, value_parser = parse_network)]
+    pub network: Option<MessageMagicNumber>,
+    /// Timeout in seconds for connecting and for every read. Overrides `timeout`.
+    #[arg(long, global = true)]
+    pub timeout: Option<u64>,
+    /// User agent announced in our version message. Overrides `user_agent`.
+    #[arg(long, global = true)]
+    pub user_agent: Option<String>,
+    /// How many times a peer is retried after a transient failure. Overrides `retry.retries`.
+    #[arg(long, global = true)]
+    pub retries: Option<u32>,
+    /// Format of the results printed on stdout. Overrides `output`.
+    #[arg(long, global = true, value_enum)]
+    pub output: Option<OutputFormat>,
+    /// Format of the log lines written to stderr. Overrides `log_format`.
+    #[arg(long, global = true, value_enum)]
+    pub log_format: Option<LogFormat>,
+    /// What to do, `handshake` if omitted.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// The subcommands of the CLI.
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Handshake with a node, or with one found through the DNS seeds.
+    Handshake {
+        /// Address of the node. Overrides `dest_addr`.
+        address: Option<String>,
+    },
+    /// Accept inbound peers and answer their handshake.
+    Listen {
+        /// Address to listen on, all interfaces on the default port of the network if omitted.
+        #[arg(long)]
+        bind: Option<String>,
+    },
+    /// Handshake with every peer returned by the DNS seeds and report each.
+    Crawl,
+    /// Decode a recording, a file of raw frames or hex-encoded frames.
+    Decode {
+        /// Path of the file, or the hex-encoded frames.
+        input: String,
+    },
+    /// Handshake, then print every message the node sends until it disconnects.
+    Monitor {
+        /// Address of the node. Overrides `dest_addr`.
+        address: Option<String>,
+    },
+    /// Handshake with relay on, then print the transactions the node announces, reconnecting
+    /// when it disconnects.
+    Mempool {
+        /// Address of the node. Overrides `dest_addr`.
+        address: Option<String>,
+    },
+    /// Handshake, then download and validate the headers of the node's best chain.
+    Headers {
+        /// Address of the node. Overrides `dest_addr`.
+        address: Option<String>,
+    },
+    /// Handshake with relay on, then announce a raw transaction and serve it when the node asks
+    /// for it.
+    Broadcast {
+        /// Address of the node. Overrides `dest_addr`.
+        address: Option<String>,
+        /// Path of a file holding the hex-encoded transaction, read from stdin if omitted or `-`.
+        #[arg(long)]
+        file: Option<PathBuf>,
+        /// How many seconds to wait for the node to ask for the transaction.
+        #[arg(long, default_value_t = DEFAULT_BROADCAST_WAIT.as_secs())]
+        wait: u64,
+    },
+    /// Handshake, then download a block and check its proof of work and merkle root.
+    Block {
+        /// Hash of the block, as displayed by block explorers.
+        hash: String,
+        /// Address of the node. Overrides `dest_addr`.
+        address: Option<String>,
+    },
+    /// Handshake, then verify the node's compact block filters and match scripts against them.
+    Filters {
+        /// Address of the node. Overrides `dest_addr`.
+        address: Option<String>,
+        /// A hex-encoded scriptPubKey to look for, may be repeated.
+        #[arg(long = "script", required = true)]
+        scripts: Vec<String>,
+        /// Height of the first filter to download.
+        #[arg(long, default_value_t = 0)]
+        start: u32,
+    },
+    /// Handshake with relay off, load a bloom filter, then download a block as a `merkleblock`
+    /// and the transactions matching the filter.
+    #[command(name = "merkleblock")]
+    MerkleBlock {
+        /// Hash of the block, as displayed by block explorers.
+        hash: String,
+        /// Address of the node. Overrides `dest_addr`.
+        address: Option<String>,
+        /// A hex-encoded element to add to the filter, e.g. a public key hash or a txid in
+        /// internal byte order. May be repeated.
+        #[arg(long = "element", required = true)]
+        elements: Vec<String>,
+        /// A hex-encoded element to add with `filteradd` once the filter is loaded. May be
+        /// repeated.
+        #[arg(long = "add")]
+        added: Vec<String>,
+    },
+    /// Detect which network the node at an address speaks.
+    Probe {
+        /// Address of the node, e.g. `94.130.79.4:8333`.
+        address: String,
+    },
+    /// Replay a recorded handshake without a network.
+    Replay {
+        /// Path of the recording.
+        file: PathBuf,
+    },
+}
+
+impl Cli {
+    /// Loads the configuration file and the environment, then applies the flags on top of them.
+    pub fn config(&self) -> Result<Config, Error> {
+        let mut config = match &self.config {
+            Some(path) => Config::load(Some(&path.to_string_lossy()))?,
+            None if Path::new(DEFAULT_CONFIG).exists() => Config::load(Some(DEFAULT_CONFIG))?,
+            None => Config::load(None)?,
+        };
+        self.apply(&mut config);
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Overrides the fields of `config` set by flags.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The configuration to override.
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(network) = &self.network {
+            config.network_type = network.clone();
+        }
+        if let Some(timeout) = self.timeout {
+            config.timeout = Some(timeout);
+        }
+        if let Some(user_agent) = &self.user_agent {
+            config.user_agent = Some(user_agent.clone());
+        }
+        if let Some(retries) = self.retries {
+            config.retry.retries = retries;
+        }
+        if let Some(output) = self.output {
+            config.output = output;
+        }
+        if let Some(log_format) = self.log_format {
+            config.log_format = log_format;
+        }
+        match &self.command {
+            Some(Command::Handshake {
+                address: Some(address),
+            })
+            | Some(Command::Monitor {
+                address: Some(address),
+            })
+            | Some(Command::Headers {
+                address: Some(address),
+            })
+            | Some(Command::Mempool {
+                address: Some(address),
+            })
+            | Some(Command::Broadcast {
+                address: Some(address),
+                ..
+            })
+            | Some(Command::Block {
+                address: Some(address),
+                ..
+            })
+            | Some(Command::Filters {
+                address: Some(address),
+                ..
+            })
+            | Some(Command::MerkleBlock {
+                address: Some(address),
+                ..
+            }) => config.dest_addr = Some(address.clone()),
+            _ => {}
+        }
+        // Announcing transactions after asking not to get any gets us disconnected
+        if let Some(Command::Mempool { .. } | Command::Broadcast { .. }) = self.command {
+            config.relay = true;
+        }
+        // With a bloom filter, transactions are relayed once the filter is loaded (BIP37)
+        if let Some(Command::MerkleBlock { .. }) = self.command {
+            config.relay = false;
+        }
+    }
+}
+
+/// Parses the name of a well-known network, custom networks can only be configured in the
+/// configuration file.
+fn parse_network(name: &str) -> Result<MessageMagicNumber, String> {
+    MessageMagicNumber::KNOWN
+        .into_iter()
+        .find(|network| format!("{network:?}").eq_ignore_ascii_case(name))
+        .ok_or_else(|| format!("unknown network: {name}"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_flags_override_config() {
+        let cli = Cli::try_parse_from([
+            "handshaker",
+            "--network",
+            "testnet4",
+            "--timeout",
+            "3",
+            "--output",
+            "json",
+            "handshake",
+            "127.0.0.1",
+        ])
+        .unwrap();
+        let mut config = Config::default();
+        cli.apply(&mut config);
+        assert_eq!(config.network_type, MessageMagicNumber::Testnet4);
+        assert_eq!(config.timeout, Some(3));
+        assert_eq!(config.output, OutputFormat::Json);
+        assert_eq!(config.dest_addr.as_deref(), Some("127.0.0.1"));
+        assert_eq!(config.user_agent, None);
+    }
+
+    #[test]
+    fn test_argument_errors() {
+        let error: Error = Cli::try_parse_from(["handshaker", "--network", "moon", "crawl"])
+            .unwrap_err()
+            .into();
+        assert_eq!(error.kind(), "cli");
+        assert!(Cli::try_parse_from(["handshaker", "decode"]).is_err());
+        assert!(Cli::try_parse_from(["handshaker", "teleport"]).is_err());
+    }
+}
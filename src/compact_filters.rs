@@ -0,0 +1,203 @@
+use std::fmt;
+
+use serde::Serialize;
+use tracing::{debug, info};
+
+use crate::{
+    block_filter::{filter_header, BlockFilter},
+    error::Error,
+    handshake::Connection,
+    header_sync::{download_headers, HeaderChain},
+    messages::{
+        filter::{
+            CFCheckpt, CFHeaders, CFilter, GetCFCheckpt, GetCFHeaders, GetCFilters,
+            CFCHECKPT_INTERVAL, FILTER_TYPE_BASIC, MAX_GETCFHEADERS_SIZE, MAX_GETCFILTERS_SIZE,
+        },
+        message::{double_sha256, hash_to_hex},
+        version::NODE_COMPACT_FILTERS,
+    },
+};
+
+/// A block whose filter matches one of the scripts looked for.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct FilterMatch {
+    /// Height of the block.
+    pub height: u32,
+    /// Hash of the block, as displayed by block explorers.
+    pub hash: String,
+}
+
+/// The outcome of a filter scan, printed by `handshaker filters`.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct FilterScanReport {
+    /// Address of the peer.
+    pub peer: String,
+    /// Height of the validated tip, the filter headers are verified up to it.
+    pub height: u32,
+    /// Height of the first filter checked.
+    pub start_height: u32,
+    /// How many filters were downloaded and checked against their filter headers.
+    pub filters_checked: usize,
+    /// The blocks whose filters match, false positives included.
+    pub matches: Vec<FilterMatch>,
+}
+
+impl fmt::Display for FilterScanReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: filter headers verified up to height {}, {} filters checked from height {}, {} matching",
+            self.peer,
+            self.height,
+            self.filters_checked,
+            self.start_height,
+            self.matches.len()
+        )?;
+        for filter_match in &self.matches {
+            write!(f, "\n  {} {}", filter_match.height, filter_match.hash)?;
+        }
+        Ok(())
+    }
+}
+
+/// Syncs the peer's headers, then verifies its chain of filter headers (BIP157) from the
+/// genesis block against its checkpoints, and downloads the basic filters (BIP158) from
+/// `start_height` to match `scripts` against them. Every filter is checked against its filter
+/// header.
+///
+/// # Arguments
+///
+/// * `connection` - A connection whose handshake completed.
+/// * `services` - The services the peer announced in its version message.
+/// * `scripts` - The scriptPubKeys looked for.
+/// * `start_height` - Height of the first filter to download.
+pub fn scan_filters(
+    connection: &mut Connection,
+    services: u64,
+    scripts: &[Vec<u8>],
+    start_height: u32,
+) -> Result<FilterScanReport, Error> {
+    let peer = connection.reader.peer().to_owned();
+    let violation = |reason: String| Error::ProtocolViolation {
+        peer: peer.clone(),
+        reason,
+    };
+    if services & NODE_COMPACT_FILTERS == 0 {
+        return Err(Error::MissingService {
+            peer: peer.clone(),
+            service: "NODE_COMPACT_FILTERS",
+        });
+    }
+    let mut chain = HeaderChain::new(connection.network.params());
+    download_headers(connection, &mut chain)?;
+    let height = chain.height();
+    let hash_at = |height| chain.hash_at(height).expect("height within the chain");
+
+    connection.send_raw("getcfcheckpt", &GetCFCheckpt::new(chain.tip()).payload())?;
+    let checkpoint = CFCheckpt::try_from(&next_message(connection, "cfcheckpt")?[..])
+        .map_err(|e| violation(format!("malformed cfcheckpt message: {e}")))?;
+    if checkpoint.filter_type != FILTER_TYPE_BASIC
+        || checkpoint.stop_hash != chain.tip()
+        || checkpoint.filter_headers.len() != (height / CFCHECKPT_INTERVAL) as usize
+    {
+        return Err(violation("cfcheckpt does not match the request".to_owned()));
+    }
+
+    // The filter hashes by height, their headers chained from the genesis block
+    let mut filter_hashes = Vec::with_capacity(height as usize + 1);
+    let mut previous_header = [0; 32];
+    for start in (0..=height).step_by(MAX_GETCFHEADERS_SIZE as usize) {
+        let stop = height.min(start + MAX_GETCFHEADERS_SIZE - 1);
+        let request = GetCFHeaders::new(start, hash_at(stop));
+        connection.send_raw("getcfheaders", &request.payload())?;
+        let cfheaders = CFHeaders::try_from(&next_message(connection, "cfheaders")?[..])
+            .map_err(|e| violation(format!("malformed cfheaders message: {e}")))?;
+        if cfheaders.filter_type != FILTER_TYPE_BASIC
+            || cfheaders.stop_hash != request.stop_hash
+            || cfheaders.filter_hashes.len() != (stop - start + 1) as usize
+        {
+            return Err(violation("cfheaders does not match the request".to_owned()));
+        }
+        if cfheaders.previous_filter_header != previous_header {
+            return Err(violation(format!(
+                "filter headers from height {start} do not follow the previous ones"
+            )));
+        }
+        for (filter_height, filter_hash) in (start..).zip(cfheaders.filter_hashes) {
+            previous_header = filter_header(&filter_hash, &previous_header);
+            filter_hashes.push(filter_hash);
+            let checkpoint_index = (filter_height / CFCHECKPT_INTERVAL) as usize;
+            if filter_height > 0
+                && filter_height.is_multiple_of(CFCHECKPT_INTERVAL)
+                && checkpoint.filter_headers[checkpoint_index - 1] != previous_header
+            {
+                return Err(violation(format!(
+                    "filter header at height {filter_height} does not match its checkpoint"
+                )));
+            }
+        }
+        debug!(height = stop, "filter headers verified");
+    }
+    info!(height, "filter headers verified");
+
+    let mut filters_checked = 0;
+    let mut matches = Vec::new();
+    for start in (start_height..=height).step_by(MAX_GETCFILTERS_SIZE as usize) {
+        let stop = height.min(start + MAX_GETCFILTERS_SIZE - 1);
+        let request = GetCFilters::new(start, hash_at(stop));
+        connection.send_raw("getcfilters", &request.payload())?;
+        // One cfilter per block, in order
+        for filter_height in start..=stop {
+            let cfilter = CFilter::try_from(&next_message(connection, "cfilter")?[..])
+                .map_err(|e| violation(format!("malformed cfilter message: {e}")))?;
+            let block_hash = hash_at(filter_height);
+            if cfilter.filter_type != FILTER_TYPE_BASIC || cfilter.block_hash != block_hash {
+                return Err(violation(format!(
+                    "expected the filter of block {}",
+                    hash_to_hex(&block_hash)
+                )));
+            }
+            if double_sha256(&cfilter.filter) != filter_hashes[filter_height as usize] {
+                return Err(violation(format!(
+                    "filter of block {} does not match its filter header",
+                    hash_to_hex(&block_hash)
+                )));
+            }
+            let filter = BlockFilter::new(cfilter.filter);
+            if filter
+                .match_any(&block_hash, scripts)
+                .map_err(|e| violation(format!("malformed filter: {e}")))?
+            {
+                info!(height = filter_height, "filter matches");
+                matches.push(FilterMatch {
+                    height: filter_height,
+                    hash: hash_to_hex(&block_hash),
+                });
+            }
+            filters_checked += 1;
+        }
+    }
+
+    Ok(FilterScanReport {
+        peer,
+        height,
+        start_height,
+        filters_checked,
+        matches,
+    })
+}
+
+/// Reads messages up to the next `command` one, answering pings, and returns its payload.
+fn next_message(connection: &mut Connection, command: &str) -> Result<Vec<u8>, Error> {
+    loop {
+        connection.reader.read_message()?;
+        match connection.reader.command_name().as_str() {
+            name if name == command => return Ok(connection.reader.payload().to_vec()),
+            "ping" => {
+                let nonce = connection.reader.payload().to_vec();
+                connection.send_raw("pong", &nonce)?;
+            }
+            name => debug!(command = name, "ignored"),
+        }
+    }
+}
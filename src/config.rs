@@ -1,21 +1,168 @@
-use std::{fs, io};
+use std::{
+    env, fmt, fs, io,
+    net::{AddrParseError, IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
+use clap::ValueEnum;
 use serde::Deserialize;
+use serde_json::{Map, Value};
 use thiserror::Error;
 
-use crate::messages::message::MessageMagicNumber;
+use crate::{
+    handshake::ConnectOptions, logging::LogFormat, messages::message::MessageMagicNumber,
+    retry::RetryPolicy,
+};
+
+/// Prefix of the environment variables overriding configuration fields, e.g.
+/// `HANDSHAKER_NETWORK_TYPE=signet` or `HANDSHAKER_PROXY_ADDR=127.0.0.1:9050`.
+pub const ENV_PREFIX: &str = "HANDSHAKER_";
+
+/// The fields of `Config` that can be set from the environment, tables aside.
+const FIELDS: [&str; 12] = [
+    "dest_addr",
+    "network_type",
+    "v2_transport",
+    "capture_file",
+    "recording_file",
+    "output",
+    "log_format",
+    "timeout",
+    "user_agent",
+    "announce_features",
+    "relay",
+    "peers",
+];
+
+/// The tables of `Config` and their fields, set from the environment as
+/// `HANDSHAKER_<TABLE>_<FIELD>`, e.g. `HANDSHAKER_PROXY_ADDR`.
+const TABLES: [(&str, &[&str]); 2] = [
+    (
+        "proxy",
+        &[
+            "addr",
+            "remote_dns",
+            "isolate_streams",
+            "username",
+            "password",
+        ],
+    ),
+    (
+        "retry",
+        &["retries", "initial_backoff_ms", "max_backoff_ms", "jitter"],
+    ),
+];
+
+/// Fields whose environment value is always a string, even if it looks like a number.
+const STRING_FIELDS: [&str; 7] = [
+    "dest_addr",
+    "capture_file",
+    "recording_file",
+    "user_agent",
+    "proxy.addr",
+    "proxy.username",
+    "proxy.password",
+];
+
+/// Longest user agent Bitcoin Core accepts.
+const MAX_USER_AGENT_LEN: usize = 256;
 
 /// Represents configuration data for the `handshaker`.
-#[derive(Deserialize)]
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
-    /// The destination address to connect to.
-    pub dest_addr: String,
-    /// The type of Bitcoin network.
+    /// The destination address to connect to. When omitted, a peer is discovered through
+    /// the DNS seeds of the network (bootstrap mode).
+    #[serde(default)]
+    pub dest_addr: Option<String>,
+    /// The type of Bitcoin network, main if omitted.
+    #[serde(default)]
     pub network_type: MessageMagicNumber,
+    /// SOCKS5 proxy to connect through, e.g. Tor. Connects directly when omitted.
+    #[serde(default)]
+    pub proxy: Option<ProxyConfig>,
+    /// Whether to try the BIP324 v2 encrypted transport first. Peers that reject it are
+    /// reconnected using v1.
+    #[serde(default)]
+    pub v2_transport: bool,
+    /// Path of a pcapng file recording every frame exchanged with the peer.
+    #[serde(default)]
+    pub capture_file: Option<String>,
+    /// Path of a file recording every frame in the native format, see `handshaker replay`.
+    #[serde(default)]
+    pub recording_file: Option<String>,
+    /// How the outcome of the handshake is printed.
+    #[serde(default)]
+    pub output: OutputFormat,
+    /// Format of the log lines written to stderr.
+    #[serde(default)]
+    pub log_format: LogFormat,
+    /// Timeout in seconds for connecting and for every read. Waits forever when omitted.
+    #[serde(default)]
+    pub timeout: Option<u64>,
+    /// User agent announced in our version message.
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    /// Whether to negotiate features with `wtxidrelay`, `sendaddrv2`, `sendheaders`,
+    /// `sendcmpct` and `feefilter`, announcing protocol version 70016.
+    #[serde(default)]
+    pub announce_features: bool,
+    /// Whether to ask peers to announce their transactions. Always on for `handshaker mempool`.
+    #[serde(default)]
+    pub relay: bool,
+    /// More peers to fail over to, in order, when the handshake with `dest_addr` fails.
+    #[serde(default)]
+    pub peers: Vec<String>,
+    /// How failed handshakes are retried.
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    /// `HANDSHAKER_*` variables naming no setting, which were ignored. Warned about once
+    /// logging is set up.
+    #[serde(skip)]
+    pub ignored_vars: Vec<String>,
+}
+
+/// Format of the handshake outcome printed on stdout.
+#[derive(Deserialize, ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Human-readable lines.
+    #[default]
+    Text,
+    /// JSON: a `HandshakeReport` for a handshake, one object per line for streams of results.
+    Json,
+}
+
+/// SOCKS5 proxy settings.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ProxyConfig {
+    /// Address of the proxy, e.g. `127.0.0.1:9050` for Tor.
+    pub addr: String,
+    /// Whether host names are resolved by the proxy instead of locally. `.onion` addresses
+    /// are always resolved by the proxy.
+    #[serde(default = "default_remote_dns")]
+    pub remote_dns: bool,
+    /// Whether every connection authenticates with fresh random credentials, so Tor routes
+    /// it through its own circuit. Ignored when `username` and `password` are set.
+    #[serde(default)]
+    pub isolate_streams: bool,
+    /// Username for the proxy.
+    #[serde(default)]
+    pub username: Option<String>,
+    /// Password for the proxy.
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+fn default_remote_dns() -> bool {
+    true
 }
 
 impl Config {
-    /// Loads a configuration from the specified file.
+    /// Loads a configuration from the specified file, with the `HANDSHAKER_*` environment
+    /// variables applied on top of it.
     ///
     /// # Arguments
     ///
@@ -26,10 +173,291 @@ impl Config {
     /// This function may return a `ConfigLoadError` indicating various errors that can occur
     /// during the process of loading and deserializing the configuration.
     pub fn load_config(file_name: &str) -> Result<Self, ConfigLoadError> {
-        let str_config = fs::read_to_string(file_name)?;
-        let config: Config = serde_json::from_str(&str_config)?;
+        Self::load(Some(file_name))
+    }
+
+    /// Loads a configuration in layers: defaults, then the file if one is given, then the
+    /// `HANDSHAKER_*` environment variables. The format of the file is chosen by its
+    /// extension: `.toml`, `.yaml`/`.yml` or JSON for anything else.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_name` - The name of the configuration file, if any.
+    pub fn load(file_name: Option<&str>) -> Result<Self, ConfigLoadError> {
+        let file = match file_name {
+            Some(file_name) => Some((file_name, fs::read_to_string(file_name)?)),
+            None => None,
+        };
+        Self::load_from(
+            file.as_ref()
+                .map(|(file_name, contents)| (*file_name, contents.as_str())),
+            env::vars(),
+        )
+    }
+
+    /// Loads a configuration from the contents of a file and environment variables.
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - The name and the contents of the configuration file, if any.
+    /// * `vars` - The environment variables, only those starting with `HANDSHAKER_` are used.
+    pub fn load_from(
+        file: Option<(&str, &str)>,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, ConfigLoadError> {
+        let mut value = match file {
+            Some((file_name, contents)) => parse_file(file_name, contents)?,
+            None => Value::Object(Map::new()),
+        };
+        let ignored_vars = apply_env(&mut value, vars);
+        let mut config: Config = serde_path_to_error::deserialize(value).map_err(|e| {
+            ConfigLoadError::Invalid(vec![FieldError::new(
+                e.path().to_string(),
+                e.inner().to_string(),
+            )])
+        })?;
+        config.validate()?;
+        config.ignored_vars = ignored_vars;
         Ok(config)
     }
+
+    /// Checks the values of the fields, reporting every problem found.
+    pub fn validate(&self) -> Result<(), ConfigLoadError> {
+        let mut problems = Vec::new();
+        if let Some(dest_addr) = &self.dest_addr {
+            if self.dest_host_port(dest_addr).is_err() {
+                problems.push(FieldError::new(
+                    "dest_addr",
+                    "not an address with a valid port",
+                ));
+            }
+        }
+        match &self.network_type {
+            MessageMagicNumber::CustomSignet { challenge, .. } if challenge.is_empty() => {
+                problems.push(FieldError::new(
+                    "network_type.customsignet.challenge",
+                    "must not be empty",
+                ));
+            }
+            MessageMagicNumber::Custom { port: 0, .. } => {
+                problems.push(FieldError::new("network_type.custom.port", "must not be 0"));
+            }
+            _ => {}
+        }
+        for (i, peer) in self.peers.iter().enumerate() {
+            if self.dest_host_port(peer).is_err() {
+                problems.push(FieldError::new(
+                    format!("peers[{i}]"),
+                    "not an address with a valid port",
+                ));
+            }
+        }
+        if self.retry.initial_backoff_ms > self.retry.max_backoff_ms {
+            problems.push(FieldError::new(
+                "retry.initial_backoff_ms",
+                "greater than max_backoff_ms",
+            ));
+        }
+        if let Some(proxy) = &self.proxy {
+            if proxy.addr.parse::<SocketAddr>().is_err() {
+                problems.push(FieldError::new("proxy.addr", "not an IP address and port"));
+            }
+            if proxy.username.is_some() != proxy.password.is_some() {
+                problems.push(FieldError::new(
+                    "proxy.password",
+                    "username and password must be set together",
+                ));
+            }
+        }
+        if self.timeout == Some(0) {
+            problems.push(FieldError::new("timeout", "must be at least 1 second"));
+        }
+        if let Some(user_agent) = &self.user_agent {
+            if user_agent.len() > MAX_USER_AGENT_LEN {
+                problems.push(FieldError::new(
+                    "user_agent",
+                    format!("longer than {MAX_USER_AGENT_LEN} bytes"),
+                ));
+            }
+        }
+        if self.capture_file.is_some() && self.capture_file == self.recording_file {
+            problems.push(FieldError::new(
+                "recording_file",
+                "same file as capture_file",
+            ));
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigLoadError::Invalid(problems))
+        }
+    }
+
+    /// Returns the connection settings of the configuration.
+    pub fn connect_options(&self) -> ConnectOptions {
+        ConnectOptions {
+            proxy: self.proxy.clone(),
+            timeout: self.timeout.map(Duration::from_secs),
+            v2_transport: self.v2_transport,
+            capture: self.capture_file.as_ref().map(PathBuf::from),
+            recording: self.recording_file.as_ref().map(PathBuf::from),
+            user_agent: self.user_agent.clone(),
+            announce_features: self.announce_features,
+            relay: self.relay,
+        }
+    }
+
+    /// Parses `dest_addr` into a socket address. An address without a port gets the default
+    /// port of the configured network.
+    ///
+    /// # Arguments
+    ///
+    /// * `dest_addr` - The address to parse, e.g. `94.130.79.4:8333` or `94.130.79.4`.
+    pub fn dest_socket_addr(&self, dest_addr: &str) -> Result<SocketAddr, AddrParseError> {
+        match dest_addr.parse::<SocketAddr>() {
+            Ok(address) => Ok(address),
+            Err(e) => match dest_addr.parse::<IpAddr>() {
+                Ok(ip) => Ok(SocketAddr::new(ip, self.network_type.params().default_port)),
+                Err(_) => Err(e),
+            },
+        }
+    }
+
+    /// Splits `dest_addr` into host and port. Unlike `dest_socket_addr` the host may be a
+    /// host name or a `.onion` address, which can only be reached through the proxy.
+    ///
+    /// # Arguments
+    ///
+    /// * `dest_addr` - The address to split, e.g. `example.onion:8333` or `[::1]`.
+    pub fn dest_host_port(&self, dest_addr: &str) -> Result<(String, u16), ConfigLoadError> {
+        if let Ok(address) = self.dest_socket_addr(dest_addr) {
+            return Ok((address.ip().to_string(), address.port()));
+        }
+        let default_port = self.network_type.params().default_port;
+        if let Some(host) = dest_addr
+            .strip_prefix('[')
+            .and_then(|rest| rest.strip_suffix(']'))
+        {
+            return Ok((host.to_owned(), default_port));
+        }
+        match dest_addr.rsplit_once(':') {
+            Some((host, port)) => {
+                let port = port
+                    .parse()
+                    .map_err(|_| ConfigLoadError::InvalidAddress(dest_addr.to_owned()))?;
+                Ok((host.trim_matches(['[', ']']).to_owned(), port))
+            }
+            None => Ok((dest_addr.to_owned(), default_port)),
+        }
+    }
+}
+
+/// Parses a configuration file into a tree of values, in the format given by its extension.
+fn parse_file(file_name: &str, contents: &str) -> Result<Value, ConfigLoadError> {
+    let extension = Path::new(file_name)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    let value: Value = match extension.as_deref() {
+        Some("toml") => toml::from_str(contents)?,
+        Some("yaml") | Some("yml") => serde_yaml::from_str(contents)?,
+        _ => serde_json::from_str(contents)?,
+    };
+    match value {
+        Value::Object(_) => Ok(value),
+        // An empty YAML file
+        Value::Null => Ok(Value::Object(Map::new())),
+        _ => Err(ConfigLoadError::Invalid(vec![FieldError::new(
+            ".",
+            "the configuration must be a map of fields",
+        )])),
+    }
+}
+
+/// Overrides the fields of `value` with the `HANDSHAKER_*` variables among `vars`, returning
+/// the variables that name no setting, which are ignored.
+fn apply_env(value: &mut Value, vars: impl IntoIterator<Item = (String, String)>) -> Vec<String> {
+    let mut ignored = Vec::new();
+    for (key, raw) in vars {
+        let Some(name) = key.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        let name = name.to_lowercase();
+        let table = TABLES.iter().find_map(|(table, fields)| {
+            name.strip_prefix(table)
+                .and_then(|rest| rest.strip_prefix('_'))
+                .filter(|field| fields.contains(field))
+                .map(|field| vec![*table, field])
+        });
+        let path: Vec<&str> = if let Some(path) = table {
+            path
+        } else if FIELDS.contains(&name.as_str()) {
+            vec![&name]
+        } else {
+            ignored.push(key);
+            continue;
+        };
+
+        let new = if STRING_FIELDS.contains(&path.join(".").as_str()) {
+            Value::String(raw)
+        } else {
+            // Booleans, numbers and objects (e.g. custom networks) are written as JSON
+            serde_json::from_str(&raw).unwrap_or(Value::String(raw))
+        };
+        let Value::Object(fields) = value else {
+            unreachable!("parse_file only returns maps");
+        };
+        match path[..] {
+            [field] => {
+                fields.insert(field.to_owned(), new);
+            }
+            [table, field] => {
+                let table = fields
+                    .entry(table)
+                    .or_insert_with(|| Value::Object(Map::new()));
+                if !table.is_object() {
+                    *table = Value::Object(Map::new());
+                }
+                if let Value::Object(table) = table {
+                    table.insert(field.to_owned(), new);
+                }
+            }
+            _ => unreachable!("settings are at most two levels deep"),
+        }
+    }
+    ignored
+}
+
+/// A problem with the value of one configuration field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldError {
+    /// Path of the field, e.g. `proxy.addr`.
+    pub field: String,
+    /// What is wrong with the value.
+    pub reason: String,
+}
+
+impl FieldError {
+    fn new(field: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.reason)
+    }
+}
+
+fn join_field_errors(problems: &[FieldError]) -> String {
+    problems
+        .iter()
+        .map(FieldError::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
 }
 
 #[derive(Error, Debug)]
@@ -47,6 +475,26 @@ pub enum ConfigLoadError {
         #[source]
         serde_json::Error,
     ),
+
+    #[error("Error while deserializing TOML config: {0}")]
+    Toml(
+        #[from]
+        #[source]
+        toml::de::Error,
+    ),
+
+    #[error("Error while deserializing YAML config: {0}")]
+    Yaml(
+        #[from]
+        #[source]
+        serde_yaml::Error,
+    ),
+
+    #[error("Invalid destination address: {0}")]
+    InvalidAddress(String),
+
+    #[error("Invalid configuration: {}", join_field_errors(.0))]
+    Invalid(Vec<FieldError>),
 }
 
 #[cfg(test)]
@@ -57,4 +505,208 @@ mod test {
         let config = Config::load_config("config.json").unwrap();
         assert_eq!(config.network_type, MessageMagicNumber::Main);
     }
+
+    #[test]
+    fn test_default_port() {
+        let config = Config {
+            dest_addr: None,
+            network_type: MessageMagicNumber::Testnet,
+            proxy: None,
+            v2_transport: false,
+            capture_file: None,
+            recording_file: None,
+            output: OutputFormat::Text,
+            log_format: LogFormat::Human,
+            timeout: None,
+            user_agent: None,
+            announce_features: false,
+            relay: false,
+            peers: Vec::new(),
+            retry: RetryPolicy::default(),
+            ignored_vars: Vec::new(),
+        };
+        assert_eq!(
+            config.dest_socket_addr("127.0.0.1").unwrap(),
+            "127.0.0.1:18333".parse().unwrap()
+        );
+        assert_eq!(
+            config.dest_socket_addr("[::1]:1234").unwrap(),
+            "[::1]:1234".parse().unwrap()
+        );
+        assert_eq!(
+            config.dest_host_port("example.onion").unwrap(),
+            ("example.onion".to_owned(), 18333)
+        );
+        assert_eq!(
+            config.dest_host_port("example.onion:8333").unwrap(),
+            ("example.onion".to_owned(), 8333)
+        );
+        assert_eq!(
+            config.dest_host_port("[::1]").unwrap(),
+            ("::1".to_owned(), 18333)
+        );
+        assert_eq!(
+            config.dest_host_port("[::1]:8333").unwrap(),
+            ("::1".to_owned(), 8333)
+        );
+        assert!(config.dest_host_port("example.onion:port").is_err());
+    }
+
+    #[test]
+    fn test_proxy_defaults() {
+        let config: Config = serde_json::from_str(
+            r#"{"network_type": "main", "proxy": {"addr": "127.0.0.1:9050"}}"#,
+        )
+        .unwrap();
+        let proxy = config.proxy.unwrap();
+        assert!(proxy.remote_dns);
+        assert!(!proxy.isolate_streams);
+        assert_eq!(proxy.username, None);
+    }
+
+    #[test]
+    fn test_testnet4() {
+        let config: Config = serde_json::from_str(r#"{"network_type": "testnet4"}"#).unwrap();
+        assert_eq!(config.network_type, MessageMagicNumber::Testnet4);
+        assert_eq!(
+            config.dest_socket_addr("127.0.0.1").unwrap(),
+            "127.0.0.1:48333".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_custom_network() {
+        let config: Config = serde_json::from_str(
+            r#"{"network_type": {"custom": {"magic": "0a0b0c0d", "port": 19444}}}"#,
+        )
+        .unwrap();
+        let params = config.network_type.params();
+        assert_eq!(params.magic, [0x0A, 0x0B, 0x0C, 0x0D]);
+        assert_eq!(params.default_port, 19444);
+
+        let config: Config = serde_json::from_str(
+            r#"{"network_type": {"customsignet": {"challenge": "51", "port": 38334}}}"#,
+        )
+        .unwrap();
+        assert_eq!(config.network_type.params().default_port, 38334);
+        assert!(serde_json::from_str::<Config>(
+            r#"{"network_type": {"custom": {"magic": "0a0b0c", "port": 19444}}}"#
+        )
+        .is_err());
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_formats_by_extension() {
+        let toml = r#"
+            network_type = "signet"
+            timeout = 3
+
+            [proxy]
+            addr = "127.0.0.1:9050"
+        "#;
+        let config = Config::load_from(Some(("config.toml", toml)), []).unwrap();
+        assert_eq!(config.network_type, MessageMagicNumber::Signet);
+        assert_eq!(config.timeout, Some(3));
+        assert_eq!(config.proxy.unwrap().addr, "127.0.0.1:9050");
+
+        let yaml = "network_type:\n  custom:\n    magic: 0a0b0c0d\n    port: 19444\noutput: json\n";
+        let config = Config::load_from(Some(("config.yml", yaml)), []).unwrap();
+        assert_eq!(config.network_type.params().default_port, 19444);
+        assert_eq!(config.output, OutputFormat::Json);
+
+        // Every field has a default
+        let config = Config::load_from(Some(("empty.json", "{}")), []).unwrap();
+        assert_eq!(config.network_type, MessageMagicNumber::Main);
+        assert!(matches!(
+            Config::load_from(Some(("config.toml", "timeout = ")), []),
+            Err(ConfigLoadError::Toml(_))
+        ));
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let vars = env(&[
+            ("HANDSHAKER_NETWORK_TYPE", "regtest"),
+            ("HANDSHAKER_DEST_ADDR", "127.0.0.1"),
+            ("HANDSHAKER_TIMEOUT", "7"),
+            ("HANDSHAKER_USER_AGENT", "1234"),
+            ("HANDSHAKER_PROXY_ADDR", "127.0.0.1:9050"),
+            ("HANDSHAKER_PROXY_REMOTE_DNS", "false"),
+            ("HANDSHAKER_RETRY_RETRIES", "5"),
+            ("HANDSHAKER_PEERS", r#"["127.0.0.2", "127.0.0.3:18444"]"#),
+            ("PATH", "/usr/bin"),
+        ]);
+        let config =
+            Config::load_from(Some(("config.json", r#"{"network_type": "main"}"#)), vars).unwrap();
+        assert_eq!(config.network_type, MessageMagicNumber::Regtest);
+        assert_eq!(config.dest_addr.as_deref(), Some("127.0.0.1"));
+        assert_eq!(config.timeout, Some(7));
+        assert_eq!(config.user_agent.as_deref(), Some("1234"));
+        assert!(!config.proxy.unwrap().remote_dns);
+        assert_eq!(config.retry.retries, 5);
+        assert_eq!(config.peers, ["127.0.0.2", "127.0.0.3:18444"]);
+
+        // Unknown variables are ignored, bad values of known ones are not
+        let config = Config::load_from(None, env(&[("HANDSHAKER_TIMEOUTS", "7")])).unwrap();
+        assert_eq!(config.timeout, None);
+        assert_eq!(config.ignored_vars, ["HANDSHAKER_TIMEOUTS"]);
+        let Err(ConfigLoadError::Invalid(problems)) =
+            Config::load_from(None, env(&[("HANDSHAKER_TIMEOUT", "soon")]))
+        else {
+            panic!("string timeout accepted");
+        };
+        assert_eq!(problems[0].field, "timeout");
+    }
+
+    #[test]
+    fn test_validation_names_fields() {
+        let Err(ConfigLoadError::Invalid(problems)) = Config::load_from(
+            Some((
+                "config.json",
+                r#"{"proxy": {"addr": "127.0.0.1:9050", "port": 1}}"#,
+            )),
+            [],
+        ) else {
+            panic!("unknown field accepted");
+        };
+        assert_eq!(problems[0].field, "proxy.port");
+        assert!(problems[0].reason.contains("unknown field `port`"));
+
+        let Err(ConfigLoadError::Invalid(problems)) =
+            Config::load_from(Some(("config.json", r#"{"timeout": "soon"}"#)), [])
+        else {
+            panic!("string timeout accepted");
+        };
+        assert_eq!(problems[0].field, "timeout");
+
+        let json = r#"{
+            "dest_addr": "127.0.0.1:port",
+            "timeout": 0,
+            "proxy": {"addr": "localhost", "username": "alice"},
+            "capture_file": "out",
+            "recording_file": "out"
+        }"#;
+        let Err(ConfigLoadError::Invalid(problems)) =
+            Config::load_from(Some(("config.json", json)), [])
+        else {
+            panic!("invalid values accepted");
+        };
+        let fields: Vec<_> = problems.iter().map(|p| p.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            [
+                "dest_addr",
+                "proxy.addr",
+                "proxy.password",
+                "timeout",
+                "recording_file"
+            ]
+        );
+    }
 }
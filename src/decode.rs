@@ -0,0 +1,184 @@
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{
+    capture::{recording::Record, Direction},
+    error::Error,
+    message_reader::MessageReader,
+    messages::{
+        message::{command_name, MessageHeader, MessageMagicNumber},
+        version::PeerVersion,
+    },
+};
+
+/// A frame decoded for inspection.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct DecodedFrame {
+    /// Whether the frame was sent or received, if it comes from a recording.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub direction: Option<Direction>,
+    /// When the frame was sent or received, if it comes from a recording.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<DateTime<Utc>>,
+    /// The network of the magic, `None` if the magic is not of a known network.
+    pub network: Option<MessageMagicNumber>,
+    /// The command name, e.g. `version`.
+    pub command: String,
+    /// Length of the payload.
+    pub payload_len: u32,
+    /// Whether the checksum in the header matches the payload.
+    pub checksum_valid: bool,
+    /// The fields of a version message.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<PeerVersion>,
+}
+
+/// Decodes a single complete frame.
+///
+/// # Arguments
+///
+/// * `frame` - The frame, header included.
+pub fn decode_frame(frame: &[u8]) -> Result<DecodedFrame, Error> {
+    if frame.len() < MessageReader::HEADER_SIZE {
+        return Err(anyhow::anyhow!("frame shorter than a header: {} bytes", frame.len()).into());
+    }
+    let header: MessageHeader = frame.try_into()?;
+    let payload = &frame[MessageReader::HEADER_SIZE..];
+    if payload.len() != header.payload_len as usize {
+        return Err(anyhow::anyhow!(
+            "payload length is {} bytes, header says {}",
+            payload.len(),
+            header.payload_len
+        )
+        .into());
+    }
+    let command = command_name(&header.command);
+    let version = match command.as_str() {
+        "version" => Some(PeerVersion::try_from(payload)?),
+        _ => None,
+    };
+    Ok(DecodedFrame {
+        direction: None,
+        timestamp: None,
+        network: MessageMagicNumber::try_from(header.magin_network_nr).ok(),
+        command,
+        payload_len: header.payload_len,
+        checksum_valid: header.checksum_matches(payload),
+        version,
+    })
+}
+
+/// Decodes consecutive frames, e.g. a hex dump of the bytes received from a peer.
+///
+/// # Arguments
+///
+/// * `bytes` - The frames, back to back.
+pub fn decode_stream(mut bytes: &[u8]) -> Result<Vec<DecodedFrame>, Error> {
+    let mut frames = Vec::new();
+    while !bytes.is_empty() {
+        if bytes.len() < MessageReader::HEADER_SIZE {
+            return Err(anyhow::anyhow!("trailing {} bytes", bytes.len()).into());
+        }
+        let header: MessageHeader = bytes.try_into()?;
+        let len = MessageReader::HEADER_SIZE + header.payload_len as usize;
+        if bytes.len() < len {
+            return Err(
+                anyhow::anyhow!("truncated {} frame", command_name(&header.command)).into(),
+            );
+        }
+        frames.push(decode_frame(&bytes[..len])?);
+        bytes = &bytes[len..];
+    }
+    Ok(frames)
+}
+
+/// Decodes the frames of a recording, keeping their direction and timestamp.
+///
+/// # Arguments
+///
+/// * `records` - The records of a recording.
+pub fn decode_recording(records: &[Record]) -> Result<Vec<DecodedFrame>, Error> {
+    records
+        .iter()
+        .map(|record| {
+            Ok(DecodedFrame {
+                direction: Some(record.direction),
+                timestamp: Some(record.timestamp),
+                ..decode_frame(&record.frame)?
+            })
+        })
+        .collect()
+}
+
+impl fmt::Display for DecodedFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(timestamp) = self.timestamp {
+            write!(f, "{} ", timestamp.to_rfc3339())?;
+        }
+        match self.direction {
+            Some(Direction::Sent) => write!(f, "-> ")?,
+            Some(Direction::Received) => write!(f, "<- ")?,
+            None => {}
+        }
+        write!(f, "{} ({} bytes)", self.command, self.payload_len)?;
+        match &self.network {
+            Some(network) => write!(f, " on {network:?}")?,
+            None => write!(f, " on an unknown network")?,
+        }
+        if !self.checksum_valid {
+            write!(f, ", bad checksum")?;
+        }
+        if let Some(version) = &self.version {
+            write!(
+                f,
+                ", version {} from {} at height {}",
+                version.version, version.user_agent, version.start_height
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::messages::{
+        message::Message, verack::VerackMessageBuilder, version::VersionMessageBuilder,
+        ToNetworkMessage,
+    };
+
+    #[test]
+    fn test_decode_stream() {
+        let network = MessageMagicNumber::Testnet4;
+        let version = Message::Version(VersionMessageBuilder::new(
+            network.clone(),
+            "127.0.0.1:48333".parse().unwrap(),
+            0,
+            0,
+        ))
+        .to_network_message()
+        .unwrap();
+        let mut verack = Message::Verack(VerackMessageBuilder::new(network.clone()))
+            .to_network_message()
+            .unwrap();
+        // Corrupt the checksum
+        verack[20] ^= 0xFF;
+
+        let frames = decode_stream(&[version, verack].concat()).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].network, Some(network));
+        assert_eq!(frames[0].command, "version");
+        assert!(frames[0].checksum_valid);
+        assert_eq!(
+            frames[0].version.as_ref().unwrap().user_agent,
+            "emil-handshake"
+        );
+        assert_eq!(frames[1].command, "verack");
+        assert!(!frames[1].checksum_valid);
+        assert!(frames[1].to_string().ends_with("bad checksum"));
+
+        assert!(decode_stream(&[0xF9, 0xBE]).is_err());
+    }
+}
@@ -2,8 +2,15 @@ use std::{io, net::AddrParseError};
 
 use thiserror::Error;
 
-use crate::config::ConfigLoadError;
+use crate::{
+    bip324::transport::{OversizedPacket, MAX_CONTENTS_LEN},
+    config::ConfigLoadError,
+};
 
+/// Errors of the `handshaker`.
+///
+/// Failures talking to a peer carry the address of the peer. Each kind of error ends the
+/// process with its own exit code, see `exit_code`.
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("config load error: {0}")]
@@ -34,6 +41,59 @@ pub enum Error {
         Box<bincode::ErrorKind>,
     ),
 
+    #[error("invalid arguments: {0}")]
+    Cli(
+        #[from]
+        #[source]
+        clap::Error,
+    ),
+
+    #[error("{peer} refused the connection")]
+    ConnectRefused {
+        peer: String,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("timed out waiting for {peer}")]
+    Timeout { peer: String },
+
+    #[error("{peer} disconnected")]
+    PeerDisconnected { peer: String },
+
+    #[error("bad checksum in {command} message from {peer}")]
+    BadChecksum { peer: String, command: String },
+
+    #[error("{peer} is on a different network, magic: {magic:02x?}")]
+    WrongNetwork { peer: String, magic: [u8; 4] },
+
+    #[error("protocol violation by {peer}: {reason}")]
+    ProtocolViolation { peer: String, reason: String },
+
+    #[error("{command} message from {peer} is {size} bytes, more than the {max} allowed")]
+    OversizedMessage {
+        peer: String,
+        command: String,
+        size: u32,
+        max: u32,
+    },
+
+    #[error("connected to ourselves through {peer}")]
+    SelfConnection { peer: String },
+
+    #[error("{peer} rejected the transaction: {reason}")]
+    TransactionRejected { peer: String, reason: String },
+
+    #[error("{peer} did not serve block {hash}: {reason}")]
+    BlockNotFound {
+        peer: String,
+        hash: String,
+        reason: String,
+    },
+
+    #[error("{peer} does not offer {service}")]
+    MissingService { peer: String, service: &'static str },
+
     #[error("unexpected error: {0}")]
     Unexpected(
         #[source]
@@ -41,3 +101,129 @@ pub enum Error {
         anyhow::Error,
     ),
 }
+
+impl Error {
+    /// Classifies an IO error on the connection to `peer`, leaving unrelated errors as `Io`.
+    ///
+    /// # Arguments
+    ///
+    /// * `peer` - The address of the peer.
+    /// * `error` - The error returned while connecting, reading or writing.
+    pub fn io(peer: impl Into<String>, error: io::Error) -> Self {
+        let peer = peer.into();
+        if let Some(packet) = error
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<OversizedPacket>())
+        {
+            return Error::OversizedMessage {
+                peer,
+                command: "encrypted".to_owned(),
+                size: packet.size,
+                max: MAX_CONTENTS_LEN,
+            };
+        }
+        match error.kind() {
+            io::ErrorKind::ConnectionRefused => Error::ConnectRefused {
+                peer,
+                source: error,
+            },
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => Error::Timeout { peer },
+            io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe => Error::PeerDisconnected { peer },
+            _ => Error::Io(error),
+        }
+    }
+
+    /// Returns whether the failure may be transient, so trying the same peer again later can
+    /// succeed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            Error::ConnectRefused { .. } | Error::Timeout { .. } | Error::PeerDisconnected { .. }
+        )
+    }
+
+    /// Returns the exit code of the process when it fails with this error.
+    ///
+    /// | Code | Error |
+    /// |------|-------|
+    /// | 1 | IO and unexpected errors |
+    /// | 2 | Invalid arguments |
+    /// | 3 | Invalid configuration or address |
+    /// | 10 | Connection refused |
+    /// | 11 | Timeout |
+    /// | 12 | Peer disconnected |
+    /// | 13 | Bad checksum |
+    /// | 14 | Wrong network |
+    /// | 15 | Protocol violation, malformed messages included |
+    /// | 16 | Oversized message |
+    /// | 17 | Self-connection |
+    /// | 18 | Transaction rejected |
+    /// | 19 | Block not found |
+    /// | 20 | Service not offered by the peer |
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Io(_) | Error::Unexpected(_) => 1,
+            Error::Cli(_) => 2,
+            Error::ConfigLoad(_) | Error::AddressParse(_) => 3,
+            Error::ConnectRefused { .. } => 10,
+            Error::Timeout { .. } => 11,
+            Error::PeerDisconnected { .. } => 12,
+            Error::BadChecksum { .. } => 13,
+            Error::WrongNetwork { .. } => 14,
+            Error::ProtocolViolation { .. } | Error::ParseMessage(_) => 15,
+            Error::OversizedMessage { .. } => 16,
+            Error::SelfConnection { .. } => 17,
+            Error::TransactionRejected { .. } => 18,
+            Error::BlockNotFound { .. } => 19,
+            Error::MissingService { .. } => 20,
+        }
+    }
+
+    /// Returns a stable, machine-readable name of the kind of error.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::ConfigLoad(_) => "config_load",
+            Error::AddressParse(_) => "address_parse",
+            Error::Io(_) => "io",
+            Error::ParseMessage(_) => "parse_message",
+            Error::Cli(_) => "cli",
+            Error::ConnectRefused { .. } => "connect_refused",
+            Error::Timeout { .. } => "timeout",
+            Error::PeerDisconnected { .. } => "peer_disconnected",
+            Error::BadChecksum { .. } => "bad_checksum",
+            Error::WrongNetwork { .. } => "wrong_network",
+            Error::ProtocolViolation { .. } => "protocol_violation",
+            Error::OversizedMessage { .. } => "oversized_message",
+            Error::SelfConnection { .. } => "self_connection",
+            Error::TransactionRejected { .. } => "transaction_rejected",
+            Error::BlockNotFound { .. } => "block_not_found",
+            Error::MissingService { .. } => "missing_service",
+            Error::Unexpected(_) => "unexpected",
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_io_classification() {
+        let error = Error::io("127.0.0.1:8333", io::ErrorKind::ConnectionRefused.into());
+        assert_eq!(error.kind(), "connect_refused");
+        assert_eq!(error.exit_code(), 10);
+        assert_eq!(error.to_string(), "127.0.0.1:8333 refused the connection");
+
+        let error = Error::io("127.0.0.1:8333", io::ErrorKind::WouldBlock.into());
+        assert_eq!(error.exit_code(), 11);
+        let error = Error::io("127.0.0.1:8333", io::ErrorKind::UnexpectedEof.into());
+        assert_eq!(error.exit_code(), 12);
+        assert!(error.is_retryable());
+        let error = Error::io("127.0.0.1:8333", io::ErrorKind::PermissionDenied.into());
+        assert_eq!(error.kind(), "io");
+        assert!(!error.is_retryable());
+    }
+}
@@ -0,0 +1,314 @@
+use std::{
+    io::Write,
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    error::Error,
+    message_reader::MessageReader,
+    messages::{
+        feature::{
+            FeatureMessage, FeatureMessageBuilder, COMPACT_BLOCKS_VERSION, DEFAULT_FEE_FILTER,
+            FEATURES_PROTOCOL_VERSION,
+        },
+        message::{build_frame, Message, MessageCommand, MessageMagicNumber},
+        verack::VerackMessageBuilder,
+        version::VersionMessageBuilder,
+        ToNetworkMessage,
+    },
+};
+
+/// How a `FakeNode` answers the handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Behavior {
+    /// Answers with version and verack, then keeps the connection up.
+    Honest,
+    /// Like `Honest`, but waits before sending every message.
+    Slow(Duration),
+    /// Reads everything and never sends anything.
+    Silent,
+    /// Sends its verack before its version.
+    Misordered,
+    /// Sends its version with the magic of another network.
+    WrongMagic,
+    /// Sends its version with a corrupted checksum.
+    BadChecksum,
+    /// Negotiates features, but sends `wtxidrelay` after its verack.
+    LateWtxidRelay,
+}
+
+/// Answers a message received after the handshake with the `(command, payload)` of the
+/// messages to send back.
+pub type Responder = dyn Fn(&str, &[u8]) -> Vec<(String, Vec<u8>)> + Send + Sync;
+
+/// A scriptable Bitcoin node listening on localhost, for tests.
+pub struct FakeNode {
+    network: MessageMagicNumber,
+    behavior: Behavior,
+    user_agent: String,
+    services: u64,
+    features: bool,
+    version: Option<i32>,
+    responder: Option<Arc<Responder>>,
+    announcements: Vec<(String, Vec<u8>)>,
+}
+
+/// A running `FakeNode`. It keeps accepting connections until the process exits.
+pub struct FakeNodeHandle {
+    address: SocketAddr,
+    received: Arc<Mutex<Vec<String>>>,
+}
+
+impl FakeNode {
+    /// The user agent announced unless another one is set with `with_user_agent`.
+    pub const DEFAULT_USER_AGENT: &'static str = "/fake-node:0.1.0/";
+
+    /// Creates a new instance of `FakeNode` that answers the handshake honestly.
+    ///
+    /// # Arguments
+    ///
+    /// * `network` - The network the node is on.
+    pub fn new(network: MessageMagicNumber) -> Self {
+        Self {
+            network,
+            behavior: Behavior::Honest,
+            user_agent: Self::DEFAULT_USER_AGENT.to_owned(),
+            services: 0,
+            features: false,
+            version: None,
+            responder: None,
+            announcements: Vec::new(),
+        }
+    }
+
+    /// Sets how the node answers the handshake.
+    ///
+    /// # Arguments
+    ///
+    /// * `behavior` - The behavior of the node.
+    pub fn with_behavior(mut self, behavior: Behavior) -> Self {
+        self.behavior = behavior;
+        self
+    }
+
+    /// Sets the user agent announced in the node's version message.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_agent` - The user agent.
+    pub fn with_user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = user_agent.into();
+        self
+    }
+
+    /// Sets the services announced in the node's version message, none unless set.
+    ///
+    /// # Arguments
+    ///
+    /// * `services` - The service bits, e.g. `NODE_NETWORK | NODE_WITNESS`.
+    pub fn with_services(mut self, services: u64) -> Self {
+        self.services = services;
+        self
+    }
+
+    /// Sets the protocol version announced in the node's version message, 70001 unless set or
+    /// negotiating features.
+    ///
+    /// # Arguments
+    ///
+    /// * `version` - The protocol version, e.g. an obsolete one.
+    pub fn with_version(mut self, version: i32) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    /// Makes the node announce protocol version 70016 and negotiate features like Bitcoin Core:
+    /// `wtxidrelay` and `sendaddrv2` before its verack, `sendheaders`, `sendcmpct` and
+    /// `feefilter` after.
+    pub fn with_features(mut self) -> Self {
+        self.features = true;
+        self
+    }
+
+    /// Sets how the node answers the messages received after the handshake. Pings are always
+    /// answered with a pong.
+    ///
+    /// # Arguments
+    ///
+    /// * `responder` - Called with the command name and payload of every message received.
+    pub fn with_responder(
+        mut self,
+        responder: impl Fn(&str, &[u8]) -> Vec<(String, Vec<u8>)> + Send + Sync + 'static,
+    ) -> Self {
+        self.responder = Some(Arc::new(responder));
+        self
+    }
+
+    /// Sets messages sent to every peer right after the handshake, e.g. an `inv`.
+    ///
+    /// # Arguments
+    ///
+    /// * `announcements` - The `(command, payload)` of the messages, in order.
+    pub fn with_announcements(mut self, announcements: Vec<(String, Vec<u8>)>) -> Self {
+        self.announcements = announcements;
+        self
+    }
+
+    /// Starts listening on a free port of 127.0.0.1, serving every connection on its own thread.
+    pub fn spawn(self) -> Result<FakeNodeHandle, Error> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let node = Arc::new(self);
+        let handle = FakeNodeHandle {
+            address,
+            received: received.clone(),
+        };
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let node = node.clone();
+                let received = received.clone();
+                // The peer hanging up ends the connection, whatever the outcome
+                thread::spawn(move || node.serve(stream, &received));
+            }
+        });
+        Ok(handle)
+    }
+
+    fn serve(&self, stream: TcpStream, received: &Mutex<Vec<String>>) -> Result<(), Error> {
+        let peer = stream.peer_addr()?;
+        let mut writer = stream.try_clone()?;
+        let mut reader = MessageReader::new(Box::new(stream), &self.network.params());
+        let record = |reader: &MessageReader| {
+            if let Ok(mut received) = received.lock() {
+                received.push(reader.command_name());
+            }
+        };
+
+        // Wait for the version of the peer
+        while reader.read_message()? != Some(MessageCommand::Version) {
+            record(&reader);
+        }
+        record(&reader);
+
+        let version = VersionMessageBuilder::new(
+            self.network.clone(),
+            peer,
+            chrono::offset::Utc::now().timestamp(),
+            rand::random(),
+        )
+        .with_user_agent(self.user_agent.clone())
+        .with_services(self.services);
+        let features = self.features || self.behavior == Behavior::LateWtxidRelay;
+        let version = match (self.version, features) {
+            (Some(announced), _) => version.with_version(announced),
+            (None, true) => version.with_version(FEATURES_PROTOCOL_VERSION),
+            (None, false) => version,
+        };
+        let mut version = Message::Version(version).to_network_message()?;
+        let verack = Message::Verack(VerackMessageBuilder::new(self.network.clone()))
+            .to_network_message()?;
+        let feature =
+            |message| FeatureMessageBuilder::new(self.network.clone(), message).to_frame();
+        let (before_verack, after_verack) = if features {
+            let after_verack = vec![
+                feature(FeatureMessage::SendHeaders)?,
+                feature(FeatureMessage::SendCmpct {
+                    high_bandwidth: false,
+                    version: COMPACT_BLOCKS_VERSION,
+                })?,
+                feature(FeatureMessage::FeeFilter {
+                    fee_rate: DEFAULT_FEE_FILTER,
+                })?,
+            ];
+            let wtxid_relay = feature(FeatureMessage::WtxidRelay)?;
+            let send_addr_v2 = feature(FeatureMessage::SendAddrV2)?;
+            if self.behavior == Behavior::LateWtxidRelay {
+                (
+                    vec![send_addr_v2],
+                    [after_verack, vec![wtxid_relay]].concat(),
+                )
+            } else {
+                (vec![wtxid_relay, send_addr_v2], after_verack)
+            }
+        } else {
+            (vec![], vec![])
+        };
+        // Everything sent after the version
+        let rest = [before_verack, vec![verack], after_verack].concat();
+        let frames = match self.behavior {
+            Behavior::Silent => vec![],
+            Behavior::Misordered => [rest, vec![version]].concat(),
+            Behavior::WrongMagic => {
+                let other = if self.network == MessageMagicNumber::Main {
+                    MessageMagicNumber::Testnet
+                } else {
+                    MessageMagicNumber::Main
+                };
+                version[..4].copy_from_slice(&other.params().magic);
+                [vec![version], rest].concat()
+            }
+            Behavior::BadChecksum => {
+                version[20] ^= 0xFF;
+                [vec![version], rest].concat()
+            }
+            Behavior::Honest | Behavior::Slow(_) | Behavior::LateWtxidRelay => {
+                [vec![version], rest].concat()
+            }
+        };
+        for frame in frames {
+            self.send(&mut writer, &frame)?;
+        }
+        if self.behavior != Behavior::Silent {
+            for (command, payload) in &self.announcements {
+                self.send(&mut writer, &build_frame(&self.network, command, payload)?)?;
+            }
+        }
+
+        loop {
+            reader.read_message()?;
+            record(&reader);
+            if self.behavior == Behavior::Silent {
+                continue;
+            }
+            let command = reader.command_name();
+            let replies = match (command.as_str(), &self.responder) {
+                ("ping", _) => vec![("pong".to_owned(), reader.payload().to_vec())],
+                (_, Some(responder)) => responder(&command, reader.payload()),
+                (_, None) => vec![],
+            };
+            for (command, payload) in replies {
+                self.send(
+                    &mut writer,
+                    &build_frame(&self.network, &command, &payload)?,
+                )?;
+            }
+        }
+    }
+
+    fn send(&self, writer: &mut TcpStream, frame: &[u8]) -> Result<(), Error> {
+        if let Behavior::Slow(delay) = self.behavior {
+            thread::sleep(delay);
+        }
+        writer.write_all(frame)?;
+        Ok(())
+    }
+}
+
+impl FakeNodeHandle {
+    /// Returns the address the node listens on.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Returns the command names of the messages received so far, over every connection.
+    pub fn received(&self) -> Vec<String> {
+        self.received
+            .lock()
+            .map(|received| received.clone())
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,224 @@
+use std::fmt;
+
+use serde::Serialize;
+use tracing::{debug, info};
+
+use crate::{
+    error::Error,
+    handshake::Connection,
+    messages::{
+        bloom::{filteradd_payload, BloomFilter},
+        inventory::{inventory_payload, parse_inventory, Inventory, MSG_FILTERED_BLOCK},
+        merkle_block::MerkleBlock,
+        message::hash_to_hex,
+        transaction::Transaction,
+        version::NODE_BLOOM,
+    },
+    pow::check_proof_of_work,
+};
+
+/// The false positive rate of the filters `handshaker merkleblock` loads.
+pub const DEFAULT_FP_RATE: f64 = 0.0001;
+
+/// A transaction of a filtered block matching the bloom filter.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct MatchedTransaction {
+    /// Position of the transaction in the block, 0 for the coinbase.
+    pub index: u32,
+    /// The txid, as displayed by block explorers.
+    pub txid: String,
+    /// Whether the peer sent the transaction after the `merkleblock`.
+    pub received: bool,
+}
+
+/// The outcome of a filtered block download, printed by `handshaker merkleblock`.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct FilteredBlockReport {
+    /// Address of the peer.
+    pub peer: String,
+    /// Hash of the block, as displayed by block explorers.
+    pub hash: String,
+    /// Number of transactions in the block.
+    pub total_transactions: u32,
+    /// The transactions matching the filter, false positives included, their merkle branches
+    /// checked against the header.
+    pub matched: Vec<MatchedTransaction>,
+}
+
+impl fmt::Display for FilteredBlockReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let received = self.matched.iter().filter(|tx| tx.received).count();
+        write!(
+            f,
+            "{}: block {}, {} of {} transactions matched, {} received",
+            self.peer,
+            self.hash,
+            self.matched.len(),
+            self.total_transactions,
+            received
+        )?;
+        for tx in &self.matched {
+            write!(f, "\n  {} {}", tx.index, tx.txid)?;
+        }
+        Ok(())
+    }
+}
+
+/// Loads `filter` into the peer with `filterload` (BIP37). From then on the peer only relays
+/// the transactions matching it, and answers requests of filtered blocks.
+///
+/// # Arguments
+///
+/// * `connection` - A connection whose handshake completed.
+/// * `services` - The services the peer announced in its version message.
+/// * `filter` - The filter to load.
+pub fn load_filter(
+    connection: &mut Connection,
+    services: u64,
+    filter: &BloomFilter,
+) -> Result<(), Error> {
+    // Bitcoin Core disconnects peers sending filterload without offering NODE_BLOOM
+    if services & NODE_BLOOM == 0 {
+        return Err(Error::MissingService {
+            peer: connection.reader.peer().to_owned(),
+            service: "NODE_BLOOM",
+        });
+    }
+    connection.send_raw("filterload", &filter.payload())?;
+    info!(
+        size = filter.data.len(),
+        hash_funcs = filter.hash_funcs,
+        "filter loaded"
+    );
+    Ok(())
+}
+
+/// Adds `element` to the filter loaded into the peer with `filteradd`.
+///
+/// # Arguments
+///
+/// * `connection` - A connection whose peer has a filter loaded.
+/// * `element` - The element, at most 520 bytes.
+pub fn add_to_filter(connection: &mut Connection, element: &[u8]) -> Result<(), Error> {
+    connection.send_raw("filteradd", &filteradd_payload(element)?)?;
+    debug!(element = %hex::encode(element), "element added to the filter");
+    Ok(())
+}
+
+/// Removes the filter loaded into the peer with `filterclear`. The peer then relays every
+/// transaction and no longer serves filtered blocks.
+///
+/// # Arguments
+///
+/// * `connection` - A connection whose peer has a filter loaded.
+pub fn clear_filter(connection: &mut Connection) -> Result<(), Error> {
+    connection.send_raw("filterclear", &[])?;
+    info!("filter cleared");
+    Ok(())
+}
+
+/// Downloads the block `hash` as a `merkleblock` (BIP37), filtered by the filter loaded into
+/// the peer, and the matching transactions the peer sends after it. The proof of work of the
+/// header and the merkle branches of the matches are checked.
+///
+/// A ping follows the `getdata`, as the peer answers `getdata` before the messages after it:
+/// a pong before the `merkleblock` means the peer does not have the block, or has no filter
+/// loaded.
+///
+/// # Arguments
+///
+/// * `connection` - A connection whose peer has a filter loaded, see `load_filter`.
+/// * `hash` - The hash of the block, in internal byte order.
+pub fn download_filtered_block(
+    connection: &mut Connection,
+    hash: [u8; 32],
+) -> Result<FilteredBlockReport, Error> {
+    let peer = connection.reader.peer().to_owned();
+    let violation = |reason: String| Error::ProtocolViolation {
+        peer: peer.clone(),
+        reason,
+    };
+    let not_found = |reason: &str| Error::BlockNotFound {
+        peer: peer.clone(),
+        hash: hash_to_hex(&hash),
+        reason: reason.to_owned(),
+    };
+    let getdata = inventory_payload(&[Inventory::new(MSG_FILTERED_BLOCK, hash)]);
+    connection.send_raw("getdata", &getdata)?;
+    let nonce: u64 = rand::random();
+    connection.send_raw("ping", &nonce.to_le_bytes())?;
+    info!(hash = %hash_to_hex(&hash), "filtered block requested");
+
+    let mut report: Option<FilteredBlockReport> = None;
+    let mut matched_txids = Vec::new();
+    loop {
+        connection.reader.read_message()?;
+        let payload = connection.reader.payload();
+        match connection.reader.command_name().as_str() {
+            "merkleblock" => {
+                let merkle_block = MerkleBlock::try_from(payload)
+                    .map_err(|e| violation(format!("malformed merkleblock message: {e}")))?;
+                if merkle_block.header.hash() != hash {
+                    debug!("unrequested merkleblock ignored");
+                    continue;
+                }
+                if !check_proof_of_work(
+                    hash,
+                    merkle_block.header.bits,
+                    &connection.network.params(),
+                ) {
+                    return Err(violation(format!(
+                        "block {} does not meet its proof of work target",
+                        hash_to_hex(&hash)
+                    )));
+                }
+                let matches = merkle_block.matched_txids().map_err(|e| {
+                    violation(format!(
+                        "invalid merkleblock of {}: {e}",
+                        hash_to_hex(&hash)
+                    ))
+                })?;
+                info!(matched = matches.len(), "merkle branches verified");
+                matched_txids = matches.iter().map(|(_, txid)| *txid).collect();
+                report = Some(FilteredBlockReport {
+                    peer: peer.clone(),
+                    hash: hash_to_hex(&hash),
+                    total_transactions: merkle_block.tree.total_transactions,
+                    matched: matches
+                        .into_iter()
+                        .map(|(index, txid)| MatchedTransaction {
+                            index,
+                            txid: hash_to_hex(&txid),
+                            received: false,
+                        })
+                        .collect(),
+                });
+            }
+            "tx" => {
+                let transaction = Transaction::try_from(payload)
+                    .map_err(|e| violation(format!("malformed tx message: {e}")))?;
+                let position = matched_txids
+                    .iter()
+                    .position(|txid| *txid == transaction.txid());
+                match (&mut report, position) {
+                    (Some(report), Some(position)) => report.matched[position].received = true,
+                    _ => debug!("transaction outside the filtered block ignored"),
+                }
+            }
+            "notfound" => {
+                let items = parse_inventory(payload).unwrap_or_default();
+                if items.iter().any(|item| item.hash == hash) {
+                    return Err(not_found("notfound"));
+                }
+            }
+            "ping" => {
+                let nonce = payload.to_vec();
+                connection.send_raw("pong", &nonce)?;
+            }
+            "pong" if payload == nonce.to_le_bytes() => {
+                return report.ok_or_else(|| not_found("the peer does not have it"));
+            }
+            command => debug!(command, "ignored"),
+        }
+    }
+}
@@ -0,0 +1,726 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Cursor, Read, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, ToSocketAddrs},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use rand::Rng;
+use tracing::{info, info_span};
+
+use crate::{
+    bip324,
+    capture::{
+        pcapng::PcapngWriter, recording::RecordingWriter, Direction, FrameSink, SharedSink, Tap,
+    },
+    config::ProxyConfig,
+    error::Error,
+    message_reader::MessageReader,
+    messages::{
+        feature::{
+            FeatureMessage, FeatureMessageBuilder, PeerFeatures, COMPACT_BLOCKS_VERSION,
+            DEFAULT_FEE_FILTER, FEATURES_PROTOCOL_VERSION,
+        },
+        message::{
+            build_frame, command_name, Message, MessageCommand, MessageHeader, MessageMagicNumber,
+        },
+        verack::VerackMessageBuilder,
+        version::{PeerVersion, VersionMessageBuilder, PROTOCOL_VERSION},
+        ToNetworkMessage,
+    },
+    report::{HandshakeReport, Step},
+    socks5,
+};
+
+/// How connections to peers are made and what we announce on them.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectOptions {
+    /// Optional SOCKS5 proxy to connect through.
+    pub proxy: Option<ProxyConfig>,
+    /// Optional timeout applied to connecting and to every read. `None` waits forever.
+    pub timeout: Option<Duration>,
+    /// Whether to try the BIP324 v2 transport first, falling back to v1 if the peer rejects it.
+    pub v2_transport: bool,
+    /// Optional pcapng file recording the frames exchanged with the peer.
+    pub capture: Option<PathBuf>,
+    /// Optional file recording the frames in the native format, replayable offline.
+    pub recording: Option<PathBuf>,
+    /// User agent announced in our version message, the default one if `None`.
+    pub user_agent: Option<String>,
+    /// Whether to send `wtxidrelay`, `sendaddrv2`, `sendheaders`, `sendcmpct` and `feefilter`.
+    pub announce_features: bool,
+    /// Whether to ask the peer to announce its transactions with the relay flag of our version.
+    pub relay: bool,
+}
+
+/// The first protocol version answering pings with pongs (BIP31).
+const BIP31_VERSION: i32 = 60001;
+
+/// The first protocol version understanding `sendheaders` (BIP130).
+const SEND_HEADERS_VERSION: i32 = 70012;
+
+/// The first protocol version understanding `feefilter` (BIP133).
+const FEE_FILTER_VERSION: i32 = 70013;
+
+/// The first protocol version understanding `sendcmpct` (BIP152).
+const COMPACT_BLOCKS_PROTOCOL_VERSION: i32 = 70014;
+
+/// The reading and the writing half of a connection, carrying v1 frames.
+type Transport = (Box<dyn Read>, Box<dyn Write>);
+
+/// Opens a TCP connection to `host:port`, through the SOCKS5 proxy if one is given.
+///
+/// # Arguments
+///
+/// * `host` - The host to connect to: an IP address or a host name.
+/// * `port` - The port to connect to.
+/// * `options` - Proxy and timeout to use.
+pub fn connect(host: &str, port: u16, options: &ConnectOptions) -> Result<TcpStream, Error> {
+    let stream = match &options.proxy {
+        Some(proxy) => socks5::connect(proxy, host, port, options.timeout)
+            .map_err(|e| Error::io(format!("{host}:{port}"), e))?,
+        None => {
+            let dest_address = (host, port)
+                .to_socket_addrs()?
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("{host} did not resolve to any address"))?;
+            match options.timeout {
+                Some(timeout) => TcpStream::connect_timeout(&dest_address, timeout),
+                None => TcpStream::connect(dest_address),
+            }
+            .map_err(|e| Error::io(format!("{host}:{port}"), e))?
+        }
+    };
+    stream.set_read_timeout(options.timeout)?;
+    Ok(stream)
+}
+
+/// An established connection to a peer whose handshake completed.
+pub struct Connection {
+    /// Reads the messages sent by the peer.
+    pub reader: MessageReader,
+    /// Sink for the v1 frames sent to the peer.
+    pub writer: Box<dyn Write>,
+    /// The network of the peer.
+    pub network: MessageMagicNumber,
+    /// The features the peer asked for during the handshake.
+    pub features: PeerFeatures,
+}
+
+impl Connection {
+    /// Sends `message` to the peer.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The message to send.
+    pub fn send(&mut self, message: Message) -> Result<(), Error> {
+        send_frame(
+            &mut self.writer,
+            self.reader.peer(),
+            &message.to_network_message()?,
+        )
+    }
+
+    /// Sends a message with any command to the peer, e.g. one without a builder of its own.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The command name, e.g. `pong`.
+    /// * `payload` - The serialized payload.
+    pub fn send_raw(&mut self, command: &str, payload: &[u8]) -> Result<(), Error> {
+        send_frame(
+            &mut self.writer,
+            self.reader.peer(),
+            &build_frame(&self.network, command, payload)?,
+        )
+    }
+}
+
+/// Performs the version/verack handshake with the node at `host:port`.
+///
+/// # Arguments
+///
+/// * `network` - The Bitcoin network the node is expected to be on.
+/// * `host` - The host of the node: an IP address, a host name or, with a proxy, a `.onion` address.
+/// * `port` - The port of the node.
+/// * `options` - How to connect to the node.
+/// * `report` - Collects what the node announced and when each step happened.
+pub fn handshake(
+    network: &MessageMagicNumber,
+    host: &str,
+    port: u16,
+    options: &ConnectOptions,
+    report: &mut HandshakeReport,
+) -> Result<Connection, Error> {
+    // Addresses that are not IPs (e.g. onion services) can't be put in the version message,
+    // errors and reports name the host as configured instead
+    let ip = host.parse().unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    let dest_address = SocketAddr::new(ip, port);
+    let label = format!("{host}:{port}");
+    let _span = info_span!("connection", peer = %label, network = ?network).entered();
+
+    let mut stream = connect(host, port, options)?;
+    report.step(Step::Connected);
+    info!("connected");
+    let mut v2 = None;
+    if options.v2_transport {
+        info!("trying v2 transport");
+        let magic = network.params().magic;
+        let transport =
+            bip324::transport::initiate(stream.try_clone()?, stream.try_clone()?, magic)
+                .map_err(|e| Error::io(label.clone(), e))?;
+        match transport {
+            Some(transport) => {
+                info!("using v2 transport");
+                v2 = Some(transport);
+            }
+            None => {
+                info!("peer rejected v2 transport, falling back to v1");
+                stream = connect(host, port, options)?;
+            }
+        }
+    }
+
+    let (reader, writer): Transport = match v2 {
+        Some((reader, writer)) => (Box::new(reader), Box::new(writer)),
+        None => (Box::new(stream.try_clone()?), Box::new(stream.try_clone()?)),
+    };
+    // Behind a proxy the peer's IP may be unknown, the proxy's address stands in for it
+    let peer = if ip.is_unspecified() {
+        stream.peer_addr()?
+    } else {
+        dest_address
+    };
+    let (reader, writer) = tap(reader, writer, options, stream.local_addr()?, peer)?;
+    let reader = MessageReader::new(reader, &network.params()).with_peer(label);
+    exchange(
+        reader,
+        writer,
+        network,
+        dest_address,
+        options,
+        report,
+        false,
+    )
+}
+
+/// Answers the handshake of a peer that connected to us.
+///
+/// With the v2 transport enabled, peers whose first 16 bytes are not those of a v1 `version`
+/// message are assumed to start a BIP324 key exchange.
+///
+/// # Arguments
+///
+/// * `stream` - The accepted connection.
+/// * `network` - The Bitcoin network the peer is expected to be on.
+/// * `options` - Timeout, transport, recording and what to announce.
+/// * `report` - Collects what the peer announced and when each step happened.
+pub fn accept(
+    stream: TcpStream,
+    network: &MessageMagicNumber,
+    options: &ConnectOptions,
+    report: &mut HandshakeReport,
+) -> Result<Connection, Error> {
+    let peer = stream.peer_addr()?;
+    let _span = info_span!("connection", %peer, network = ?network, inbound = true).entered();
+    stream.set_read_timeout(options.timeout)?;
+    report.step(Step::Connected);
+    info!("accepted");
+
+    let magic = network.params().magic;
+    let mut input = stream.try_clone()?;
+    let (first, v2) = if options.v2_transport {
+        detect_v2(&mut input, magic).map_err(|e| Error::io(peer.to_string(), e))?
+    } else {
+        (Vec::new(), false)
+    };
+    // The bytes read to tell the transports apart are read again by the transport
+    let input = Cursor::new(first).chain(input);
+    let (reader, writer): Transport = if v2 {
+        info!("using v2 transport");
+        let (reader, writer) = bip324::transport::respond(input, stream.try_clone()?, magic)
+            .map_err(|e| Error::io(peer.to_string(), e))?;
+        (Box::new(reader), Box::new(writer))
+    } else {
+        (Box::new(input), Box::new(stream.try_clone()?))
+    };
+    let (reader, writer) = tap(reader, writer, options, stream.local_addr()?, peer)?;
+    let reader = MessageReader::new(reader, &network.params()).with_peer(peer.to_string());
+    exchange(reader, writer, network, peer, options, report, true)
+}
+
+/// Reads the first bytes sent by a peer until they show whether it speaks v1, starting with
+/// the 16 bytes of a `version` message header (magic and command), or starts a v2 key exchange.
+/// Returns the bytes read and whether the peer uses v2.
+///
+/// # Arguments
+///
+/// * `reader` - The reading half of the connection, whose read timeout bounds the wait.
+/// * `magic` - Magic bytes of the network.
+fn detect_v2(reader: &mut impl Read, magic: [u8; 4]) -> io::Result<(Vec<u8>, bool)> {
+    let mut v1_prefix = [0u8; 16];
+    v1_prefix[..4].copy_from_slice(&magic);
+    v1_prefix[4..11].copy_from_slice(b"version");
+    let mut first = [0u8; 16];
+    let mut len = 0;
+    while len < first.len() {
+        let read = reader.read(&mut first[len..])?;
+        if read == 0 {
+            // The peer hung up, the v1 handshake reports it
+            break;
+        }
+        len += read;
+        if first[..len] != v1_prefix[..len] {
+            return Ok((first[..len].to_vec(), true));
+        }
+    }
+    Ok((first[..len].to_vec(), false))
+}
+
+/// Records the frames passing through `reader` and `writer` if `options` ask for it.
+fn tap(
+    reader: Box<dyn Read>,
+    writer: Box<dyn Write>,
+    options: &ConnectOptions,
+    local: SocketAddr,
+    peer: SocketAddr,
+) -> Result<Transport, Error> {
+    if options.capture.is_none() && options.recording.is_none() {
+        return Ok((reader, writer));
+    }
+    let sink = start_recording(options, local, peer)?;
+    Ok((
+        Box::new(Tap::new(reader, Direction::Received, sink.clone())),
+        Box::new(Tap::new(writer, Direction::Sent, sink)),
+    ))
+}
+
+/// Creates the capture and recording files requested in `options` for the connection
+/// between `local` and `peer`.
+fn start_recording(
+    options: &ConnectOptions,
+    local: SocketAddr,
+    peer: SocketAddr,
+) -> Result<SharedSink, Error> {
+    let mut sinks: Vec<Box<dyn FrameSink>> = Vec::new();
+    if let Some(path) = &options.capture {
+        info!(path = %path.display(), "capturing frames");
+        let file = BufWriter::new(File::create(path)?);
+        sinks.push(Box::new(PcapngWriter::new(file, local, peer)?));
+    }
+    if let Some(path) = &options.recording {
+        info!(path = %path.display(), "recording frames");
+        let file = BufWriter::new(File::create(path)?);
+        sinks.push(Box::new(RecordingWriter::new(file)?));
+    }
+    Ok(Arc::new(Mutex::new(sinks)))
+}
+
+/// Performs the version/verack handshake over an established transport.
+///
+/// # Arguments
+///
+/// * `reader` - Source of the v1 frames sent by the node.
+/// * `writer` - Sink for the v1 frames sent to the node.
+/// * `network` - The Bitcoin network the node is expected to be on.
+/// * `dest_address` - The address of the node, as announced in the version message.
+/// * `options` - What to announce in the version message.
+/// * `report` - Collects what the node announced and when each step happened.
+pub fn handshake_transport(
+    reader: Box<dyn Read>,
+    writer: Box<dyn Write>,
+    network: &MessageMagicNumber,
+    dest_address: SocketAddr,
+    options: &ConnectOptions,
+    report: &mut HandshakeReport,
+) -> Result<Connection, Error> {
+    let reader = MessageReader::new(reader, &network.params()).with_peer(dest_address.to_string());
+    exchange(
+        reader,
+        writer,
+        network,
+        dest_address,
+        options,
+        report,
+        false,
+    )
+}
+
+/// Exchanges version and verack messages. The initiator of the connection sends its version
+/// first, the other side only once it received the initiator's.
+///
+/// With `announce_features`, our feature negotiation messages are sent around our verack.
+/// Those peers send right after their verack are then collected by sending a ping and reading
+/// up to its pong, as peers answer messages in order.
+///
+/// Errors name the peer of `reader`, `dest_address` only goes in our version message.
+fn exchange(
+    mut reader: MessageReader,
+    mut writer: Box<dyn Write>,
+    network: &MessageMagicNumber,
+    dest_address: SocketAddr,
+    options: &ConnectOptions,
+    report: &mut HandshakeReport,
+    inbound: bool,
+) -> Result<Connection, Error> {
+    let mut rng = rand::thread_rng();
+    let nonce: u64 = rng.gen();
+    let our_version = if options.announce_features {
+        FEATURES_PROTOCOL_VERSION
+    } else {
+        PROTOCOL_VERSION
+    };
+
+    let mut version = Some(Message::Version(
+        VersionMessageBuilder::new(
+            network.clone(),
+            dest_address,
+            chrono::offset::Utc::now().timestamp(),
+            nonce,
+        )
+        .with_version(our_version)
+        .with_relay(options.relay)
+        .with_user_agent(
+            options
+                .user_agent
+                .as_deref()
+                .unwrap_or(VersionMessageBuilder::DEFAULT_USER_AGENT),
+        ),
+    ));
+
+    let peer = reader.peer().to_owned();
+    if !inbound {
+        if let Some(version) = version.take() {
+            send_frame(&mut writer, &peer, &version.to_network_message()?)?;
+            report.step(Step::VersionSent);
+        }
+    }
+
+    let violation = |reason: &str| Error::ProtocolViolation {
+        peer: peer.clone(),
+        reason: reason.to_owned(),
+    };
+    let mut features = PeerFeatures::default();
+    // The lower of our protocol version and the peer's, once its version is received
+    let mut common_version = None;
+    loop {
+        let command = if let Some(command) = reader.read_message()? {
+            command
+        } else {
+            continue;
+        };
+        match command {
+            MessageCommand::Version => {
+                if common_version.is_some() {
+                    return Err(violation("duplicate version message"));
+                }
+                report.step(Step::VersionReceived);
+                let peer_version = PeerVersion::try_from(reader.payload())
+                    .map_err(|e| violation(&format!("malformed version message: {e}")))?;
+                if peer_version.nonce == nonce {
+                    return Err(Error::SelfConnection { peer });
+                }
+                let min_version = network.params().min_protocol_version;
+                if peer_version.version < min_version {
+                    return Err(violation(&format!(
+                        "protocol version {} is below the minimum {min_version}",
+                        peer_version.version
+                    )));
+                }
+                common_version = Some(peer_version.version.min(our_version));
+                report.peer_version(peer_version, our_version);
+                if let Some(version) = version.take() {
+                    send_frame(&mut writer, &peer, &version.to_network_message()?)?;
+                    report.step(Step::VersionSent);
+                }
+                if options.announce_features && common_version >= Some(FEATURES_PROTOCOL_VERSION) {
+                    send_feature(&mut writer, network, &peer, FeatureMessage::WtxidRelay)?;
+                    // Addresses are only relayed as addrv2 on networks that know BIP155
+                    if network.params().supports_bip155 {
+                        send_feature(&mut writer, network, &peer, FeatureMessage::SendAddrV2)?;
+                    }
+                }
+                let verack_message = Message::Verack(VerackMessageBuilder::new(network.clone()));
+                send_frame(&mut writer, &peer, &verack_message.to_network_message()?)?;
+                report.step(Step::VerackSent);
+            }
+            MessageCommand::Verack => {
+                if common_version.is_none() {
+                    return Err(violation("verack before version"));
+                }
+                report.step(Step::VerackReceived);
+                info!(%peer, "handshake completed");
+                break;
+            }
+            _ => {
+                let name = reader.command_name();
+                if common_version.is_none() {
+                    return Err(violation(&format!("{name} before version")));
+                }
+                if let Some(message) = FeatureMessage::parse(&name, reader.payload())
+                    .map_err(|e| violation(&e.to_string()))?
+                {
+                    features.record(message);
+                }
+            }
+        }
+    }
+
+    let common_version = common_version.unwrap_or(PROTOCOL_VERSION);
+    if options.announce_features {
+        let announced = [
+            (SEND_HEADERS_VERSION, FeatureMessage::SendHeaders),
+            (
+                COMPACT_BLOCKS_PROTOCOL_VERSION,
+                FeatureMessage::SendCmpct {
+                    high_bandwidth: false,
+                    version: COMPACT_BLOCKS_VERSION,
+                },
+            ),
+            (
+                FEE_FILTER_VERSION,
+                FeatureMessage::FeeFilter {
+                    fee_rate: DEFAULT_FEE_FILTER,
+                },
+            ),
+        ];
+        for (version, message) in announced {
+            if common_version >= version {
+                send_feature(&mut writer, network, &peer, message)?;
+            }
+        }
+        if common_version >= BIP31_VERSION {
+            let ping: u64 = rng.gen();
+            let frame = build_frame(network, "ping", &ping.to_le_bytes())?;
+            send_frame(&mut writer, &peer, &frame)?;
+            loop {
+                reader.read_message()?;
+                let name = reader.command_name();
+                match name.as_str() {
+                    "pong" if reader.payload() == ping.to_le_bytes() => break,
+                    "ping" => {
+                        let frame = build_frame(network, "pong", reader.payload())?;
+                        send_frame(&mut writer, &peer, &frame)?;
+                    }
+                    _ => {
+                        let message = FeatureMessage::parse(&name, reader.payload())
+                            .map_err(|e| violation(&e.to_string()))?;
+                        match message {
+                            Some(message) if message.before_verack() => {
+                                return Err(violation(&format!("{name} after verack")))
+                            }
+                            Some(message) => features.record(message),
+                            None => {}
+                        }
+                    }
+                }
+            }
+        }
+    }
+    report.features = features.clone();
+
+    Ok(Connection {
+        reader,
+        writer,
+        network: network.clone(),
+        features,
+    })
+}
+
+/// Sends a feature negotiation message to the peer.
+fn send_feature(
+    writer: &mut dyn Write,
+    network: &MessageMagicNumber,
+    peer: &str,
+    message: FeatureMessage,
+) -> Result<(), Error> {
+    let frame = FeatureMessageBuilder::new(network.clone(), message).to_frame()?;
+    send_frame(writer, peer, &frame)
+}
+
+/// Sends `frame` to the peer within a span describing it.
+///
+/// # Arguments
+///
+/// * `writer` - Sink for the v1 frames sent to the node.
+/// * `peer` - The address of the node, reported in errors.
+/// * `frame` - The frame to send.
+fn send_frame(writer: &mut dyn Write, peer: &str, frame: &[u8]) -> Result<(), Error> {
+    let header: MessageHeader = frame.try_into()?;
+    let _span = info_span!(
+        "message",
+        command = %command_name(&header.command),
+        size = header.payload_len,
+        direction = "sent"
+    )
+    .entered();
+    writer.write_all(frame).map_err(|e| Error::io(peer, e))?;
+    info!("message sent");
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io, net::TcpListener, thread};
+
+    use super::*;
+
+    #[test]
+    fn test_self_connection_detected() {
+        // A peer echoing everything sends our own version back
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let _ = io::copy(&mut stream.try_clone().unwrap(), &mut &stream);
+        });
+
+        let options = ConnectOptions {
+            timeout: Some(Duration::from_secs(5)),
+            ..ConnectOptions::default()
+        };
+        let mut report = HandshakeReport::new(address.to_string(), MessageMagicNumber::Regtest);
+        let error = handshake(
+            &MessageMagicNumber::Regtest,
+            "127.0.0.1",
+            address.port(),
+            &options,
+            &mut report,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(error.kind(), "self_connection");
+        assert_eq!(error.exit_code(), 17);
+    }
+
+    #[test]
+    fn test_connect_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let error = connect("127.0.0.1", port, &ConnectOptions::default()).unwrap_err();
+        assert_eq!(error.kind(), "connect_refused");
+        assert_eq!(
+            error.to_string(),
+            format!("127.0.0.1:{port} refused the connection")
+        );
+    }
+
+    /// Accepts one connection on a free port, answering with the v2 transport enabled.
+    fn spawn_v2_listener() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let options = ConnectOptions {
+                timeout: Some(Duration::from_secs(5)),
+                v2_transport: true,
+                ..ConnectOptions::default()
+            };
+            let mut report = HandshakeReport::new(address.to_string(), MessageMagicNumber::Regtest);
+            let _ = accept(stream, &MessageMagicNumber::Regtest, &options, &mut report);
+        });
+        address
+    }
+
+    /// Writes the first 2 bytes of the first write on their own, then the rest after a while.
+    struct SplitWriter {
+        inner: TcpStream,
+        split: bool,
+    }
+
+    impl Write for SplitWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.split {
+                return self.inner.write(buf);
+            }
+            self.split = true;
+            let written = self.inner.write(&buf[..buf.len().min(2)])?;
+            thread::sleep(Duration::from_millis(100));
+            Ok(written)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    #[test]
+    fn test_accept_split_v1_version() {
+        let address = spawn_v2_listener();
+        let stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let network = MessageMagicNumber::Regtest;
+        let version = Message::Version(VersionMessageBuilder::new(network.clone(), address, 0, 1))
+            .to_network_message()
+            .unwrap();
+        let mut writer = SplitWriter {
+            inner: stream.try_clone().unwrap(),
+            split: false,
+        };
+        writer.write_all(&version).unwrap();
+
+        // A v1 answer, not the public key of a v2 key exchange
+        let mut reader = MessageReader::new(Box::new(stream), &network.params());
+        assert_eq!(
+            reader.read_message().unwrap(),
+            Some(MessageCommand::Version)
+        );
+    }
+
+    #[test]
+    fn test_accept_split_v2_key() {
+        let address = spawn_v2_listener();
+        let stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let writer = SplitWriter {
+            inner: stream.try_clone().unwrap(),
+            split: false,
+        };
+        let magic = MessageMagicNumber::Regtest.params().magic;
+        let transport = bip324::transport::initiate(stream, writer, magic).unwrap();
+        assert!(transport.is_some());
+    }
+
+    #[test]
+    fn test_connect_refused_through_proxy() {
+        // A SOCKS5 stand-in accepting no authentication and refusing every destination
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).unwrap();
+            stream.write_all(&[0x05, 0x00]).unwrap();
+            // Version, command, reserved, IPv4 address type, address and port
+            let mut request = [0u8; 10];
+            stream.read_exact(&mut request).unwrap();
+            stream
+                .write_all(&[0x05, 0x05, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+                .unwrap();
+        });
+
+        let options = ConnectOptions {
+            proxy: Some(ProxyConfig {
+                addr: address.to_string(),
+                remote_dns: true,
+                isolate_streams: false,
+                username: None,
+                password: None,
+            }),
+            timeout: Some(Duration::from_secs(5)),
+            ..ConnectOptions::default()
+        };
+        let error = connect("10.0.0.1", 8333, &options).unwrap_err();
+        assert_eq!(error.kind(), "connect_refused");
+        assert_eq!(error.exit_code(), 10);
+        assert!(error.is_retryable());
+        assert!(error.to_string().starts_with("10.0.0.1:8333 refused"));
+    }
+}
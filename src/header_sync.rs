@@ -0,0 +1,552 @@
+use std::{collections::HashMap, fmt};
+
+use serde::Serialize;
+use thiserror::Error;
+use tracing::{info, warn};
+
+use crate::{
+    chain_params::ChainParams,
+    error::Error,
+    handshake::Connection,
+    messages::{
+        block::BlockHeader,
+        headers::{parse_headers, GetHeaders, MAX_HEADERS_RESULTS},
+        message::{hash_to_hex, MessageMagicNumber},
+        version::PROTOCOL_VERSION,
+    },
+    pow::{
+        block_proof, calculate_next_work_required, check_proof_of_work,
+        DIFFICULTY_ADJUSTMENT_INTERVAL, POW_TARGET_SPACING, U256,
+    },
+};
+
+/// How far in the future a block time may be, two hours.
+const MAX_FUTURE_BLOCK_TIME: i64 = 2 * 60 * 60;
+
+/// How much earlier than its parent the first block of a period may be under BIP94.
+const MAX_TIMEWARP: u32 = 600;
+
+/// The number of previous blocks whose median time a block must exceed.
+const MEDIAN_TIME_SPAN: usize = 11;
+
+/// Why a header was rejected.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum HeaderError {
+    #[error("header {hash} does not connect to the chain")]
+    Unconnected { hash: String },
+    #[error("header at height {height} has bits {actual:08x}, expected {expected:08x}")]
+    BadDifficulty {
+        height: u32,
+        actual: u32,
+        expected: u32,
+    },
+    #[error("header {hash} at height {height} does not meet its target")]
+    BadProofOfWork { height: u32, hash: String },
+    #[error("header at height {height} is not after the median time of the previous blocks")]
+    TimeTooOld { height: u32 },
+    #[error("header at height {height} is more than two hours in the future")]
+    TimeTooNew { height: u32 },
+    #[error("header at height {height} is more than {MAX_TIMEWARP} seconds before its parent")]
+    TimeWarp { height: u32 },
+}
+
+/// A header of the chain, with what later headers are validated against.
+#[derive(Debug, Clone, Copy)]
+struct ChainEntry {
+    hash: [u8; 32],
+    time: u32,
+    bits: u32,
+    /// The work of the chain up to and including the block.
+    chain_work: U256,
+}
+
+/// A chain of validated headers, starting at the genesis block of a network.
+#[derive(Debug, Clone)]
+pub struct HeaderChain {
+    params: ChainParams,
+    entries: Vec<ChainEntry>,
+    heights: HashMap<[u8; 32], u32>,
+}
+
+impl HeaderChain {
+    /// Creates a new instance of `HeaderChain` holding only the genesis block.
+    ///
+    /// # Arguments
+    ///
+    /// * `params` - The parameters of the network.
+    pub fn new(params: ChainParams) -> Self {
+        let genesis = ChainEntry {
+            hash: params.genesis_hash,
+            time: params.genesis_time,
+            bits: params.genesis_bits,
+            chain_work: block_proof(params.genesis_bits),
+        };
+        Self {
+            params,
+            entries: vec![genesis],
+            heights: HashMap::from([(genesis.hash, 0)]),
+        }
+    }
+
+    /// Returns the height of the tip, 0 for the genesis block.
+    pub fn height(&self) -> u32 {
+        self.entries.len() as u32 - 1
+    }
+
+    /// Returns the hash of the tip, in internal byte order.
+    pub fn tip(&self) -> [u8; 32] {
+        self.tip_entry().hash
+    }
+
+    /// Returns the hash of the block at `height`, in internal byte order.
+    ///
+    /// # Arguments
+    ///
+    /// * `height` - The height of the block, 0 for the genesis block.
+    pub fn hash_at(&self, height: u32) -> Option<[u8; 32]> {
+        self.entries.get(height as usize).map(|entry| entry.hash)
+    }
+
+    /// Returns hashes of the chain for `getheaders`, like Bitcoin Core: the 10 most recent,
+    /// then exponentially further apart, always ending at the genesis block.
+    pub fn locator(&self) -> Vec<[u8; 32]> {
+        let mut locator = Vec::new();
+        let mut height = self.height() as usize;
+        let mut step = 1;
+        loop {
+            locator.push(self.entries[height].hash);
+            if height == 0 {
+                break;
+            }
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            height = height.saturating_sub(step);
+        }
+        locator
+    }
+
+    /// Validates `headers` and appends them to the chain. If the first header builds on an
+    /// earlier block than the tip, the headers form a fork: they replace the blocks above that
+    /// block only if they are all valid and the fork has more work than the tip. Otherwise the
+    /// chain is left as it was.
+    ///
+    /// # Arguments
+    ///
+    /// * `headers` - Consecutive headers, as received in a `headers` message.
+    /// * `now` - The current time, in seconds since the Unix epoch.
+    pub fn connect(&mut self, headers: &[BlockHeader], now: i64) -> Result<(), HeaderError> {
+        let Some(first) = headers.first() else {
+            return Ok(());
+        };
+        if first.prev_blockhash == self.tip() {
+            return headers
+                .iter()
+                .try_for_each(|header| self.accept(header, now));
+        }
+        let height =
+            *self
+                .heights
+                .get(&first.prev_blockhash)
+                .ok_or_else(|| HeaderError::Unconnected {
+                    hash: hash_to_hex(&first.hash()),
+                })?;
+        let tip_work = self.tip_entry().chain_work;
+        let replaced = self.truncate(height);
+        let result = headers
+            .iter()
+            .try_for_each(|header| self.accept(header, now));
+        if result.is_ok() && self.tip_entry().chain_work > tip_work {
+            info!(height, "peer is on a fork with more work, switched to it");
+            return Ok(());
+        }
+        if result.is_ok() {
+            warn!(height, "fork without more work than the tip ignored");
+        }
+        self.truncate(height);
+        for entry in replaced {
+            self.heights.insert(entry.hash, self.entries.len() as u32);
+            self.entries.push(entry);
+        }
+        result
+    }
+
+    /// Removes the blocks above `height` and returns them.
+    fn truncate(&mut self, height: u32) -> Vec<ChainEntry> {
+        let removed = self.entries.split_off(height as usize + 1);
+        for entry in &removed {
+            self.heights.remove(&entry.hash);
+        }
+        removed
+    }
+
+    /// Validates `header` against the tip and appends it.
+    fn accept(&mut self, header: &BlockHeader, now: i64) -> Result<(), HeaderError> {
+        let height = self.height() + 1;
+        let hash = header.hash();
+        let parent = *self.tip_entry();
+        if header.prev_blockhash != parent.hash {
+            return Err(HeaderError::Unconnected {
+                hash: hash_to_hex(&hash),
+            });
+        }
+        let expected = self.next_work_required(header.time);
+        if header.bits != expected {
+            return Err(HeaderError::BadDifficulty {
+                height,
+                actual: header.bits,
+                expected,
+            });
+        }
+        if !check_proof_of_work(hash, header.bits, &self.params) {
+            return Err(HeaderError::BadProofOfWork {
+                height,
+                hash: hash_to_hex(&hash),
+            });
+        }
+        if header.time <= self.median_time_past() {
+            return Err(HeaderError::TimeTooOld { height });
+        }
+        if i64::from(header.time) > now + MAX_FUTURE_BLOCK_TIME {
+            return Err(HeaderError::TimeTooNew { height });
+        }
+        if self.params.enforce_bip94
+            && height.is_multiple_of(DIFFICULTY_ADJUSTMENT_INTERVAL)
+            && header.time < parent.time.saturating_sub(MAX_TIMEWARP)
+        {
+            return Err(HeaderError::TimeWarp { height });
+        }
+        self.entries.push(ChainEntry {
+            hash,
+            time: header.time,
+            bits: header.bits,
+            chain_work: parent.chain_work + block_proof(header.bits),
+        });
+        self.heights.insert(hash, height);
+        Ok(())
+    }
+
+    /// Returns the bits the block following the tip must have, as in Bitcoin Core's
+    /// `GetNextWorkRequired`.
+    fn next_work_required(&self, time: u32) -> u32 {
+        let height = self.height() + 1;
+        let last = self.tip_entry();
+        if !height.is_multiple_of(DIFFICULTY_ADJUSTMENT_INTERVAL) {
+            if !self.params.pow_allow_min_difficulty_blocks {
+                return last.bits;
+            }
+            // A block 20 minutes late may have the easiest target
+            if u64::from(time) > u64::from(last.time) + 2 * u64::from(POW_TARGET_SPACING) {
+                return self.params.pow_limit;
+            }
+            // Otherwise it has the target of the last block that did not use that rule
+            let mut index = self.height();
+            while index > 0
+                && !index.is_multiple_of(DIFFICULTY_ADJUSTMENT_INTERVAL)
+                && self.entries[index as usize].bits == self.params.pow_limit
+            {
+                index -= 1;
+            }
+            return self.entries[index as usize].bits;
+        }
+        let first = self.entries[(height - DIFFICULTY_ADJUSTMENT_INTERVAL) as usize];
+        // BIP94 starts from the first block of the period, which the exception above cannot
+        // lower
+        let bits = if self.params.enforce_bip94 {
+            first.bits
+        } else {
+            last.bits
+        };
+        calculate_next_work_required(bits, first.time, last.time, &self.params)
+    }
+
+    /// Returns the median time of the last 11 blocks.
+    fn median_time_past(&self) -> u32 {
+        let start = self.entries.len().saturating_sub(MEDIAN_TIME_SPAN);
+        let mut times: Vec<u32> = self.entries[start..]
+            .iter()
+            .map(|entry| entry.time)
+            .collect();
+        times.sort_unstable();
+        times[times.len() / 2]
+    }
+
+    fn tip_entry(&self) -> &ChainEntry {
+        self.entries.last().expect("the chain starts at genesis")
+    }
+}
+
+/// The outcome of a header sync, printed by the `headers` command.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct HeaderSyncReport {
+    /// Address of the peer.
+    pub peer: String,
+    /// The network of the peer.
+    pub network: MessageMagicNumber,
+    /// Height of the validated tip.
+    pub height: u32,
+    /// Hash of the validated tip, as displayed by block explorers.
+    pub hash: String,
+    /// How many headers the peer sent.
+    pub headers_received: usize,
+    /// The height the peer announced in its version message.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub claimed_height: Option<i32>,
+    /// Whether the validated chain reaches the claimed height.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub claim_verified: Option<bool>,
+}
+
+impl fmt::Display for HeaderSyncReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: tip {} at height {}",
+            self.peer, self.hash, self.height
+        )?;
+        match (self.claimed_height, self.claim_verified) {
+            (Some(claimed), Some(true)) => write!(f, ", claimed height {claimed} verified"),
+            (Some(claimed), _) => write!(f, ", claimed height {claimed} not reached"),
+            (None, _) => Ok(()),
+        }
+    }
+}
+
+/// Downloads and validates the headers of the peer's best chain, from the genesis block.
+///
+/// # Arguments
+///
+/// * `connection` - A connection whose handshake completed.
+/// * `claimed_height` - The `start_height` the peer announced, checked against the validated
+///   chain.
+pub fn sync_headers(
+    connection: &mut Connection,
+    claimed_height: Option<i32>,
+) -> Result<HeaderSyncReport, Error> {
+    let mut chain = HeaderChain::new(connection.network.params());
+    let headers_received = download_headers(connection, &mut chain)?;
+
+    let claim_verified =
+        claimed_height.map(|claimed| i64::from(chain.height()) >= i64::from(claimed));
+    if claim_verified == Some(false) {
+        warn!(
+            claimed_height,
+            height = chain.height(),
+            "peer's chain is shorter than it claimed"
+        );
+    }
+    Ok(HeaderSyncReport {
+        peer: connection.reader.peer().to_owned(),
+        network: connection.network.clone(),
+        height: chain.height(),
+        hash: hash_to_hex(&chain.tip()),
+        headers_received,
+        claimed_height,
+        claim_verified,
+    })
+}
+
+/// Downloads the headers of the peer's best chain following the tip of `chain`, validating
+/// them into `chain`. Returns how many headers the peer sent.
+///
+/// # Arguments
+///
+/// * `connection` - A connection whose handshake completed.
+/// * `chain` - The chain to extend.
+pub fn download_headers(
+    connection: &mut Connection,
+    chain: &mut HeaderChain,
+) -> Result<usize, Error> {
+    let peer = connection.reader.peer().to_owned();
+    let violation = |reason: String| Error::ProtocolViolation {
+        peer: peer.clone(),
+        reason,
+    };
+    let mut headers_received = 0;
+    loop {
+        let getheaders = GetHeaders::new(PROTOCOL_VERSION, chain.locator());
+        connection.send_raw("getheaders", &getheaders.payload())?;
+        let headers = loop {
+            connection.reader.read_message()?;
+            match connection.reader.command_name().as_str() {
+                "headers" => {
+                    break parse_headers(connection.reader.payload())
+                        .map_err(|e| violation(format!("malformed headers message: {e}")))?
+                }
+                "ping" => {
+                    let nonce = connection.reader.payload().to_vec();
+                    connection.send_raw("pong", &nonce)?;
+                }
+                _ => {}
+            }
+        };
+        headers_received += headers.len();
+        let tip = chain.tip();
+        chain
+            .connect(&headers, chrono::Utc::now().timestamp())
+            .map_err(|e| violation(e.to_string()))?;
+        info!(height = chain.height(), "headers validated");
+        // A fork without more work leaves the tip, asking again would get the same headers
+        if headers.len() < MAX_HEADERS_RESULTS || chain.tip() == tip {
+            return Ok(headers_received);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::chain_params::{MAIN, REGTEST, TESTNET};
+
+    /// Mines a header on top of `parent` with the easiest regtest target.
+    fn mine(parent: [u8; 32], time: u32) -> BlockHeader {
+        let mut header = BlockHeader {
+            version: 4,
+            prev_blockhash: parent,
+            merkle_root: [0; 32],
+            time,
+            bits: REGTEST.pow_limit,
+            nonce: 0,
+        };
+        while !check_proof_of_work(header.hash(), header.bits, &REGTEST) {
+            header.nonce += 1;
+        }
+        header
+    }
+
+    fn mine_chain(parent: [u8; 32], start_time: u32, count: u32) -> Vec<BlockHeader> {
+        let mut headers: Vec<BlockHeader> = Vec::new();
+        for i in 0..count {
+            let parent = headers.last().map_or(parent, |header| header.hash());
+            headers.push(mine(parent, start_time + i + 1));
+        }
+        headers
+    }
+
+    #[test]
+    fn test_connect_and_fork() {
+        let now = chrono::Utc::now().timestamp();
+        let mut chain = HeaderChain::new(REGTEST);
+        let headers = mine_chain(REGTEST.genesis_hash, REGTEST.genesis_time, 20);
+        chain.connect(&headers, now).unwrap();
+        assert_eq!(chain.height(), 20);
+        assert_eq!(chain.tip(), headers[19].hash());
+        assert_eq!(chain.locator().last(), Some(&REGTEST.genesis_hash));
+        assert_eq!(
+            chain.locator()[..10],
+            [
+                headers[19].hash(),
+                headers[18].hash(),
+                headers[17].hash(),
+                headers[16].hash(),
+                headers[15].hash(),
+                headers[14].hash(),
+                headers[13].hash(),
+                headers[12].hash(),
+                headers[11].hash(),
+                headers[10].hash()
+            ]
+        );
+
+        // A branch forking at height 10 with as much work leaves the tip
+        let fork = mine_chain(headers[9].hash(), headers[9].time + 100, 15);
+        chain.connect(&fork[..10], now).unwrap();
+        assert_eq!(chain.tip(), headers[19].hash());
+        assert_eq!(chain.hash_at(10), Some(headers[9].hash()));
+        assert_eq!(chain.hash_at(11), Some(headers[10].hash()));
+
+        // An invalid branch leaves it too, even after valid headers
+        let mut invalid = fork.clone();
+        invalid[12].bits = 0x1d00ffff;
+        assert!(matches!(
+            chain.connect(&invalid, now),
+            Err(HeaderError::BadDifficulty { height: 23, .. })
+        ));
+        assert_eq!(chain.height(), 20);
+        assert_eq!(chain.tip(), headers[19].hash());
+        assert_eq!(chain.locator()[9], headers[10].hash());
+
+        // A branch with more work replaces it
+        chain.connect(&fork, now).unwrap();
+        assert_eq!(chain.height(), 25);
+        assert_eq!(chain.tip(), fork[14].hash());
+
+        let orphan = mine([7; 32], now as u32);
+        assert!(matches!(
+            chain.connect(&[orphan], now),
+            Err(HeaderError::Unconnected { .. })
+        ));
+    }
+
+    #[test]
+    fn test_rejects_invalid_headers() {
+        let now = chrono::Utc::now().timestamp();
+        let mut chain = HeaderChain::new(REGTEST);
+        let mut chained = mine(REGTEST.genesis_hash, REGTEST.genesis_time + 1);
+        chained.bits = 0x1d00ffff;
+        assert!(matches!(
+            chain.connect(&[chained], now),
+            Err(HeaderError::BadDifficulty { height: 1, .. })
+        ));
+
+        let mut unmined = mine(REGTEST.genesis_hash, REGTEST.genesis_time + 1);
+        while check_proof_of_work(unmined.hash(), unmined.bits, &REGTEST) {
+            unmined.nonce += 1;
+        }
+        assert!(matches!(
+            chain.connect(&[unmined], now),
+            Err(HeaderError::BadProofOfWork { height: 1, .. })
+        ));
+
+        let old = mine(REGTEST.genesis_hash, REGTEST.genesis_time);
+        assert_eq!(
+            chain.connect(&[old], now),
+            Err(HeaderError::TimeTooOld { height: 1 })
+        );
+        let future = mine(REGTEST.genesis_hash, (now + 3 * 60 * 60) as u32);
+        assert_eq!(
+            chain.connect(&[future], now),
+            Err(HeaderError::TimeTooNew { height: 1 })
+        );
+        assert_eq!(chain.height(), 0);
+    }
+
+    #[test]
+    fn test_difficulty_rules() {
+        // Testnet allows the easiest target for a block 20 minutes late, then returns to the
+        // target of the last regular block
+        let mut chain = HeaderChain::new(TESTNET);
+        chain.entries.push(ChainEntry {
+            hash: [1; 32],
+            time: TESTNET.genesis_time + 600,
+            bits: 0x1c05a3f4,
+            chain_work: U256::ZERO,
+        });
+        chain.entries.push(ChainEntry {
+            hash: [2; 32],
+            time: TESTNET.genesis_time + 3000,
+            bits: TESTNET.pow_limit,
+            chain_work: U256::ZERO,
+        });
+        assert_eq!(
+            chain.next_work_required(TESTNET.genesis_time + 4201),
+            TESTNET.pow_limit
+        );
+        assert_eq!(
+            chain.next_work_required(TESTNET.genesis_time + 3600),
+            0x1c05a3f4
+        );
+
+        // Mainnet retargets every 2016 blocks, here with blocks twice as fast as expected
+        let mut chain = HeaderChain::new(MAIN);
+        for height in 1..DIFFICULTY_ADJUSTMENT_INTERVAL {
+            chain.entries.push(ChainEntry {
+                hash: [0; 32],
+                time: MAIN.genesis_time + height * POW_TARGET_SPACING / 2,
+                bits: MAIN.genesis_bits,
+                chain_work: U256::ZERO,
+            });
+        }
+        assert_eq!(chain.next_work_required(0), 0x1c7fef3f);
+    }
+}
@@ -0,0 +1,343 @@
+use block_download::BlockSummary;
+use bootstrap::{Bootstrap, DnsSeedResolver};
+use capture::recording::{read_recording, replay, RECORDING_MAGIC};
+use clap::Parser;
+use cli::{Cli, Command};
+use error::Error;
+use probe::{probe, ProbeOutcome};
+use serde::Serialize;
+use std::fs;
+use std::io::{self, Read};
+use std::net::{SocketAddr, TcpListener};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use config::{Config, OutputFormat};
+use handshake::Connection;
+use mempool::{SeenTransactions, DEFAULT_SEEN_CAPACITY};
+use messages::{
+    bloom::{BloomFilter, BLOOM_UPDATE_NONE},
+    message::hash_from_hex,
+    transaction::Transaction,
+};
+use report::HandshakeReport;
+use retry::{handshake_with_retry, Candidate};
+
+pub mod bip324;
+pub mod block_download;
+pub mod block_filter;
+pub mod bootstrap;
+pub mod broadcast;
+pub mod capture;
+pub mod chain_params;
+pub mod cli;
+pub mod compact_filters;
+pub mod config;
+pub mod decode;
+pub mod error;
+#[cfg(feature = "test-utils")]
+pub mod fake_node;
+pub mod filtered_block;
+pub mod handshake;
+pub mod header_sync;
+pub mod listen;
+pub mod logging;
+pub mod mempool;
+pub mod message_reader;
+pub mod messages;
+pub mod monitor;
+pub mod pow;
+pub mod probe;
+pub mod report;
+pub mod retry;
+pub mod socks5;
+
+/// Timeout used for every network tried in probe mode, unless one is configured.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Runs the `handshaker` CLI with the given arguments, the first being the program name.
+pub fn run(args: Vec<String>) -> Result<(), Error> {
+    let cli = Cli::try_parse_from(args)?;
+    let config = cli.config()?;
+    logging::init(config.log_format);
+    for var in &config.ignored_vars {
+        warn!("Ignoring {var}, which names no setting");
+    }
+
+    match cli.command.unwrap_or(Command::Handshake { address: None }) {
+        Command::Handshake { .. } => {
+            let (report, result) = connect(&config);
+            if config.output == OutputFormat::Json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&report).map_err(anyhow::Error::from)?
+                );
+            }
+            result.map(|_| ())
+        }
+        Command::Listen { bind } => {
+            let bind = match bind {
+                Some(bind) => config.dest_socket_addr(&bind)?,
+                None => SocketAddr::new(
+                    [0, 0, 0, 0].into(),
+                    config.network_type.params().default_port,
+                ),
+            };
+            let listener = TcpListener::bind(bind)?;
+            info!(%bind, "listening");
+            let output = config.output;
+            listen::listen(
+                listener,
+                &config.network_type,
+                &config.connect_options(),
+                move |report| {
+                    if let Err(e) = print(output, &report) {
+                        warn!(error = %e, "failed to print report");
+                    }
+                },
+            )
+        }
+        Command::Crawl => {
+            let bootstrap = Bootstrap::new(DnsSeedResolver, config.network_type.clone())
+                .with_options(config.connect_options());
+            for report in bootstrap.crawl() {
+                print(config.output, &report)?;
+            }
+            Ok(())
+        }
+        Command::Decode { input } => {
+            let frames = if Path::new(&input).exists() {
+                let bytes = fs::read(&input)?;
+                if bytes.starts_with(&RECORDING_MAGIC) {
+                    decode::decode_recording(&read_recording(bytes.as_slice())?)?
+                } else {
+                    // A file of hex, or of raw frames
+                    match hex::decode(String::from_utf8_lossy(&bytes).trim()) {
+                        Ok(decoded) => decode::decode_stream(&decoded)?,
+                        Err(_) => decode::decode_stream(&bytes)?,
+                    }
+                }
+            } else {
+                let bytes = hex::decode(input.trim()).map_err(anyhow::Error::from)?;
+                decode::decode_stream(&bytes)?
+            };
+            for frame in &frames {
+                print(config.output, frame)?;
+            }
+            Ok(())
+        }
+        Command::Monitor { .. } => {
+            let (report, result) = connect(&config);
+            let mut connection = result?;
+            print(config.output, &report)?;
+            let output = config.output;
+            monitor::monitor(&mut connection, |message| match output {
+                OutputFormat::Text => println!(
+                    "{} {} ({} bytes)",
+                    message.at.to_rfc3339(),
+                    message.command,
+                    message.size
+                ),
+                OutputFormat::Json => {
+                    if let Ok(json) = serde_json::to_string(&message) {
+                        println!("{json}");
+                    }
+                }
+            })
+        }
+        Command::Mempool { .. } => {
+            // Shared across connections, so reconnecting does not report transactions again
+            let mut seen = SeenTransactions::new(DEFAULT_SEEN_CAPACITY);
+            let output = config.output;
+            loop {
+                let (_, result) = connect(&config);
+                let mut connection = result?;
+                let result = mempool::watch_mempool(&mut connection, &mut seen, |transaction| {
+                    if let Err(e) = print(output, &transaction) {
+                        warn!(error = %e, "failed to print transaction");
+                    }
+                });
+                match result {
+                    Ok(()) => {}
+                    Err(e) if e.is_retryable() => warn!(error = %e, "connection lost"),
+                    Err(e) => return Err(e),
+                }
+                info!("reconnecting");
+                thread::sleep(config.retry.backoff(1));
+            }
+        }
+        Command::Headers { .. } => {
+            let (report, result) = connect(&config);
+            let mut connection = result?;
+            let claimed_height = report.peer_version.map(|version| version.start_height);
+            let synced = header_sync::sync_headers(&mut connection, claimed_height)?;
+            print(config.output, &synced)
+        }
+        Command::Broadcast { file, wait, .. } => {
+            let hex = match file {
+                Some(path) if path.as_os_str() != "-" => fs::read_to_string(path)?,
+                _ => {
+                    let mut hex = String::new();
+                    io::stdin().read_to_string(&mut hex)?;
+                    hex
+                }
+            };
+            let bytes = hex::decode(hex.trim()).map_err(anyhow::Error::from)?;
+            let transaction = Transaction::try_from(bytes.as_slice())?;
+            let (_, result) = connect(&config);
+            let mut connection = result?;
+            let report =
+                broadcast::broadcast(&mut connection, &transaction, Duration::from_secs(wait))?;
+            print(config.output, &report)?;
+            report.into_result()
+        }
+        Command::Block { hash, .. } => {
+            let hash = hash_from_hex(&hash)?;
+            let (report, result) = connect(&config);
+            let mut connection = result?;
+            let services = report.peer_version.map_or(0, |version| version.services);
+            let block = block_download::download_block(&mut connection, hash, services)?;
+            let witness = services & messages::version::NODE_WITNESS != 0;
+            print(
+                config.output,
+                &BlockSummary::new(report.peer, &block, witness),
+            )
+        }
+        Command::Filters { scripts, start, .. } => {
+            let scripts = scripts
+                .iter()
+                .map(|script| hex::decode(script.trim()))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(anyhow::Error::from)?;
+            let (report, result) = connect(&config);
+            let mut connection = result?;
+            let services = report.peer_version.map_or(0, |version| version.services);
+            let scan = compact_filters::scan_filters(&mut connection, services, &scripts, start)?;
+            print(config.output, &scan)
+        }
+        Command::MerkleBlock {
+            hash,
+            elements,
+            added,
+            ..
+        } => {
+            let hash = hash_from_hex(&hash)?;
+            let decode = |elements: &[String]| {
+                elements
+                    .iter()
+                    .map(|element| hex::decode(element.trim()))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(anyhow::Error::from)
+            };
+            let (elements, added) = (decode(&elements)?, decode(&added)?);
+            // Sized for the elements added later too
+            let mut filter = BloomFilter::new(
+                elements.len() + added.len(),
+                filtered_block::DEFAULT_FP_RATE,
+                rand::random(),
+                BLOOM_UPDATE_NONE,
+            );
+            for element in &elements {
+                filter.insert(element);
+            }
+            let (report, result) = connect(&config);
+            let mut connection = result?;
+            let services = report.peer_version.map_or(0, |version| version.services);
+            filtered_block::load_filter(&mut connection, services, &filter)?;
+            for element in &added {
+                filtered_block::add_to_filter(&mut connection, element)?;
+            }
+            let filtered = filtered_block::download_filtered_block(&mut connection, hash)?;
+            print(config.output, &filtered)
+        }
+        Command::Probe { address } => {
+            let address: SocketAddr = config.dest_socket_addr(&address)?;
+            let timeout = config
+                .timeout
+                .map(Duration::from_secs)
+                .unwrap_or(PROBE_TIMEOUT);
+            match probe(address, timeout)? {
+                ProbeOutcome::Network(network) => println!("{address} speaks {network:?}"),
+                ProbeOutcome::NotBitcoin(magic) => {
+                    println!("{address} is not a Bitcoin node, first bytes: {magic:02x?}")
+                }
+                ProbeOutcome::NoResponse => println!("{address} did not answer on any network"),
+            }
+            Ok(())
+        }
+        Command::Replay { file } => {
+            let report = replay(&file)?;
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&report).map_err(anyhow::Error::from)?
+            );
+            match report.error {
+                Some(error) => Err(anyhow::anyhow!("replay failed: {}", error.message).into()),
+                None => Ok(()),
+            }
+        }
+    }
+}
+
+/// Handshakes with the configured nodes, failing over from `dest_addr` to the `peers`, or with
+/// one found through the DNS seeds.
+fn connect(config: &Config) -> (HandshakeReport, Result<Connection, Error>) {
+    let addresses: Vec<&String> = config.dest_addr.iter().chain(&config.peers).collect();
+    if addresses.is_empty() {
+        info!("no destination address configured, bootstrapping from DNS seeds");
+        let bootstrap = Bootstrap::new(DnsSeedResolver, config.network_type.clone())
+            .with_options(config.connect_options());
+        return match bootstrap.connect() {
+            Ok((report, connection)) => (report, Ok(connection)),
+            Err(e) => {
+                let mut report = HandshakeReport::new(String::new(), config.network_type.clone());
+                let result = Err(e);
+                report.finish(&result);
+                (report, result)
+            }
+        };
+    }
+
+    let candidates: Result<Vec<Candidate>, Error> = addresses
+        .into_iter()
+        .map(|address| {
+            let (host, port) = config.dest_host_port(address)?;
+            Ok(Candidate {
+                address: address.clone(),
+                host,
+                port,
+            })
+        })
+        .collect();
+    match candidates {
+        Ok(candidates) => handshake_with_retry(
+            &config.network_type,
+            &candidates,
+            &config.connect_options(),
+            &config.retry,
+        ),
+        Err(e) => {
+            let mut report = HandshakeReport::new(
+                config.dest_addr.clone().unwrap_or_default(),
+                config.network_type.clone(),
+            );
+            let result = Err(e);
+            report.finish(&result);
+            (report, result)
+        }
+    }
+}
+
+/// Prints one result on stdout: its `Display` form as text, or a line of JSON.
+fn print(output: OutputFormat, value: &(impl Serialize + std::fmt::Display)) -> Result<(), Error> {
+    match output {
+        OutputFormat::Text => println!("{value}"),
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string(value).map_err(anyhow::Error::from)?
+        ),
+    }
+    Ok(())
+}
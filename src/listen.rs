@@ -0,0 +1,98 @@
+use std::{net::TcpListener, sync::Arc, thread};
+
+use tracing::warn;
+
+use crate::{
+    error::Error,
+    handshake::{accept, ConnectOptions},
+    messages::message::MessageMagicNumber,
+    report::HandshakeReport,
+};
+
+/// Accepts peers on `listener` and answers their handshake, each on its own thread. The
+/// connection is closed once the handshake is over and its report is passed to `on_report`.
+///
+/// Only returns if accepting fails.
+///
+/// # Arguments
+///
+/// * `listener` - The bound listener.
+/// * `network` - The network peers are expected to be on.
+/// * `options` - Timeout, transport, recording and what to announce.
+/// * `on_report` - Called with the report of every handshake, failed ones included.
+pub fn listen(
+    listener: TcpListener,
+    network: &MessageMagicNumber,
+    options: &ConnectOptions,
+    on_report: impl Fn(HandshakeReport) + Send + Sync + 'static,
+) -> Result<(), Error> {
+    let on_report = Arc::new(on_report);
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let peer = match stream.peer_addr() {
+            Ok(peer) => peer,
+            Err(e) => {
+                warn!(error = %e, "dropping connection without peer address");
+                continue;
+            }
+        };
+        let network = network.clone();
+        let options = options.clone();
+        let on_report = on_report.clone();
+        thread::spawn(move || {
+            let mut report = HandshakeReport::new(peer.to_string(), network.clone());
+            let result = accept(stream, &network, &options, &mut report);
+            report.finish(&result);
+            on_report(report);
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::mpsc, time::Duration};
+
+    use super::*;
+    use crate::handshake::handshake;
+
+    #[test]
+    fn test_inbound_handshake() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let options = ConnectOptions {
+            timeout: Some(Duration::from_secs(5)),
+            user_agent: Some("/listener/".to_owned()),
+            ..ConnectOptions::default()
+        };
+        let (sender, receiver) = mpsc::channel();
+        let listen_options = options.clone();
+        thread::spawn(move || {
+            listen(
+                listener,
+                &MessageMagicNumber::Regtest,
+                &listen_options,
+                move |report| sender.send(report).unwrap(),
+            )
+        });
+
+        let mut report = HandshakeReport::new(address.to_string(), MessageMagicNumber::Regtest);
+        let options = ConnectOptions {
+            user_agent: Some("/dialer/".to_owned()),
+            ..options
+        };
+        handshake(
+            &MessageMagicNumber::Regtest,
+            "127.0.0.1",
+            address.port(),
+            &options,
+            &mut report,
+        )
+        .unwrap();
+        assert_eq!(report.peer_version.unwrap().user_agent, "/listener/");
+
+        let inbound = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(inbound.success);
+        assert_eq!(inbound.peer_version.unwrap().user_agent, "/dialer/");
+    }
+}
@@ -0,0 +1,35 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use tracing_subscriber::EnvFilter;
+
+/// Format of the log lines written to stderr.
+#[derive(Deserialize, ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Multi-field lines for people watching a terminal.
+    #[default]
+    Human,
+    /// One JSON object per event, including the fields of the enclosing spans.
+    Json,
+    /// Shorter lines, with span fields appended instead of prefixed.
+    Compact,
+}
+
+/// Installs the global subscriber writing to stderr in `format`. The verbosity is taken
+/// from `RUST_LOG`, `info` by default. Does nothing if a subscriber is already installed,
+/// e.g. by a program using this crate as a library.
+///
+/// # Arguments
+///
+/// * `format` - The format of the log lines.
+pub fn init(format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    let _ = match format {
+        LogFormat::Human => builder.try_init(),
+        LogFormat::Json => builder.json().try_init(),
+        LogFormat::Compact => builder.compact().try_init(),
+    };
+}
@@ -0,0 +1,257 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    fmt,
+};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{debug, info};
+
+use crate::{
+    error::Error,
+    handshake::Connection,
+    messages::{
+        inventory::{
+            inventory_payload, parse_inventory, Inventory, MSG_TX, MSG_WITNESS_TX, MSG_WTX,
+        },
+        message::hash_to_hex,
+        transaction::Transaction,
+    },
+};
+
+/// How many transactions `handshaker mempool` remembers to drop duplicates, about the number of
+/// transactions of a full mempool.
+pub const DEFAULT_SEEN_CAPACITY: usize = 500_000;
+
+/// The hashes of the transactions already reported, by txid and by wtxid. Once full, the
+/// oldest are forgotten first.
+#[derive(Debug, Clone)]
+pub struct SeenTransactions {
+    hashes: HashSet<[u8; 32]>,
+    order: VecDeque<([u8; 32], [u8; 32])>,
+    capacity: usize,
+}
+
+/// A transaction relayed by the peer, printed by `handshaker mempool`.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct MempoolTransaction {
+    /// When the transaction was received.
+    pub at: DateTime<Utc>,
+    /// Address of the peer that relayed it.
+    pub peer: String,
+    /// The txid, as displayed by block explorers.
+    pub txid: String,
+    /// The wtxid, as displayed by block explorers.
+    pub wtxid: String,
+    /// Size of the serialized transaction, witnesses included.
+    pub size: usize,
+    /// Virtual size, the weight divided by 4.
+    pub vsize: usize,
+    /// Weight (BIP141).
+    pub weight: usize,
+    /// Number of inputs.
+    pub inputs: usize,
+    /// Number of outputs.
+    pub outputs: usize,
+    /// Sum of the outputs, in satoshis.
+    pub value: u64,
+    /// The serialized transaction.
+    pub hex: String,
+}
+
+impl SeenTransactions {
+    /// Creates a new instance of `SeenTransactions`.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - How many transactions to remember.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            hashes: HashSet::new(),
+            order: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Returns whether a transaction with this txid or wtxid was seen.
+    pub fn contains(&self, hash: &[u8; 32]) -> bool {
+        self.hashes.contains(hash)
+    }
+
+    /// Remembers a transaction. Returns whether it was not seen before.
+    ///
+    /// # Arguments
+    ///
+    /// * `transaction` - The transaction received.
+    pub fn insert(&mut self, transaction: &Transaction) -> bool {
+        let (txid, wtxid) = (transaction.txid(), transaction.wtxid());
+        if self.contains(&txid) || self.contains(&wtxid) {
+            return false;
+        }
+        if self.order.len() >= self.capacity {
+            if let Some((txid, wtxid)) = self.order.pop_front() {
+                self.hashes.remove(&txid);
+                self.hashes.remove(&wtxid);
+            }
+        }
+        self.order.push_back((txid, wtxid));
+        self.hashes.insert(txid);
+        self.hashes.insert(wtxid);
+        true
+    }
+}
+
+impl MempoolTransaction {
+    /// Creates a new instance of `MempoolTransaction`, received now.
+    ///
+    /// # Arguments
+    ///
+    /// * `peer` - Address of the peer that relayed the transaction.
+    /// * `transaction` - The transaction.
+    pub fn new(peer: impl Into<String>, transaction: &Transaction) -> Self {
+        Self {
+            at: Utc::now(),
+            peer: peer.into(),
+            txid: hash_to_hex(&transaction.txid()),
+            wtxid: hash_to_hex(&transaction.wtxid()),
+            size: transaction.size(),
+            vsize: transaction.vsize(),
+            weight: transaction.weight(),
+            inputs: transaction.input.len(),
+            outputs: transaction.output.len(),
+            value: transaction.output.iter().map(|output| output.value).sum(),
+            hex: hex::encode(transaction.serialize()),
+        }
+    }
+}
+
+impl fmt::Display for MempoolTransaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} vB, {} in, {} out, {} sat",
+            self.at.to_rfc3339(),
+            self.txid,
+            self.vsize,
+            self.inputs,
+            self.outputs,
+            self.value
+        )
+    }
+}
+
+/// Fetches the transactions the peer announces with `inv` until it disconnects, answering
+/// pings so the connection stays up. Every transaction not in `seen` is passed to `observe`.
+///
+/// The connection must have been made with `relay` for the peer to announce transactions.
+///
+/// # Arguments
+///
+/// * `connection` - A connection whose handshake completed.
+/// * `seen` - The transactions already reported, shared across connections.
+/// * `observe` - Called with every new transaction.
+pub fn watch_mempool(
+    connection: &mut Connection,
+    seen: &mut SeenTransactions,
+    mut observe: impl FnMut(MempoolTransaction),
+) -> Result<(), Error> {
+    let peer = connection.reader.peer().to_owned();
+    // Requested on this connection and not received yet
+    let mut requested = HashSet::new();
+    loop {
+        match connection.reader.read_message() {
+            Ok(_) => {}
+            Err(Error::PeerDisconnected { .. }) => {
+                info!("peer disconnected");
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        }
+        let payload = connection.reader.payload();
+        match connection.reader.command_name().as_str() {
+            "ping" => {
+                let nonce = payload.to_vec();
+                connection.send_raw("pong", &nonce)?;
+            }
+            "inv" => {
+                let items = parse_inventory(payload).map_err(|e| Error::ProtocolViolation {
+                    peer: peer.clone(),
+                    reason: format!("malformed inv message: {e}"),
+                })?;
+                let wanted: Vec<Inventory> = items
+                    .into_iter()
+                    .filter(|item| item.inv_type == MSG_TX || item.inv_type == MSG_WTX)
+                    .filter(|item| !seen.contains(&item.hash) && requested.insert(item.hash))
+                    // Transactions announced by txid are asked for with their witnesses
+                    .map(|item| match item.inv_type {
+                        MSG_TX => Inventory::new(MSG_WITNESS_TX, item.hash),
+                        _ => item,
+                    })
+                    .collect();
+                if !wanted.is_empty() {
+                    debug!(count = wanted.len(), "requesting transactions");
+                    connection.send_raw("getdata", &inventory_payload(&wanted))?;
+                }
+            }
+            "notfound" => {
+                // Mined or evicted since it was announced
+                for item in parse_inventory(payload).unwrap_or_default() {
+                    requested.remove(&item.hash);
+                }
+            }
+            "tx" => {
+                let transaction =
+                    Transaction::try_from(payload).map_err(|e| Error::ProtocolViolation {
+                        peer: peer.clone(),
+                        reason: format!("malformed tx message: {e}"),
+                    })?;
+                requested.remove(&transaction.txid());
+                requested.remove(&transaction.wtxid());
+                if seen.insert(&transaction) {
+                    observe(MempoolTransaction::new(peer.clone(), &transaction));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::messages::transaction::{OutPoint, TxIn, TxOut};
+
+    fn transaction(vout: u32) -> Transaction {
+        Transaction {
+            version: 2,
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: [1; 32],
+                    vout,
+                },
+                script_sig: Vec::new(),
+                sequence: u32::MAX,
+                witness: vec![vec![2; 72]],
+            }],
+            output: vec![TxOut {
+                value: 1000,
+                script_pubkey: vec![0x51],
+            }],
+            lock_time: 0,
+        }
+    }
+
+    #[test]
+    fn test_seen_transactions() {
+        let mut seen = SeenTransactions::new(2);
+        let first = transaction(0);
+        assert!(seen.insert(&first));
+        assert!(!seen.insert(&first));
+        assert!(seen.contains(&first.txid()) && seen.contains(&first.wtxid()));
+        assert!(seen.insert(&transaction(1)));
+        // The oldest is forgotten, by txid and wtxid
+        assert!(seen.insert(&transaction(2)));
+        assert!(!seen.contains(&first.txid()) && !seen.contains(&first.wtxid()));
+        assert_eq!(seen.hashes.len(), 4);
+    }
+}
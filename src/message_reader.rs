@@ -1,77 +1,138 @@
 use std::io::Read;
 
+use tracing::{debug, info, info_span};
+
 use crate::{
+    chain_params::ChainParams,
     error::Error,
-    messages::message::{MessageCommand, MessageHeader},
+    messages::message::{command_name, MessageCommand, MessageHeader},
 };
 
-/// The size of the buffer used for reading from the stream.
-const BUFFER_SIZE: usize = 1024; //1k just because
-
 /// Represents a reader for Bitcoin messages.
 pub struct MessageReader {
-    buffer: [u8; BUFFER_SIZE],
     reader: Box<dyn Read>,
+    magic: [u8; 4],
+    peer: String,
+    command: [u8; 12],
+    payload: Vec<u8>,
 }
 
 impl MessageReader {
     /// The size of a message header.
-    const HEADER_SIZE: u64 = 24;
+    pub const HEADER_SIZE: usize = 24;
+
+    /// The largest payload accepted, as in Bitcoin Core (`MAX_PROTOCOL_MESSAGE_LENGTH`).
+    pub const MAX_PAYLOAD_SIZE: u32 = 4_000_000;
 
     /// Creates a new instance of `MessageReader` with the given reader.
     ///
     /// # Arguments
     ///
     /// * `reader` - A reader implementing the `Read` trait.
-    pub fn new(reader: Box<dyn Read>) -> Self {
+    /// * `params` - Parameters of the network the messages are expected to come from.
+    pub fn new(reader: Box<dyn Read>, params: &ChainParams) -> Self {
         Self {
-            buffer: [0x0; BUFFER_SIZE],
             reader,
+            magic: params.magic,
+            peer: "unknown peer".to_owned(),
+            command: [0u8; 12],
+            payload: Vec::new(),
         }
     }
+
+    /// Sets the address of the peer, reported in the errors of `read_message`.
+    ///
+    /// # Arguments
+    ///
+    /// * `peer` - The address of the peer.
+    pub fn with_peer(mut self, peer: impl Into<String>) -> Self {
+        self.peer = peer.into();
+        self
+    }
+
+    /// Returns the address of the peer the messages come from.
+    pub fn peer(&self) -> &str {
+        &self.peer
+    }
+
+    /// Returns the command name of the message last read by `read_message`, including
+    /// unrecognized ones, e.g. `ping`.
+    pub fn command_name(&self) -> String {
+        command_name(&self.command)
+    }
+
+    /// Returns the payload of the message last read by `read_message`.
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
     /// Reads a Bitcoin message from the underlying stream.
     ///
-    /// Returns the parsed `MessageCommand` if successful. If the read was successful
-    /// but no more data is available (end of stream), or message is unrecognized `Ok(None)` is returned.
-    /// If an error occurs during reading or parsing, an `Error` is returned.
+    /// Returns the parsed `MessageCommand` if successful, its payload is then available from
+    /// `payload`. If message is unrecognized `Ok(None)` is returned. If the peer closed the
+    /// stream, or an error occurs during reading or parsing, an `Error` is returned.
     pub fn read_message(&mut self) -> Result<Option<MessageCommand>, Error> {
         // Read header
-        let mut take = self.reader.as_mut().take(MessageReader::HEADER_SIZE);
-        let readed = take.read(&mut self.buffer[0..MessageReader::HEADER_SIZE as usize])?;
+        let mut buffer = [0u8; MessageReader::HEADER_SIZE];
+        self.reader
+            .read_exact(&mut buffer)
+            .map_err(|e| Error::io(self.peer.clone(), e))?;
 
-        if readed == 0 {
-            return Ok(None);
+        // Parse header
+        let header: MessageHeader = buffer.as_ref().try_into()?;
+        if header.magin_network_nr != self.magic {
+            return Err(Error::WrongNetwork {
+                peer: self.peer.clone(),
+                magic: header.magin_network_nr,
+            });
         }
 
-        // Parse header
-        let header: MessageHeader = self.buffer.as_ref().try_into()?;
-        let command: MessageCommand = match header.command.try_into() {
-            Ok(command) => command,
-            Err(e) => {
-                eprintln!("{e}");
-                return Ok(None);
+        self.command = header.command;
+        let _span = info_span!(
+            "message",
+            command = %self.command_name(),
+            size = header.payload_len,
+            direction = "received"
+        )
+        .entered();
+        if header.payload_len > Self::MAX_PAYLOAD_SIZE {
+            return Err(Error::OversizedMessage {
+                peer: self.peer.clone(),
+                command: self.command_name(),
+                size: header.payload_len,
+                max: Self::MAX_PAYLOAD_SIZE,
+            });
+        }
+
+        // Read the payload, even of unrecognized messages, so the next header is found. The
+        // buffer grows as bytes arrive, a header announcing a large payload doesn't allocate it
+        self.payload.clear();
+        (&mut self.reader)
+            .take(header.payload_len as u64)
+            .read_to_end(&mut self.payload)
+            .map_err(|e| Error::io(self.peer.clone(), e))?;
+        if self.payload.len() < header.payload_len as usize {
+            return Err(Error::PeerDisconnected {
+                peer: self.peer.clone(),
+            });
+        }
+        if !header.checksum_matches(&self.payload) {
+            return Err(Error::BadChecksum {
+                peer: self.peer.clone(),
+                command: self.command_name(),
+            });
+        }
+
+        match header.command.try_into() {
+            Ok(command) => {
+                info!("message received");
+                Ok(Some(command))
             }
-        };
-
-        // Read the rest of the payload
-        let mut data_to_read = header.payload_len as usize;
-        // Limiting number of iteration so we won't end up in infinite loop
-        let mut it = 0;
-        loop {
-            let bytes_to_read = if data_to_read > BUFFER_SIZE {
-                BUFFER_SIZE
-            } else {
-                data_to_read
-            };
-            let mut take = self.reader.as_mut().take(bytes_to_read as u64);
-            let readed = take.read(&mut self.buffer)?;
-            data_to_read -= readed;
-            it += 1;
-            if data_to_read == 0 || it > 10 {
-                break;
+            Err(_) => {
+                debug!("ignoring unknown message");
+                Ok(None)
             }
         }
-        Ok(Some(command))
     }
 }
 
@@ -80,16 +141,59 @@ mod test {
     use std::io::Cursor;
 
     use super::*;
+    use crate::chain_params::{MAIN, TESTNET};
+
     #[test]
     fn test_big_payload() {
         let mut big_message: Vec<u8> = vec![
             0xF9, 0xBE, 0xB4, 0xD9, 0x76, 0x65, 0x72, 0x73, 0x69, 0x6F, 0x6E, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x64, 0x0A, 0x00, 0x00, 0x11, 0xF1, 0x11, 0x87, 0x71, 0x11, 0x01, 0x00,
+            0x00, 0x00, 0x64, 0x0A, 0x00, 0x00, 0x2A, 0x04, 0x13, 0xF2,
         ];
         let mut dummy_data: Vec<u8> = vec![0; 3000];
         big_message.append(&mut dummy_data);
         let cursor = Cursor::new(big_message);
-        let mut reader = MessageReader::new(Box::new(cursor));
+        let mut reader = MessageReader::new(Box::new(cursor), &MAIN);
+        reader.read_message().unwrap();
+        assert_eq!(reader.payload().len(), 0x0A64);
+    }
+
+    #[test]
+    fn test_wrong_network_rejected() {
+        let verack: Vec<u8> = vec![
+            0xF9, 0xBE, 0xB4, 0xD9, 0x76, 0x65, 0x72, 0x61, 0x63, 0x6B, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xE0, 0xE2, 0x5D, 0xF6,
+        ];
+        let mut reader = MessageReader::new(Box::new(Cursor::new(verack)), &TESTNET)
+            .with_peer("127.0.0.1:18333");
+        let error = reader.read_message().unwrap_err();
+        assert_eq!(error.kind(), "wrong_network");
+        assert!(error.to_string().starts_with("127.0.0.1:18333"));
+    }
+
+    #[test]
+    fn test_framing_errors() {
+        let verack: Vec<u8> = vec![
+            0xF9, 0xBE, 0xB4, 0xD9, 0x76, 0x65, 0x72, 0x61, 0x63, 0x6B, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x5D, 0xF6, 0xE0, 0xE2,
+        ];
+        let mut corrupt = verack.clone();
+        corrupt[20] ^= 0xFF;
+        let mut reader = MessageReader::new(Box::new(Cursor::new(corrupt)), &MAIN);
+        assert_eq!(reader.read_message().unwrap_err().kind(), "bad_checksum");
+
+        let mut oversized = verack.clone();
+        oversized[16..20].copy_from_slice(&(MessageReader::MAX_PAYLOAD_SIZE + 1).to_le_bytes());
+        let mut reader = MessageReader::new(Box::new(Cursor::new(oversized)), &MAIN);
+        assert_eq!(
+            reader.read_message().unwrap_err().kind(),
+            "oversized_message"
+        );
+
+        let mut reader = MessageReader::new(Box::new(Cursor::new(verack)), &MAIN);
         reader.read_message().unwrap();
+        assert_eq!(
+            reader.read_message().unwrap_err().kind(),
+            "peer_disconnected"
+        );
     }
 }
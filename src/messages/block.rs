@@ -0,0 +1,365 @@
+use serde::Serialize;
+
+use crate::error::Error;
+
+use super::{
+    message::{compact_size, double_sha256, hash_to_hex, PayloadCursor},
+    transaction::{Transaction, WITNESS_SCALE_FACTOR},
+};
+
+/// Length of a serialized block header.
+pub const BLOCK_HEADER_LEN: usize = 80;
+
+/// The start of the coinbase output committing to the witnesses (BIP141): `OP_RETURN`, a push
+/// of 36 bytes and a tag.
+const WITNESS_COMMITMENT_HEADER: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
+/// The header of a block, which commits to its transactions and to the previous block.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockHeader {
+    /// The block version, signalling soft forks.
+    pub version: i32,
+    /// Hash of the previous block, in internal byte order.
+    #[serde(serialize_with = "serialize_hash")]
+    pub prev_blockhash: [u8; 32],
+    /// Root of the merkle tree of the transactions, in internal byte order.
+    #[serde(serialize_with = "serialize_hash")]
+    pub merkle_root: [u8; 32],
+    /// The time the miner claims, in seconds since the Unix epoch.
+    pub time: u32,
+    /// The target the hash must meet, in compact form.
+    pub bits: u32,
+    /// The nonce the miner varied to meet the target.
+    pub nonce: u32,
+}
+
+/// A block: its header and its transactions, the coinbase first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    /// The header of the block.
+    pub header: BlockHeader,
+    /// The transactions of the block.
+    pub txdata: Vec<Transaction>,
+}
+
+impl BlockHeader {
+    /// Serializes the header into its 80 bytes.
+    pub fn serialize(&self) -> [u8; BLOCK_HEADER_LEN] {
+        let mut bytes = [0u8; BLOCK_HEADER_LEN];
+        bytes[0..4].copy_from_slice(&self.version.to_le_bytes());
+        bytes[4..36].copy_from_slice(&self.prev_blockhash);
+        bytes[36..68].copy_from_slice(&self.merkle_root);
+        bytes[68..72].copy_from_slice(&self.time.to_le_bytes());
+        bytes[72..76].copy_from_slice(&self.bits.to_le_bytes());
+        bytes[76..80].copy_from_slice(&self.nonce.to_le_bytes());
+        bytes
+    }
+
+    /// Returns the hash of the block, in internal byte order.
+    pub fn hash(&self) -> [u8; 32] {
+        double_sha256(&self.serialize())
+    }
+
+    /// Reads a header from the front of `cursor`.
+    pub(crate) fn read(cursor: &mut PayloadCursor) -> Result<Self, Error> {
+        Ok(Self {
+            version: i32::from_le_bytes(cursor.take()?),
+            prev_blockhash: cursor.take()?,
+            merkle_root: cursor.take()?,
+            time: u32::from_le_bytes(cursor.take()?),
+            bits: u32::from_le_bytes(cursor.take()?),
+            nonce: u32::from_le_bytes(cursor.take()?),
+        })
+    }
+}
+
+impl TryFrom<&[u8]> for BlockHeader {
+    type Error = Error;
+
+    /// Parses a header from exactly 80 bytes.
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        if bytes.len() != BLOCK_HEADER_LEN {
+            return Err(anyhow::anyhow!("block header is {} bytes", bytes.len()).into());
+        }
+        Self::read(&mut PayloadCursor(bytes))
+    }
+}
+
+impl Block {
+    /// Serializes the block, with the witnesses of its transactions.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = self.header.serialize().to_vec();
+        bytes.extend(compact_size(self.txdata.len() as u64));
+        for transaction in &self.txdata {
+            bytes.extend(transaction.serialize());
+        }
+        bytes
+    }
+
+    /// Returns the size of the serialized block, witnesses included.
+    pub fn size(&self) -> usize {
+        self.serialize().len()
+    }
+
+    /// Returns the weight of the block (BIP141), which may be at most 4,000,000.
+    pub fn weight(&self) -> usize {
+        let base = BLOCK_HEADER_LEN
+            + compact_size(self.txdata.len() as u64).len()
+            + self
+                .txdata
+                .iter()
+                .map(|transaction| transaction.serialize_without_witness().len())
+                .sum::<usize>();
+        base * (WITNESS_SCALE_FACTOR - 1) + self.size()
+    }
+
+    /// Returns the height the coinbase commits to (BIP34), the first push of its scriptSig.
+    /// Blocks of version 1 predate BIP34 and have none.
+    pub fn coinbase_height(&self) -> Option<u32> {
+        if self.header.version < 2 {
+            return None;
+        }
+        let script_sig = &self.txdata.first()?.input.first()?.script_sig;
+        match *script_sig.first()? {
+            // OP_0, and OP_1 to OP_16 for the first blocks
+            0x00 => Some(0),
+            opcode @ 0x51..=0x60 => Some(u32::from(opcode - 0x50)),
+            len @ 0x01..=0x04 => {
+                let bytes = script_sig.get(1..=usize::from(len))?;
+                // A script number, little-endian with the sign in the top bit
+                if bytes[bytes.len() - 1] & 0x80 != 0 {
+                    return None;
+                }
+                Some(
+                    bytes
+                        .iter()
+                        .rev()
+                        .fold(0, |height, byte| height << 8 | u32::from(*byte)),
+                )
+            }
+            _ => None,
+        }
+    }
+
+    /// Computes the merkle root of the txids of the transactions.
+    pub fn compute_merkle_root(&self) -> [u8; 32] {
+        merkle_root(self.txdata.iter().map(Transaction::txid).collect()).0
+    }
+
+    /// Returns whether the merkle root in the header commits to the transactions. A block
+    /// whose transaction list was padded with duplicates to keep the root (CVE-2012-2459) does
+    /// not match.
+    pub fn check_merkle_root(&self) -> bool {
+        let (root, mutated) = merkle_root(self.txdata.iter().map(Transaction::txid).collect());
+        root == self.header.merkle_root && !mutated
+    }
+
+    /// Computes the merkle root of the wtxids of the transactions, the coinbase counting as
+    /// all zeros (BIP141).
+    pub fn witness_root(&self) -> [u8; 32] {
+        let wtxids = self
+            .txdata
+            .iter()
+            .enumerate()
+            .map(|(i, transaction)| if i == 0 { [0; 32] } else { transaction.wtxid() })
+            .collect();
+        merkle_root(wtxids).0
+    }
+
+    /// Returns whether the witnesses of the transactions match the commitment in the coinbase
+    /// (BIP141). A block without a commitment must not have witnesses.
+    pub fn check_witness_commitment(&self) -> bool {
+        let Some(coinbase) = self.txdata.first() else {
+            return false;
+        };
+        // The last output carrying a commitment counts
+        let commitment = coinbase.output.iter().rev().find_map(|output| {
+            output
+                .script_pubkey
+                .strip_prefix(&WITNESS_COMMITMENT_HEADER)
+                .and_then(|rest| rest.get(..32))
+        });
+        let Some(commitment) = commitment else {
+            return !self.txdata.iter().any(Transaction::has_witness);
+        };
+        // The coinbase witness is a reserved value committed to along with the root
+        match coinbase.input.first().map(|input| &input.witness[..]) {
+            Some([reserved]) if reserved.len() == 32 => {
+                double_sha256(&[&self.witness_root()[..], reserved].concat()) == commitment
+            }
+            _ => false,
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for Block {
+    type Error = Error;
+
+    /// Parses a block, which must span all of `bytes`.
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        let mut cursor = PayloadCursor(bytes);
+        let header = BlockHeader::read(&mut cursor)?;
+        let mut txdata = Vec::new();
+        for _ in 0..cursor.compact_size()? {
+            txdata.push(Transaction::read(&mut cursor)?);
+        }
+        if !cursor.0.is_empty() {
+            return Err(anyhow::anyhow!("{} bytes after the block", cursor.0.len()).into());
+        }
+        Ok(Self { header, txdata })
+    }
+}
+
+/// Computes the root of the merkle tree of `hashes` like Bitcoin Core, duplicating the last
+/// hash of levels of odd length. Also returns whether two identical hashes were paired, which
+/// lets a list with duplicated transactions have the root of the list without them.
+///
+/// # Arguments
+///
+/// * `hashes` - The leaves, in internal byte order.
+pub fn merkle_root(mut hashes: Vec<[u8; 32]>) -> ([u8; 32], bool) {
+    let mut mutated = false;
+    if hashes.is_empty() {
+        return ([0; 32], mutated);
+    }
+    while hashes.len() > 1 {
+        mutated |= hashes.chunks_exact(2).any(|pair| pair[0] == pair[1]);
+        if hashes.len() % 2 == 1 {
+            hashes.push(hashes[hashes.len() - 1]);
+        }
+        hashes = hashes
+            .chunks_exact(2)
+            .map(|pair| double_sha256(&[pair[0], pair[1]].concat()))
+            .collect();
+    }
+    (hashes[0], mutated)
+}
+
+/// Serializes a hash in internal byte order as displayed by block explorers.
+fn serialize_hash<S>(hash: &[u8; 32], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_str(&hash_to_hex(hash))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        chain_params::MAIN,
+        messages::{message::hash_from_hex, transaction::TxOut},
+    };
+
+    /// The genesis block of mainnet.
+    const GENESIS_BLOCK: &str = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c0101000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000";
+
+    #[test]
+    fn test_genesis_header() {
+        let merkle_root =
+            hash_from_hex("4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b")
+                .unwrap();
+        let genesis = BlockHeader {
+            version: 1,
+            prev_blockhash: [0; 32],
+            merkle_root,
+            time: MAIN.genesis_time,
+            bits: MAIN.genesis_bits,
+            nonce: 2083236893,
+        };
+        assert_eq!(genesis.hash(), MAIN.genesis_hash);
+        assert_eq!(
+            hash_to_hex(&genesis.hash()),
+            "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f"
+        );
+        let bytes = genesis.serialize();
+        assert_eq!(BlockHeader::try_from(&bytes[..]).unwrap(), genesis);
+        assert!(BlockHeader::try_from(&bytes[..79]).is_err());
+    }
+
+    #[test]
+    fn test_genesis_block() {
+        let bytes = hex::decode(GENESIS_BLOCK).unwrap();
+        let block = Block::try_from(&bytes[..]).unwrap();
+        assert_eq!(block.header.hash(), MAIN.genesis_hash);
+        assert_eq!(block.txdata.len(), 1);
+        assert!(block.txdata[0].is_coinbase());
+        assert_eq!(block.txdata[0].output[0].value, 50 * 100_000_000);
+        assert!(block.check_merkle_root());
+        assert!(block.check_witness_commitment());
+        assert_eq!(block.serialize(), bytes);
+        assert_eq!(block.size(), 285);
+        assert_eq!(block.weight(), 285 * 4);
+        assert!(Block::try_from(&bytes[..284]).is_err());
+
+        // Another transaction breaks the commitment of the header
+        let mut modified = block.clone();
+        modified.txdata.push(block.txdata[0].clone());
+        assert!(!modified.check_merkle_root());
+    }
+
+    #[test]
+    fn test_witness_commitment() {
+        let genesis = Block::try_from(&hex::decode(GENESIS_BLOCK).unwrap()[..]).unwrap();
+        let mut coinbase = genesis.txdata[0].clone();
+        coinbase.input[0].witness = vec![vec![0; 32]];
+        let segwit = Transaction::try_from(
+            &hex::decode(crate::messages::transaction::test::SEGWIT_TX).unwrap()[..],
+        )
+        .unwrap();
+        let mut block = Block {
+            header: genesis.header,
+            txdata: vec![coinbase, segwit],
+        };
+        // Witnesses without a commitment
+        assert!(!block.check_witness_commitment());
+
+        let commitment = double_sha256(&[block.witness_root(), [0; 32]].concat());
+        block.txdata[0].output.push(TxOut {
+            value: 0,
+            script_pubkey: [&WITNESS_COMMITMENT_HEADER[..], &commitment].concat(),
+        });
+        assert!(block.check_witness_commitment());
+        block.header.merkle_root = block.compute_merkle_root();
+        assert!(block.check_merkle_root());
+        assert_eq!(Block::try_from(&block.serialize()[..]).unwrap(), block);
+
+        // The commitment covers the witnesses, unlike the merkle root
+        block.txdata[1].input[1].witness[0][10] ^= 1;
+        assert!(!block.check_witness_commitment());
+        assert!(block.check_merkle_root());
+    }
+
+    #[test]
+    fn test_coinbase_height() {
+        let mut block = Block::try_from(&hex::decode(GENESIS_BLOCK).unwrap()[..]).unwrap();
+        assert_eq!(block.coinbase_height(), None);
+        block.header.version = 2;
+        for (script_sig, height) in [
+            (vec![0x03, 0x40, 0x0d, 0x03, 0xff], Some(200_000)),
+            (vec![0x02, 0x80, 0x00], Some(128)),
+            (vec![0x51, 0x00], Some(1)),
+            (vec![0x60], Some(16)),
+            (vec![0x01, 0x81], None),
+            (vec![0x03, 0x40], None),
+            (vec![], None),
+        ] {
+            block.txdata[0].input[0].script_sig = script_sig;
+            assert_eq!(block.coinbase_height(), height);
+        }
+    }
+
+    #[test]
+    fn test_merkle_root() {
+        let hashes: Vec<[u8; 32]> = (1..=3).map(|i| [i; 32]).collect();
+        let (root, mutated) = merkle_root(hashes.clone());
+        assert!(!mutated);
+        let left = double_sha256(&[hashes[0], hashes[1]].concat());
+        let right = double_sha256(&[hashes[2], hashes[2]].concat());
+        assert_eq!(root, double_sha256(&[left, right].concat()));
+        // Duplicating the last transaction keeps the root, but is detected
+        let duplicated = [hashes.clone(), vec![hashes[2]]].concat();
+        assert_eq!(merkle_root(duplicated), (root, true));
+        assert_eq!(merkle_root(vec![hashes[0]]), (hashes[0], false));
+    }
+}
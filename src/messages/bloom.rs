@@ -0,0 +1,269 @@
+use crate::error::Error;
+
+use super::message::{compact_size, PayloadCursor};
+
+/// The largest filter a `filterload` message may carry, in bytes.
+pub const MAX_BLOOM_FILTER_SIZE: usize = 36_000;
+
+/// The most hash functions a filter may use.
+pub const MAX_HASH_FUNCS: u32 = 50;
+
+/// The largest element a `filteradd` message may carry, a script push.
+pub const MAX_FILTERADD_SIZE: usize = 520;
+
+/// The peer does not add outpoints of matched transactions to the filter.
+pub const BLOOM_UPDATE_NONE: u8 = 0;
+/// The peer adds the outpoints of every output matching the filter to it.
+pub const BLOOM_UPDATE_ALL: u8 = 1;
+/// The peer adds the outpoints of matching pay-to-pubkey and multisig outputs only.
+pub const BLOOM_UPDATE_P2PUBKEY_ONLY: u8 = 2;
+
+/// Multiplier of the seed of the hash functions.
+const SEED_MULTIPLIER: u32 = 0xFBA4_C795;
+
+/// A bloom filter (BIP37), loaded into the peer with `filterload` so it only relays the
+/// transactions matching it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    /// The bit field.
+    pub data: Vec<u8>,
+    /// The number of hash functions.
+    pub hash_funcs: u32,
+    /// Added to the seed of every hash function, so filters of the same elements differ.
+    pub tweak: u32,
+    /// How the peer updates the filter as transactions match, e.g. `BLOOM_UPDATE_ALL`.
+    pub flags: u8,
+}
+
+impl BloomFilter {
+    /// Creates a new instance of `BloomFilter` sized like Bitcoin Core does for a number of
+    /// elements and a false positive rate, within the limits of BIP37.
+    ///
+    /// # Arguments
+    ///
+    /// * `elements` - How many elements will be inserted.
+    /// * `fp_rate` - The false positive rate wanted, e.g. `0.0001`.
+    /// * `tweak` - Added to the seed of every hash function, usually random.
+    /// * `flags` - How the peer updates the filter, e.g. `BLOOM_UPDATE_ALL`.
+    pub fn new(elements: usize, fp_rate: f64, tweak: u32, flags: u8) -> Self {
+        let ln2 = std::f64::consts::LN_2;
+        let elements = elements.max(1) as f64;
+        let bits = (-1.0 / (ln2 * ln2) * elements * fp_rate.ln()) as u32;
+        let len = (bits.min(MAX_BLOOM_FILTER_SIZE as u32 * 8) / 8).max(1) as usize;
+        let hash_funcs = ((len * 8) as f64 / elements * ln2) as u32;
+        Self {
+            data: vec![0; len],
+            hash_funcs: hash_funcs.clamp(1, MAX_HASH_FUNCS),
+            tweak,
+            flags,
+        }
+    }
+
+    /// Adds an element, e.g. a scriptPubKey push, a txid or a serialized outpoint.
+    ///
+    /// # Arguments
+    ///
+    /// * `element` - The element.
+    pub fn insert(&mut self, element: &[u8]) {
+        if self.data.is_empty() {
+            return;
+        }
+        for i in 0..self.hash_funcs {
+            let bit = self.bit_index(i, element);
+            self.data[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    /// Returns whether the filter may contain `element`, always true for an empty filter.
+    ///
+    /// # Arguments
+    ///
+    /// * `element` - The element.
+    pub fn contains(&self, element: &[u8]) -> bool {
+        // An empty filter would divide by zero (CVE-2013-5700)
+        if self.data.is_empty() {
+            return true;
+        }
+        (0..self.hash_funcs).all(|i| {
+            let bit = self.bit_index(i, element);
+            self.data[bit / 8] & (1 << (bit % 8)) != 0
+        })
+    }
+
+    /// Returns the serialized payload of a `filterload` message.
+    pub fn payload(&self) -> Vec<u8> {
+        let mut payload = compact_size(self.data.len() as u64);
+        payload.extend_from_slice(&self.data);
+        payload.extend_from_slice(&self.hash_funcs.to_le_bytes());
+        payload.extend_from_slice(&self.tweak.to_le_bytes());
+        payload.push(self.flags);
+        payload
+    }
+
+    fn bit_index(&self, i: u32, element: &[u8]) -> usize {
+        let seed = i.wrapping_mul(SEED_MULTIPLIER).wrapping_add(self.tweak);
+        murmur3(seed, element) as usize % (self.data.len() * 8)
+    }
+}
+
+impl TryFrom<&[u8]> for BloomFilter {
+    type Error = Error;
+
+    /// Parses the payload of a `filterload` message.
+    fn try_from(payload: &[u8]) -> Result<Self, Self::Error> {
+        let mut cursor = PayloadCursor(payload);
+        let len = cursor.compact_size()?;
+        if len > MAX_BLOOM_FILTER_SIZE {
+            return Err(anyhow::anyhow!(
+                "filter of {len} bytes, at most {MAX_BLOOM_FILTER_SIZE} allowed"
+            )
+            .into());
+        }
+        let data = cursor.take_slice(len)?.to_vec();
+        let hash_funcs = u32::from_le_bytes(cursor.take()?);
+        if hash_funcs > MAX_HASH_FUNCS {
+            return Err(anyhow::anyhow!(
+                "{hash_funcs} hash functions, at most {MAX_HASH_FUNCS} allowed"
+            )
+            .into());
+        }
+        Ok(Self {
+            data,
+            hash_funcs,
+            tweak: u32::from_le_bytes(cursor.take()?),
+            flags: cursor.take::<1>()?[0],
+        })
+    }
+}
+
+/// Returns the serialized payload of a `filteradd` message, which adds `element` to the filter
+/// loaded into the peer.
+///
+/// # Arguments
+///
+/// * `element` - The element, at most 520 bytes.
+pub fn filteradd_payload(element: &[u8]) -> Result<Vec<u8>, Error> {
+    if element.len() > MAX_FILTERADD_SIZE {
+        return Err(anyhow::anyhow!(
+            "element of {} bytes, at most {MAX_FILTERADD_SIZE} allowed",
+            element.len()
+        )
+        .into());
+    }
+    let mut payload = compact_size(element.len() as u64);
+    payload.extend_from_slice(element);
+    Ok(payload)
+}
+
+/// Computes the 32-bit MurmurHash3 (x86 variant) of `data`, the hash function of bloom
+/// filters.
+///
+/// # Arguments
+///
+/// * `seed` - The seed.
+/// * `data` - The data hashed.
+pub fn murmur3(seed: u32, data: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+    let mix = |k: u32| k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+
+    let mut h = seed;
+    let mut blocks = data.chunks_exact(4);
+    for block in &mut blocks {
+        let k = u32::from_le_bytes(block.try_into().expect("4 bytes"));
+        h = (h ^ mix(k))
+            .rotate_left(13)
+            .wrapping_mul(5)
+            .wrapping_add(0xe654_6b64);
+    }
+    let tail = blocks.remainder();
+    if !tail.is_empty() {
+        let k = tail
+            .iter()
+            .rev()
+            .fold(0, |k, byte| k << 8 | u32::from(*byte));
+        h ^= mix(k);
+    }
+
+    h ^= data.len() as u32;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^ h >> 16
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_murmur3() {
+        // Test vectors of Bitcoin Core
+        for (expected, seed, data) in [
+            (0x0000_0000, 0x0000_0000, ""),
+            (0x6a39_6f08, 0xFBA4_C795, ""),
+            (0x81f1_6f39, 0xffff_ffff, ""),
+            (0x514e_28b7, 0x0000_0000, "00"),
+            (0xea3f_0b17, 0xFBA4_C795, "00"),
+            (0xfd6c_f10d, 0x0000_0000, "ff"),
+            (0x16c6_b7ab, 0x0000_0000, "0011"),
+            (0x8eb5_1c3d, 0x0000_0000, "001122"),
+            (0xb447_1bf8, 0x0000_0000, "00112233"),
+            (0xe230_1fa8, 0x0000_0000, "0011223344"),
+            (0xfc2e_4a15, 0x0000_0000, "001122334455"),
+            (0xb074_502c, 0x0000_0000, "00112233445566"),
+            (0x8034_d2a0, 0x0000_0000, "0011223344556677"),
+            (0xb469_8def, 0x0000_0000, "001122334455667788"),
+        ] {
+            assert_eq!(
+                murmur3(seed, &hex::decode(data).unwrap()),
+                expected,
+                "{data}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_bloom_filter() {
+        // The first filter of Bitcoin Core's bloom tests
+        let elements = [
+            "99108ad8ed9bb6274d3980bab5a85c048f0950c8",
+            "b5a2c786d9ef4658287ced5914b37a1b4aa32eee",
+            "b9300670b4c5366e95b2699e8b18bc75e5f729c5",
+        ]
+        .map(|element| hex::decode(element).unwrap());
+        for (tweak, expected) in [
+            (0, "03614e9b050000000000000001"),
+            (2147483649, "03ce4299050000000100008001"),
+        ] {
+            let mut filter = BloomFilter::new(3, 0.01, tweak, BLOOM_UPDATE_ALL);
+            for element in &elements {
+                filter.insert(element);
+                assert!(filter.contains(element));
+            }
+            assert!(
+                !filter.contains(&hex::decode("19108ad8ed9bb6274d3980bab5a85c048f0950c8").unwrap())
+            );
+            assert_eq!(hex::encode(filter.payload()), expected);
+            assert_eq!(
+                BloomFilter::try_from(&filter.payload()[..]).unwrap(),
+                filter
+            );
+        }
+
+        // Sizes are capped
+        let filter = BloomFilter::new(1_000_000, 0.000_001, 0, BLOOM_UPDATE_NONE);
+        assert_eq!(filter.data.len(), MAX_BLOOM_FILTER_SIZE);
+        let mut oversized = compact_size(36_001);
+        oversized.extend(vec![0; 36_010]);
+        assert!(BloomFilter::try_from(&oversized[..]).is_err());
+
+        let mut empty = BloomFilter::try_from(&[0, 1, 0, 0, 0, 0, 0, 0, 0, 0][..]).unwrap();
+        empty.insert(&[1]);
+        assert!(empty.contains(&[2]));
+
+        assert_eq!(filteradd_payload(&[1; 20]).unwrap()[0], 20);
+        assert!(filteradd_payload(&[1; 521]).is_err());
+    }
+}
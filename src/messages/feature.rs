@@ -0,0 +1,223 @@
+use serde::Serialize;
+
+use crate::error::Error;
+
+use super::message::{build_frame, MessageMagicNumber};
+
+/// The protocol version announced when we negotiate features, the first one that allows every
+/// `FeatureMessage` (BIP339 `wtxidrelay`).
+pub const FEATURES_PROTOCOL_VERSION: i32 = 70016;
+
+/// The compact block version we announce, the one of segwit blocks (BIP152).
+pub const COMPACT_BLOCKS_VERSION: u64 = 2;
+
+/// The fee rate we announce in `feefilter`, in satoshis per 1000 virtual bytes. This is the
+/// default minimum relay fee rate of Bitcoin Core.
+pub const DEFAULT_FEE_FILTER: u64 = 1000;
+
+/// A message negotiating a feature of the connection, exchanged around the handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeatureMessage {
+    /// Announce transactions by wtxid (BIP339). Only valid between version and verack.
+    WtxidRelay,
+    /// Send addresses as `addrv2` (BIP155). Only valid between version and verack.
+    SendAddrV2,
+    /// Announce new blocks with `headers` instead of `inv` (BIP130).
+    SendHeaders,
+    /// Announce new blocks as compact blocks (BIP152).
+    SendCmpct {
+        /// Whether blocks are pushed before they are validated.
+        high_bandwidth: bool,
+        /// The compact block version, 2 for segwit blocks.
+        version: u64,
+    },
+    /// Only relay transactions paying at least this fee rate (BIP133).
+    FeeFilter {
+        /// The fee rate, in satoshis per 1000 virtual bytes.
+        fee_rate: u64,
+    },
+}
+
+/// Represents a builder for creating a feature negotiation message.
+pub struct FeatureMessageBuilder {
+    /// The magic number for the Bitcoin network.
+    pub magic_number: MessageMagicNumber,
+    /// The message to send.
+    pub message: FeatureMessage,
+}
+
+/// The features a peer asked for.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerFeatures {
+    /// Whether the peer sent `wtxidrelay`.
+    pub wtxid_relay: bool,
+    /// Whether the peer sent `sendaddrv2`.
+    pub addr_v2: bool,
+    /// Whether the peer sent `sendheaders`.
+    pub send_headers: bool,
+    /// The compact block versions the peer announced, with whether it wants them in high
+    /// bandwidth mode.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub compact_blocks: Vec<CompactBlocks>,
+    /// The minimum fee rate of the transactions relayed to the peer, in sat/kvB.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fee_filter: Option<u64>,
+}
+
+/// A compact block version announced in `sendcmpct`.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactBlocks {
+    pub high_bandwidth: bool,
+    pub version: u64,
+}
+
+impl FeatureMessage {
+    /// Returns the command name of the message.
+    pub fn command(&self) -> &'static str {
+        match self {
+            FeatureMessage::WtxidRelay => "wtxidrelay",
+            FeatureMessage::SendAddrV2 => "sendaddrv2",
+            FeatureMessage::SendHeaders => "sendheaders",
+            FeatureMessage::SendCmpct { .. } => "sendcmpct",
+            FeatureMessage::FeeFilter { .. } => "feefilter",
+        }
+    }
+
+    /// Returns the serialized payload of the message.
+    pub fn payload(&self) -> Vec<u8> {
+        match self {
+            FeatureMessage::WtxidRelay
+            | FeatureMessage::SendAddrV2
+            | FeatureMessage::SendHeaders => Vec::new(),
+            FeatureMessage::SendCmpct {
+                high_bandwidth,
+                version,
+            } => [&[*high_bandwidth as u8][..], &version.to_le_bytes()].concat(),
+            FeatureMessage::FeeFilter { fee_rate } => fee_rate.to_le_bytes().to_vec(),
+        }
+    }
+
+    /// Parses a received message. Returns `None` if `command` is not a feature negotiation.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The command name, e.g. `sendcmpct`.
+    /// * `payload` - The payload of the message.
+    pub fn parse(command: &str, payload: &[u8]) -> Result<Option<Self>, Error> {
+        let message = match command {
+            "wtxidrelay" => FeatureMessage::WtxidRelay,
+            "sendaddrv2" => FeatureMessage::SendAddrV2,
+            "sendheaders" => FeatureMessage::SendHeaders,
+            "sendcmpct" => {
+                let payload: [u8; 9] = payload
+                    .try_into()
+                    .map_err(|_| anyhow::anyhow!("sendcmpct payload is {} bytes", payload.len()))?;
+                let mut version = [0u8; 8];
+                version.copy_from_slice(&payload[1..]);
+                FeatureMessage::SendCmpct {
+                    high_bandwidth: payload[0] != 0,
+                    version: u64::from_le_bytes(version),
+                }
+            }
+            "feefilter" => {
+                let fee_rate: [u8; 8] = payload
+                    .try_into()
+                    .map_err(|_| anyhow::anyhow!("feefilter payload is {} bytes", payload.len()))?;
+                FeatureMessage::FeeFilter {
+                    fee_rate: u64::from_le_bytes(fee_rate),
+                }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(message))
+    }
+
+    /// Returns whether the message must be sent between version and verack.
+    pub fn before_verack(&self) -> bool {
+        matches!(
+            self,
+            FeatureMessage::WtxidRelay | FeatureMessage::SendAddrV2
+        )
+    }
+}
+
+impl FeatureMessageBuilder {
+    /// Creates a new instance of `FeatureMessageBuilder`.
+    ///
+    /// # Arguments
+    ///
+    /// * `magic_number` - The magic number for the Bitcoin network.
+    /// * `message` - The message to send.
+    pub fn new(magic_number: MessageMagicNumber, message: FeatureMessage) -> Self {
+        Self {
+            magic_number,
+            message,
+        }
+    }
+
+    /// Serializes the message into a frame.
+    pub fn to_frame(&self) -> Result<Vec<u8>, Error> {
+        build_frame(
+            &self.magic_number,
+            self.message.command(),
+            &self.message.payload(),
+        )
+    }
+}
+
+impl PeerFeatures {
+    /// Records a feature the peer asked for.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The message received from the peer.
+    pub fn record(&mut self, message: FeatureMessage) {
+        match message {
+            FeatureMessage::WtxidRelay => self.wtxid_relay = true,
+            FeatureMessage::SendAddrV2 => self.addr_v2 = true,
+            FeatureMessage::SendHeaders => self.send_headers = true,
+            FeatureMessage::SendCmpct {
+                high_bandwidth,
+                version,
+            } => self.compact_blocks.push(CompactBlocks {
+                high_bandwidth,
+                version,
+            }),
+            FeatureMessage::FeeFilter { fee_rate } => self.fee_filter = Some(fee_rate),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let messages = [
+            FeatureMessage::WtxidRelay,
+            FeatureMessage::SendAddrV2,
+            FeatureMessage::SendHeaders,
+            FeatureMessage::SendCmpct {
+                high_bandwidth: false,
+                version: COMPACT_BLOCKS_VERSION,
+            },
+            FeatureMessage::FeeFilter { fee_rate: 1000 },
+        ];
+        for message in messages {
+            let parsed = FeatureMessage::parse(message.command(), &message.payload()).unwrap();
+            assert_eq!(parsed, Some(message));
+        }
+        // From a Bitcoin Core 27 peer
+        let sendcmpct = hex::decode("000200000000000000").unwrap();
+        assert_eq!(
+            FeatureMessage::parse("sendcmpct", &sendcmpct).unwrap(),
+            Some(FeatureMessage::SendCmpct {
+                high_bandwidth: false,
+                version: 2
+            })
+        );
+        assert!(FeatureMessage::parse("feefilter", &[0; 4]).is_err());
+        assert_eq!(FeatureMessage::parse("ping", &[0; 8]).unwrap(), None);
+    }
+}
@@ -0,0 +1,287 @@
+use crate::error::Error;
+
+use super::message::{compact_size, PayloadCursor};
+
+/// The basic filter type (BIP158), the only one defined.
+pub const FILTER_TYPE_BASIC: u8 = 0;
+
+/// The most filters a `getcfilters` message may ask for.
+pub const MAX_GETCFILTERS_SIZE: u32 = 1000;
+
+/// The most filter headers a `getcfheaders` message may ask for.
+pub const MAX_GETCFHEADERS_SIZE: u32 = 2000;
+
+/// The interval between the filter headers of a `cfcheckpt` message.
+pub const CFCHECKPT_INTERVAL: u32 = 1000;
+
+/// Asks the peer for the filters (`getcfilters`) or the filter headers (`getcfheaders`) of the
+/// blocks from `start_height` to `stop_hash`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GetCFilters {
+    /// The filter type, e.g. `FILTER_TYPE_BASIC`.
+    pub filter_type: u8,
+    /// Height of the first block.
+    pub start_height: u32,
+    /// Hash of the last block, in internal byte order.
+    pub stop_hash: [u8; 32],
+}
+
+/// A `getcfheaders` message, laid out like `getcfilters`.
+pub type GetCFHeaders = GetCFilters;
+
+/// Asks the peer for the filter headers of every 1000th block up to `stop_hash`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GetCFCheckpt {
+    /// The filter type, e.g. `FILTER_TYPE_BASIC`.
+    pub filter_type: u8,
+    /// Hash of the last block, in internal byte order.
+    pub stop_hash: [u8; 32],
+}
+
+/// A `cfilter` message: the filter of a block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CFilter {
+    /// The filter type, e.g. `FILTER_TYPE_BASIC`.
+    pub filter_type: u8,
+    /// Hash of the block, in internal byte order.
+    pub block_hash: [u8; 32],
+    /// The serialized filter.
+    pub filter: Vec<u8>,
+}
+
+/// A `cfheaders` message: the filter hashes of consecutive blocks, from which their filter
+/// headers follow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CFHeaders {
+    /// The filter type, e.g. `FILTER_TYPE_BASIC`.
+    pub filter_type: u8,
+    /// Hash of the last block, in internal byte order.
+    pub stop_hash: [u8; 32],
+    /// The filter header of the block before the first one, all zeros before genesis.
+    pub previous_filter_header: [u8; 32],
+    /// The hashes of the filters, in block order.
+    pub filter_hashes: Vec<[u8; 32]>,
+}
+
+/// A `cfcheckpt` message: the filter headers of every 1000th block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CFCheckpt {
+    /// The filter type, e.g. `FILTER_TYPE_BASIC`.
+    pub filter_type: u8,
+    /// Hash of the last block, in internal byte order.
+    pub stop_hash: [u8; 32],
+    /// The filter headers at heights 1000, 2000 and so on.
+    pub filter_headers: Vec<[u8; 32]>,
+}
+
+impl GetCFilters {
+    /// Creates a new instance of `GetCFilters` for basic filters.
+    ///
+    /// # Arguments
+    ///
+    /// * `start_height` - Height of the first block.
+    /// * `stop_hash` - Hash of the last block, in internal byte order.
+    pub fn new(start_height: u32, stop_hash: [u8; 32]) -> Self {
+        Self {
+            filter_type: FILTER_TYPE_BASIC,
+            start_height,
+            stop_hash,
+        }
+    }
+
+    /// Returns the serialized payload of the message.
+    pub fn payload(&self) -> Vec<u8> {
+        let mut payload = vec![self.filter_type];
+        payload.extend_from_slice(&self.start_height.to_le_bytes());
+        payload.extend_from_slice(&self.stop_hash);
+        payload
+    }
+}
+
+impl TryFrom<&[u8]> for GetCFilters {
+    type Error = Error;
+
+    fn try_from(payload: &[u8]) -> Result<Self, Self::Error> {
+        let mut cursor = PayloadCursor(payload);
+        Ok(Self {
+            filter_type: cursor.take::<1>()?[0],
+            start_height: u32::from_le_bytes(cursor.take()?),
+            stop_hash: cursor.take()?,
+        })
+    }
+}
+
+impl GetCFCheckpt {
+    /// Creates a new instance of `GetCFCheckpt` for basic filters.
+    ///
+    /// # Arguments
+    ///
+    /// * `stop_hash` - Hash of the last block, in internal byte order.
+    pub fn new(stop_hash: [u8; 32]) -> Self {
+        Self {
+            filter_type: FILTER_TYPE_BASIC,
+            stop_hash,
+        }
+    }
+
+    /// Returns the serialized payload of the message.
+    pub fn payload(&self) -> Vec<u8> {
+        [&[self.filter_type][..], &self.stop_hash].concat()
+    }
+}
+
+impl TryFrom<&[u8]> for GetCFCheckpt {
+    type Error = Error;
+
+    fn try_from(payload: &[u8]) -> Result<Self, Self::Error> {
+        let mut cursor = PayloadCursor(payload);
+        Ok(Self {
+            filter_type: cursor.take::<1>()?[0],
+            stop_hash: cursor.take()?,
+        })
+    }
+}
+
+impl CFilter {
+    /// Returns the serialized payload of the message.
+    pub fn payload(&self) -> Vec<u8> {
+        let mut payload = vec![self.filter_type];
+        payload.extend_from_slice(&self.block_hash);
+        payload.extend(compact_size(self.filter.len() as u64));
+        payload.extend_from_slice(&self.filter);
+        payload
+    }
+}
+
+impl TryFrom<&[u8]> for CFilter {
+    type Error = Error;
+
+    fn try_from(payload: &[u8]) -> Result<Self, Self::Error> {
+        let mut cursor = PayloadCursor(payload);
+        let filter_type = cursor.take::<1>()?[0];
+        let block_hash = cursor.take()?;
+        let len = cursor.compact_size()?;
+        Ok(Self {
+            filter_type,
+            block_hash,
+            filter: cursor.take_slice(len)?.to_vec(),
+        })
+    }
+}
+
+impl CFHeaders {
+    /// Returns the serialized payload of the message.
+    pub fn payload(&self) -> Vec<u8> {
+        let mut payload = vec![self.filter_type];
+        payload.extend_from_slice(&self.stop_hash);
+        payload.extend_from_slice(&self.previous_filter_header);
+        payload.extend(hashes_payload(&self.filter_hashes));
+        payload
+    }
+}
+
+impl TryFrom<&[u8]> for CFHeaders {
+    type Error = Error;
+
+    fn try_from(payload: &[u8]) -> Result<Self, Self::Error> {
+        let mut cursor = PayloadCursor(payload);
+        Ok(Self {
+            filter_type: cursor.take::<1>()?[0],
+            stop_hash: cursor.take()?,
+            previous_filter_header: cursor.take()?,
+            filter_hashes: read_hashes(&mut cursor, MAX_GETCFHEADERS_SIZE as usize)?,
+        })
+    }
+}
+
+impl CFCheckpt {
+    /// Returns the serialized payload of the message.
+    pub fn payload(&self) -> Vec<u8> {
+        let mut payload = vec![self.filter_type];
+        payload.extend_from_slice(&self.stop_hash);
+        payload.extend(hashes_payload(&self.filter_headers));
+        payload
+    }
+}
+
+impl TryFrom<&[u8]> for CFCheckpt {
+    type Error = Error;
+
+    fn try_from(payload: &[u8]) -> Result<Self, Self::Error> {
+        let mut cursor = PayloadCursor(payload);
+        let filter_type = cursor.take::<1>()?[0];
+        let stop_hash = cursor.take()?;
+        // Bounded by the message size, a checkpoint every 1000 blocks
+        let max = cursor.0.len() / 32;
+        Ok(Self {
+            filter_type,
+            stop_hash,
+            filter_headers: read_hashes(&mut cursor, max)?,
+        })
+    }
+}
+
+fn hashes_payload(hashes: &[[u8; 32]]) -> Vec<u8> {
+    let mut payload = compact_size(hashes.len() as u64);
+    for hash in hashes {
+        payload.extend_from_slice(hash);
+    }
+    payload
+}
+
+fn read_hashes(cursor: &mut PayloadCursor, max: usize) -> Result<Vec<[u8; 32]>, Error> {
+    let count = cursor.compact_size()?;
+    if count > max {
+        return Err(anyhow::anyhow!("{count} hashes, at most {max} allowed").into());
+    }
+    (0..count).map(|_| cursor.take()).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_filter_messages_round_trip() {
+        let getcfilters = GetCFilters::new(1000, [1; 32]);
+        let payload = getcfilters.payload();
+        assert_eq!(payload.len(), 1 + 4 + 32);
+        assert_eq!(GetCFilters::try_from(&payload[..]).unwrap(), getcfilters);
+        let getcfcheckpt = GetCFCheckpt::new([2; 32]);
+        assert_eq!(
+            GetCFCheckpt::try_from(&getcfcheckpt.payload()[..]).unwrap(),
+            getcfcheckpt
+        );
+
+        let cfilter = CFilter {
+            filter_type: FILTER_TYPE_BASIC,
+            block_hash: [3; 32],
+            filter: vec![0x01, 0x9d, 0xfc, 0xa8],
+        };
+        assert_eq!(CFilter::try_from(&cfilter.payload()[..]).unwrap(), cfilter);
+        assert!(CFilter::try_from(&cfilter.payload()[..36]).is_err());
+
+        let cfheaders = CFHeaders {
+            filter_type: FILTER_TYPE_BASIC,
+            stop_hash: [4; 32],
+            previous_filter_header: [5; 32],
+            filter_hashes: vec![[6; 32], [7; 32]],
+        };
+        assert_eq!(
+            CFHeaders::try_from(&cfheaders.payload()[..]).unwrap(),
+            cfheaders
+        );
+        let mut oversized = cfheaders.payload()[..65].to_vec();
+        oversized.extend(compact_size(2001));
+        assert!(CFHeaders::try_from(&oversized[..]).is_err());
+
+        let cfcheckpt = CFCheckpt {
+            filter_type: FILTER_TYPE_BASIC,
+            stop_hash: [8; 32],
+            filter_headers: vec![[9; 32]; 3],
+        };
+        let payload = cfcheckpt.payload();
+        assert_eq!(CFCheckpt::try_from(&payload[..]).unwrap(), cfcheckpt);
+        assert!(CFCheckpt::try_from(&payload[..payload.len() - 1]).is_err());
+    }
+}
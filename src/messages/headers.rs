@@ -0,0 +1,100 @@
+use crate::error::Error;
+
+use super::{
+    block::BlockHeader,
+    message::{compact_size, PayloadCursor},
+};
+
+/// The most headers a peer sends in one `headers` message. Fewer means it has no more.
+pub const MAX_HEADERS_RESULTS: usize = 2000;
+
+/// Asks the peer for the headers following the first hash of `locator` it knows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GetHeaders {
+    /// Our protocol version.
+    pub version: i32,
+    /// Hashes of our chain from the tip back to genesis, in internal byte order.
+    pub locator: Vec<[u8; 32]>,
+    /// The hash to stop at, all zeros for as many headers as possible.
+    pub stop_hash: [u8; 32],
+}
+
+impl GetHeaders {
+    /// Creates a new instance of `GetHeaders` that asks for as many headers as possible.
+    ///
+    /// # Arguments
+    ///
+    /// * `version` - Our protocol version.
+    /// * `locator` - Hashes of our chain, most recent first.
+    pub fn new(version: i32, locator: Vec<[u8; 32]>) -> Self {
+        Self {
+            version,
+            locator,
+            stop_hash: [0; 32],
+        }
+    }
+
+    /// Returns the serialized payload of the message.
+    pub fn payload(&self) -> Vec<u8> {
+        let mut payload = self.version.to_le_bytes().to_vec();
+        payload.extend(compact_size(self.locator.len() as u64));
+        for hash in &self.locator {
+            payload.extend_from_slice(hash);
+        }
+        payload.extend_from_slice(&self.stop_hash);
+        payload
+    }
+}
+
+/// Parses the payload of a `headers` message.
+///
+/// # Arguments
+///
+/// * `payload` - The payload of the message.
+pub fn parse_headers(payload: &[u8]) -> Result<Vec<BlockHeader>, Error> {
+    let mut cursor = PayloadCursor(payload);
+    let count = cursor.compact_size()?;
+    if count > MAX_HEADERS_RESULTS {
+        return Err(
+            anyhow::anyhow!("{count} headers, at most {MAX_HEADERS_RESULTS} allowed").into(),
+        );
+    }
+    let mut headers = Vec::with_capacity(count);
+    for _ in 0..count {
+        headers.push(BlockHeader::read(&mut cursor)?);
+        // The transaction count, always 0 in a headers message
+        cursor.compact_size()?;
+    }
+    Ok(headers)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_headers_round_trip() {
+        let header = BlockHeader {
+            version: 0x2000_0000,
+            prev_blockhash: [1; 32],
+            merkle_root: [2; 32],
+            time: 1_700_000_000,
+            bits: 0x1703_4219,
+            nonce: 42,
+        };
+        let mut payload = compact_size(2);
+        for _ in 0..2 {
+            payload.extend_from_slice(&header.serialize());
+            payload.push(0);
+        }
+        assert_eq!(parse_headers(&payload).unwrap(), [header, header]);
+        assert!(parse_headers(&payload[..payload.len() - 1]).is_err());
+        assert!(parse_headers(&compact_size(2001)).is_err());
+        // 1 as a CompactSize of 3 bytes
+        assert!(parse_headers(&[0xFD, 0x01, 0x00]).is_err());
+
+        let getheaders = GetHeaders::new(70016, vec![[3; 32]]).payload();
+        assert_eq!(getheaders.len(), 4 + 1 + 32 + 32);
+        assert_eq!(getheaders[4], 1);
+    }
+}
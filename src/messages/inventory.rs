@@ -0,0 +1,100 @@
+use crate::error::Error;
+
+use super::message::{compact_size, PayloadCursor};
+
+/// A transaction, by txid.
+pub const MSG_TX: u32 = 1;
+/// A block.
+pub const MSG_BLOCK: u32 = 2;
+/// A block as a `merkleblock` matching our bloom filter (BIP37).
+pub const MSG_FILTERED_BLOCK: u32 = 3;
+/// A block as a compact block (BIP152).
+pub const MSG_CMPCT_BLOCK: u32 = 4;
+/// A transaction, by wtxid (BIP339).
+pub const MSG_WTX: u32 = 5;
+/// Set on the type of a `getdata` entry to get the object with its witnesses (BIP144).
+pub const MSG_WITNESS_FLAG: u32 = 1 << 30;
+/// A transaction with its witnesses.
+pub const MSG_WITNESS_TX: u32 = MSG_TX | MSG_WITNESS_FLAG;
+/// A block with the witnesses of its transactions.
+pub const MSG_WITNESS_BLOCK: u32 = MSG_BLOCK | MSG_WITNESS_FLAG;
+
+/// The most entries an `inv`, `getdata` or `notfound` message may have.
+pub const MAX_INV_SIZE: usize = 50_000;
+
+/// An entry of an `inv`, `getdata` or `notfound` message: an object identified by its hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Inventory {
+    /// What the object is, e.g. `MSG_TX`.
+    pub inv_type: u32,
+    /// The hash of the object, in internal byte order.
+    pub hash: [u8; 32],
+}
+
+impl Inventory {
+    /// Creates a new instance of `Inventory`.
+    ///
+    /// # Arguments
+    ///
+    /// * `inv_type` - What the object is, e.g. `MSG_TX`.
+    /// * `hash` - The hash of the object, in internal byte order.
+    pub fn new(inv_type: u32, hash: [u8; 32]) -> Self {
+        Self { inv_type, hash }
+    }
+}
+
+/// Serializes the payload of an `inv`, `getdata` or `notfound` message.
+///
+/// # Arguments
+///
+/// * `items` - The entries of the message.
+pub fn inventory_payload(items: &[Inventory]) -> Vec<u8> {
+    let mut payload = compact_size(items.len() as u64);
+    for item in items {
+        payload.extend_from_slice(&item.inv_type.to_le_bytes());
+        payload.extend_from_slice(&item.hash);
+    }
+    payload
+}
+
+/// Parses the payload of an `inv`, `getdata` or `notfound` message.
+///
+/// # Arguments
+///
+/// * `payload` - The payload of the message.
+pub fn parse_inventory(payload: &[u8]) -> Result<Vec<Inventory>, Error> {
+    let mut cursor = PayloadCursor(payload);
+    let count = cursor.compact_size()?;
+    if count > MAX_INV_SIZE {
+        return Err(
+            anyhow::anyhow!("{count} inventory entries, at most {MAX_INV_SIZE} allowed").into(),
+        );
+    }
+    let mut items = Vec::with_capacity(count);
+    for _ in 0..count {
+        items.push(Inventory {
+            inv_type: u32::from_le_bytes(cursor.take()?),
+            hash: cursor.take()?,
+        });
+    }
+    Ok(items)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_inventory_round_trip() {
+        let items = [
+            Inventory::new(MSG_WTX, [1; 32]),
+            Inventory::new(MSG_WITNESS_BLOCK, [2; 32]),
+        ];
+        let payload = inventory_payload(&items);
+        assert_eq!(payload.len(), 1 + 2 * 36);
+        assert_eq!(payload[37..41], [0x02, 0x00, 0x00, 0x40]);
+        assert_eq!(parse_inventory(&payload).unwrap(), items);
+        assert!(parse_inventory(&payload[..40]).is_err());
+        assert!(parse_inventory(&compact_size(50_001)).is_err());
+    }
+}
@@ -0,0 +1,326 @@
+use crate::error::Error;
+
+use super::{
+    block::BlockHeader,
+    message::{compact_size, double_sha256, PayloadCursor},
+};
+
+/// The most transactions a block may hold: the largest weight over the smallest transaction
+/// weight.
+const MAX_BLOCK_TRANSACTIONS: u32 = 4_000_000 / 240;
+
+/// Matched txids with their position in the block.
+pub type Matches = Vec<(u32, [u8; 32])>;
+
+/// A `merkleblock` message (BIP37): a block header and the branches of its merkle tree leading
+/// to the transactions matching our bloom filter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleBlock {
+    /// The header of the block.
+    pub header: BlockHeader,
+    /// The merkle branches of the matching transactions.
+    pub tree: PartialMerkleTree,
+}
+
+/// A merkle tree pruned to the branches of some of its leaves, walked depth first: a bit per
+/// node visited tells whether a matched leaf is below it, and the nodes not descended into
+/// carry their hash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialMerkleTree {
+    /// Number of transactions in the block.
+    pub total_transactions: u32,
+    /// The hashes of the nodes not descended into, in internal byte order.
+    pub hashes: Vec<[u8; 32]>,
+    /// Whether each node visited is a matched leaf or above one.
+    pub bits: Vec<bool>,
+}
+
+impl MerkleBlock {
+    /// Returns the txids of the matching transactions with their position in the block, once
+    /// their branches are checked against the merkle root of the header.
+    pub fn matched_txids(&self) -> Result<Matches, Error> {
+        let (root, matches) = self.tree.extract_matches()?;
+        if root != self.header.merkle_root {
+            return Err(anyhow::anyhow!("merkle branches do not match the merkle root").into());
+        }
+        Ok(matches)
+    }
+
+    /// Returns the serialized payload of the message.
+    pub fn payload(&self) -> Vec<u8> {
+        let mut payload = self.header.serialize().to_vec();
+        payload.extend(self.tree.serialize());
+        payload
+    }
+}
+
+impl TryFrom<&[u8]> for MerkleBlock {
+    type Error = Error;
+
+    fn try_from(payload: &[u8]) -> Result<Self, Self::Error> {
+        let mut cursor = PayloadCursor(payload);
+        let header = BlockHeader::read(&mut cursor)?;
+        let total_transactions = u32::from_le_bytes(cursor.take()?);
+        let count = cursor.compact_size()?;
+        if count > MAX_BLOCK_TRANSACTIONS as usize {
+            return Err(anyhow::anyhow!("{count} hashes in a merkle block").into());
+        }
+        let hashes = (0..count)
+            .map(|_| cursor.take())
+            .collect::<Result<_, _>>()?;
+        let len = cursor.compact_size()?;
+        let bits = cursor
+            .take_slice(len)?
+            .iter()
+            .flat_map(|byte| (0..8).map(move |i| byte >> i & 1 == 1))
+            .collect();
+        Ok(Self {
+            header,
+            tree: PartialMerkleTree {
+                total_transactions,
+                hashes,
+                bits,
+            },
+        })
+    }
+}
+
+impl PartialMerkleTree {
+    /// Builds the tree of `txids` pruned to the branches of the matched ones, like a peer
+    /// answering a `getdata` of a filtered block.
+    ///
+    /// # Arguments
+    ///
+    /// * `txids` - The txids of the transactions of the block.
+    /// * `matches` - Whether each transaction matches.
+    pub fn new(txids: &[[u8; 32]], matches: &[bool]) -> Self {
+        let mut tree = Self {
+            total_transactions: txids.len() as u32,
+            hashes: Vec::new(),
+            bits: Vec::new(),
+        };
+        tree.build(tree.height(), 0, txids, matches);
+        tree
+    }
+
+    /// Walks the tree, checking it is well formed and uses all its hashes and bits, and
+    /// returns its merkle root and the matched txids with their position in the block.
+    pub fn extract_matches(&self) -> Result<([u8; 32], Matches), Error> {
+        if self.total_transactions == 0 {
+            return Err(anyhow::anyhow!("merkle block without transactions").into());
+        }
+        if self.total_transactions > MAX_BLOCK_TRANSACTIONS {
+            return Err(anyhow::anyhow!(
+                "merkle block of {} transactions",
+                self.total_transactions
+            )
+            .into());
+        }
+        if self.hashes.len() > self.total_transactions as usize {
+            return Err(anyhow::anyhow!("more hashes than transactions").into());
+        }
+        if self.bits.len() < self.hashes.len() {
+            return Err(anyhow::anyhow!("fewer bits than hashes").into());
+        }
+        let mut walk = Walk::default();
+        let root = self.extract(self.height(), 0, &mut walk)?;
+        // Only the padding of the last byte may be left
+        if walk.bits_used.div_ceil(8) != self.bits.len().div_ceil(8) {
+            return Err(anyhow::anyhow!("unused bits in merkle block").into());
+        }
+        if walk.hashes_used != self.hashes.len() {
+            return Err(anyhow::anyhow!("unused hashes in merkle block").into());
+        }
+        Ok((root, walk.matches))
+    }
+
+    /// Serializes the tree as in a `merkleblock` message, the bits packed least significant
+    /// first.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = self.total_transactions.to_le_bytes().to_vec();
+        bytes.extend(compact_size(self.hashes.len() as u64));
+        for hash in &self.hashes {
+            bytes.extend_from_slice(hash);
+        }
+        let mut flags = vec![0u8; self.bits.len().div_ceil(8)];
+        for (i, bit) in self.bits.iter().enumerate() {
+            flags[i / 8] |= u8::from(*bit) << (i % 8);
+        }
+        bytes.extend(compact_size(flags.len() as u64));
+        bytes.extend(flags);
+        bytes
+    }
+
+    /// Returns the height of the tree, 0 for a block of one transaction.
+    fn height(&self) -> u32 {
+        let mut height = 0;
+        while self.width(height) > 1 {
+            height += 1;
+        }
+        height
+    }
+
+    /// Returns the number of nodes at `height`, 0 being the leaves.
+    fn width(&self, height: u32) -> u32 {
+        ((u64::from(self.total_transactions) + (1 << height) - 1) >> height) as u32
+    }
+
+    fn hash(&self, height: u32, position: u32, txids: &[[u8; 32]]) -> [u8; 32] {
+        if height == 0 {
+            return txids[position as usize];
+        }
+        let left = self.hash(height - 1, position * 2, txids);
+        // A node without a right child is paired with itself
+        let right = if position * 2 + 1 < self.width(height - 1) {
+            self.hash(height - 1, position * 2 + 1, txids)
+        } else {
+            left
+        };
+        double_sha256(&[left, right].concat())
+    }
+
+    fn build(&mut self, height: u32, position: u32, txids: &[[u8; 32]], matches: &[bool]) {
+        let first = (position << height) as usize;
+        let last = (((position + 1) << height) as usize).min(txids.len());
+        let parent_of_match = matches[first..last].iter().any(|matched| *matched);
+        self.bits.push(parent_of_match);
+        if height == 0 || !parent_of_match {
+            let hash = self.hash(height, position, txids);
+            self.hashes.push(hash);
+        } else {
+            self.build(height - 1, position * 2, txids, matches);
+            if position * 2 + 1 < self.width(height - 1) {
+                self.build(height - 1, position * 2 + 1, txids, matches);
+            }
+        }
+    }
+
+    fn extract(&self, height: u32, position: u32, walk: &mut Walk) -> Result<[u8; 32], Error> {
+        let parent_of_match = *self
+            .bits
+            .get(walk.bits_used)
+            .ok_or_else(|| anyhow::anyhow!("merkle block runs out of bits"))?;
+        walk.bits_used += 1;
+        if height == 0 || !parent_of_match {
+            let hash = *self
+                .hashes
+                .get(walk.hashes_used)
+                .ok_or_else(|| anyhow::anyhow!("merkle block runs out of hashes"))?;
+            walk.hashes_used += 1;
+            if height == 0 && parent_of_match {
+                walk.matches.push((position, hash));
+            }
+            return Ok(hash);
+        }
+        let left = self.extract(height - 1, position * 2, walk)?;
+        let right = if position * 2 + 1 < self.width(height - 1) {
+            let right = self.extract(height - 1, position * 2 + 1, walk)?;
+            // Identical children would let duplicated transactions keep the root
+            // (CVE-2012-2459)
+            if right == left {
+                return Err(anyhow::anyhow!("merkle block with identical siblings").into());
+            }
+            right
+        } else {
+            left
+        };
+        Ok(double_sha256(&[left, right].concat()))
+    }
+}
+
+/// How far a walk of a partial merkle tree got.
+#[derive(Default)]
+struct Walk {
+    bits_used: usize,
+    hashes_used: usize,
+    matches: Matches,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::messages::block::merkle_root;
+
+    #[test]
+    fn test_partial_merkle_tree() {
+        for count in [1u32, 2, 3, 7, 16, 17, 100] {
+            let txids: Vec<[u8; 32]> = (0..count)
+                .map(|i| double_sha256(&i.to_le_bytes()))
+                .collect();
+            let (root, _) = merkle_root(txids.clone());
+            let matches: Vec<bool> = (0..count).map(|i| i % 5 == 3).collect();
+            let tree = PartialMerkleTree::new(&txids, &matches);
+            let expected: Vec<(u32, [u8; 32])> = (0..count)
+                .filter(|i| i % 5 == 3)
+                .map(|i| (i, txids[i as usize]))
+                .collect();
+            assert_eq!(tree.extract_matches().unwrap(), (root, expected.clone()));
+
+            let block = MerkleBlock {
+                header: BlockHeader {
+                    version: 4,
+                    prev_blockhash: [1; 32],
+                    merkle_root: root,
+                    time: 1_700_000_000,
+                    bits: 0x207f_ffff,
+                    nonce: 0,
+                },
+                tree,
+            };
+            // Parsed, the bits are padded to whole bytes
+            let parsed = MerkleBlock::try_from(&block.payload()[..]).unwrap();
+            assert_eq!(parsed.payload(), block.payload());
+            assert_eq!(parsed.matched_txids().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_rejects_malformed_trees() {
+        let txids: Vec<[u8; 32]> = (0..10u32)
+            .map(|i| double_sha256(&i.to_le_bytes()))
+            .collect();
+        let mut matches = vec![false; 10];
+        matches[4] = true;
+        let tree = PartialMerkleTree::new(&txids, &matches);
+        let (root, _) = merkle_root(txids.clone());
+
+        // A changed hash changes the root
+        let mut changed = tree.clone();
+        changed.hashes[0][0] ^= 1;
+        assert_ne!(changed.extract_matches().unwrap().0, root);
+
+        let mut extra_hash = tree.clone();
+        extra_hash.hashes.push([0; 32]);
+        extra_hash.bits.push(false);
+        assert!(extra_hash.extract_matches().is_err());
+
+        let mut missing_bits = tree.clone();
+        missing_bits.bits.truncate(3);
+        assert!(missing_bits.extract_matches().is_err());
+
+        let mut empty = tree.clone();
+        empty.total_transactions = 0;
+        assert!(empty.extract_matches().is_err());
+
+        // The last of an odd number of transactions duplicated keeps the root, but is refused
+        let duplicated = [&txids[..9], &txids[8..9]].concat();
+        let mut matches = vec![false; 10];
+        matches[9] = true;
+        assert!(PartialMerkleTree::new(&duplicated, &matches)
+            .extract_matches()
+            .is_err());
+
+        let block = MerkleBlock {
+            header: BlockHeader {
+                version: 4,
+                prev_blockhash: [1; 32],
+                merkle_root: [2; 32],
+                time: 1_700_000_000,
+                bits: 0x207f_ffff,
+                nonce: 0,
+            },
+            tree,
+        };
+        assert!(block.matched_txids().is_err());
+    }
+}
@@ -3,13 +3,16 @@ use sha2::{Digest, Sha256};
 
 use crate::error::Error;
 
-use super::{verack::VerackMessageBuilder, version::VersionMessageBuilder, ToNetworkMessage};
+use super::{
+    feature::FeatureMessageBuilder, verack::VerackMessageBuilder, version::VersionMessageBuilder,
+    ToNetworkMessage,
+};
 
 /// Represents a message header for communication with Bitcoin nodes.
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 #[repr(C)]
 pub struct MessageHeader {
-    pub(super) magin_network_nr: [u8; 4],
+    pub magin_network_nr: [u8; 4],
     pub command: [u8; 12],
     pub payload_len: u32,
     pub(super) checksum: u32,
@@ -19,16 +22,40 @@ pub struct MessageHeader {
 pub enum Message {
     Version(VersionMessageBuilder),
     Verack(VerackMessageBuilder),
+    Feature(FeatureMessageBuilder),
 }
 
 /// Enum representing magic numbers for Bitcoin networks.
-#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum MessageMagicNumber {
+    #[default]
     Main,
     Testnet,
+    /// Testnet4, defined in BIP94.
+    Testnet4,
     Signet,
     Regtest,
+    /// A private network, e.g. a regtest fork, with its own magic and port.
+    Custom {
+        /// The magic bytes, configured as 8 hex digits.
+        #[serde(
+            serialize_with = "hex::serde::serialize",
+            deserialize_with = "deserialize_hex_magic"
+        )]
+        magic: [u8; 4],
+        /// The P2P port of the network.
+        port: u16,
+    },
+    /// A signet with a custom challenge. Its magic is derived from the challenge script.
+    CustomSignet {
+        /// The challenge script, configured as hex.
+        #[serde(with = "hex::serde")]
+        challenge: Vec<u8>,
+        /// The P2P port of the network, the default signet port if omitted.
+        #[serde(default)]
+        port: Option<u16>,
+    },
 }
 
 /// Enum representing different types of message commands.
@@ -36,8 +63,55 @@ pub enum MessageMagicNumber {
 pub enum MessageCommand {
     Version,
     Verack,
+    WtxidRelay,
+    SendAddrV2,
+    SendHeaders,
+    SendCmpct,
+    FeeFilter,
+    GetHeaders,
+    Headers,
+    Inv,
+    GetData,
+    NotFound,
+    Tx,
+    Block,
+    GetCFilters,
+    CFilter,
+    GetCFHeaders,
+    CFHeaders,
+    GetCFCheckpt,
+    CFCheckpt,
+    FilterLoad,
+    FilterAdd,
+    FilterClear,
+    MerkleBlock,
+    Reject,
 }
 
+const WTXIDRELAY: [u8; 12] = *b"wtxidrelay\0\0";
+const SENDADDRV2: [u8; 12] = *b"sendaddrv2\0\0";
+const SENDHEADERS: [u8; 12] = *b"sendheaders\0";
+const SENDCMPCT: [u8; 12] = *b"sendcmpct\0\0\0";
+const FEEFILTER: [u8; 12] = *b"feefilter\0\0\0";
+const GETHEADERS: [u8; 12] = *b"getheaders\0\0";
+const HEADERS: [u8; 12] = *b"headers\0\0\0\0\0";
+const INV: [u8; 12] = *b"inv\0\0\0\0\0\0\0\0\0";
+const GETDATA: [u8; 12] = *b"getdata\0\0\0\0\0";
+const NOTFOUND: [u8; 12] = *b"notfound\0\0\0\0";
+const TX: [u8; 12] = *b"tx\0\0\0\0\0\0\0\0\0\0";
+const BLOCK: [u8; 12] = *b"block\0\0\0\0\0\0\0";
+const GETCFILTERS: [u8; 12] = *b"getcfilters\0";
+const CFILTER: [u8; 12] = *b"cfilter\0\0\0\0\0";
+const GETCFHEADERS: [u8; 12] = *b"getcfheaders";
+const CFHEADERS: [u8; 12] = *b"cfheaders\0\0\0";
+const GETCFCHECKPT: [u8; 12] = *b"getcfcheckpt";
+const CFCHECKPT: [u8; 12] = *b"cfcheckpt\0\0\0";
+const FILTERLOAD: [u8; 12] = *b"filterload\0\0";
+const FILTERADD: [u8; 12] = *b"filteradd\0\0\0";
+const FILTERCLEAR: [u8; 12] = *b"filterclear\0";
+const MERKLEBLOCK: [u8; 12] = *b"merkleblock\0";
+const REJECT: [u8; 12] = *b"reject\0\0\0\0\0\0";
+
 /// Converts a u16 to network byte order (big-endian).
 pub fn htons(u: u16) -> u16 {
     u.to_be()
@@ -48,15 +122,115 @@ pub fn htonl(u: u32) -> u32 {
     u.to_be()
 }
 
-/// Calculates the checksum for a payload. According to bitcoin spec
-/// checksum consist of 4 first byes of sha256(sha256(payload))
-pub fn calc_checksum(paylod: &[u8]) -> u32 {
+/// Calculates sha256(sha256(data)), the hash function used all over the bitcoin protocol.
+pub fn double_sha256(data: &[u8]) -> [u8; 32] {
     let mut hasher = Sha256::new();
-    hasher.update(paylod);
+    hasher.update(data);
     let result = hasher.finalize();
     let mut hasher = Sha256::new();
     hasher.update(result.as_slice());
-    let result = hasher.finalize();
+    hasher.finalize().into()
+}
+
+/// Formats a hash in internal byte order the way block explorers display it, byte-reversed.
+pub fn hash_to_hex(hash: &[u8; 32]) -> String {
+    hash.iter()
+        .rev()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Parses a hash displayed the way block explorers do, byte-reversed, into internal byte order.
+pub fn hash_from_hex(hex: &str) -> Result<[u8; 32], Error> {
+    let mut hash: [u8; 32] = hex::decode(hex.trim())
+        .map_err(anyhow::Error::from)?
+        .try_into()
+        .map_err(|bytes: Vec<u8>| anyhow::anyhow!("a hash is 32 bytes, got {}", bytes.len()))?;
+    hash.reverse();
+    Ok(hash)
+}
+
+/// Encodes a length as a bitcoin CompactSize integer.
+pub fn compact_size(len: u64) -> Vec<u8> {
+    match len {
+        0..=0xFC => vec![len as u8],
+        0xFD..=0xFFFF => [&[0xFD][..], &(len as u16).to_le_bytes()].concat(),
+        0x10000..=0xFFFF_FFFF => [&[0xFE][..], &(len as u32).to_le_bytes()].concat(),
+        _ => [&[0xFF][..], &len.to_le_bytes()].concat(),
+    }
+}
+
+/// Reads the fields of a payload front to back.
+pub(crate) struct PayloadCursor<'a>(pub(crate) &'a [u8]);
+
+impl<'a> PayloadCursor<'a> {
+    pub(crate) fn take_slice(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.0.len() < len {
+            return Err(anyhow::anyhow!("payload truncated").into());
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    pub(crate) fn take<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let mut bytes = [0u8; N];
+        bytes.copy_from_slice(self.take_slice(N)?);
+        Ok(bytes)
+    }
+
+    /// Reads a CompactSize integer, rejecting non-canonical encodings like Bitcoin Core.
+    pub(crate) fn compact_size(&mut self) -> Result<usize, Error> {
+        let (value, min) = match self.take::<1>()?[0] {
+            0xFD => (u64::from(u16::from_le_bytes(self.take()?)), 0xFD),
+            0xFE => (u64::from(u32::from_le_bytes(self.take()?)), 0x10000),
+            0xFF => (u64::from_le_bytes(self.take()?), 0x1_0000_0000),
+            len => return Ok(len as usize),
+        };
+        if value < min {
+            return Err(anyhow::anyhow!("non-canonical CompactSize {value}").into());
+        }
+        Ok(value as usize)
+    }
+}
+
+/// Builds a frame for any command, including those without a builder of their own.
+///
+/// # Arguments
+///
+/// * `network` - The network the frame is sent on.
+/// * `command` - The command name, at most 12 ASCII characters, e.g. `pong`.
+/// * `payload` - The serialized payload.
+pub fn build_frame(
+    network: &MessageMagicNumber,
+    command: &str,
+    payload: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let mut name = [0u8; 12];
+    if command.len() > name.len() {
+        return Err(anyhow::anyhow!("command name too long: {command}").into());
+    }
+    name[..command.len()].copy_from_slice(command.as_bytes());
+    let header = MessageHeader {
+        magin_network_nr: network.params().magic,
+        command: name,
+        payload_len: payload.len() as u32,
+        checksum: calc_checksum(payload),
+    };
+    Ok([&bincode::serialize(&header)?[..], payload].concat())
+}
+
+/// Returns the name of a command as sent on the wire, without the NUL padding.
+pub fn command_name(command: &[u8; 12]) -> String {
+    String::from_utf8_lossy(command)
+        .trim_end_matches('\0')
+        .to_owned()
+}
+
+/// Calculates the checksum for a payload. According to bitcoin spec
+/// checksum consist of 4 first byes of sha256(sha256(payload))
+pub fn calc_checksum(paylod: &[u8]) -> u32 {
+    let result = double_sha256(paylod);
 
     htonl(
         ((result[0] as u32) << 24)
@@ -66,11 +240,33 @@ pub fn calc_checksum(paylod: &[u8]) -> u32 {
     )
 }
 
+/// Deserializes magic bytes written as 8 hex digits, e.g. `"fabfb5da"`.
+fn deserialize_hex_magic<'de, D>(deserializer: D) -> Result<[u8; 4], D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let magic: String = Deserialize::deserialize(deserializer)?;
+    let mut bytes = [0u8; 4];
+    hex::decode_to_slice(magic, &mut bytes).map_err(serde::de::Error::custom)?;
+    Ok(bytes)
+}
+
+impl MessageHeader {
+    /// Returns whether the checksum in the header matches `payload`.
+    pub fn checksum_matches(&self, payload: &[u8]) -> bool {
+        self.checksum == calc_checksum(payload)
+    }
+}
+
 impl TryFrom<&[u8]> for MessageHeader {
     type Error = Error;
 
+    /// Parses the header at the start of `buf`, which must hold at least 24 bytes.
     fn try_from(buf: &[u8]) -> Result<Self, Self::Error> {
-        Ok(bincode::deserialize(&buf[0..24])?)
+        let header = buf
+            .get(..24)
+            .ok_or_else(|| anyhow::anyhow!("header needs 24 bytes, got {}", buf.len()))?;
+        Ok(bincode::deserialize(header)?)
     }
 }
 
@@ -85,6 +281,7 @@ impl ToNetworkMessage for Message {
                 let btc_message: SerializedBitcoinMessage = verack_message.try_into()?;
                 Ok(btc_message.to_network_message()?)
             }
+            Message::Feature(feature_message) => feature_message.to_frame(),
         }
     }
 }
@@ -107,6 +304,29 @@ impl TryFrom<[u8; 12]> for MessageCommand {
             [0x76, 0x65, 0x72, 0x61, 0x63, 0x6B, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00] => {
                 Ok(Self::Verack)
             }
+            WTXIDRELAY => Ok(Self::WtxidRelay),
+            SENDADDRV2 => Ok(Self::SendAddrV2),
+            SENDHEADERS => Ok(Self::SendHeaders),
+            SENDCMPCT => Ok(Self::SendCmpct),
+            FEEFILTER => Ok(Self::FeeFilter),
+            GETHEADERS => Ok(Self::GetHeaders),
+            HEADERS => Ok(Self::Headers),
+            INV => Ok(Self::Inv),
+            GETDATA => Ok(Self::GetData),
+            NOTFOUND => Ok(Self::NotFound),
+            TX => Ok(Self::Tx),
+            BLOCK => Ok(Self::Block),
+            GETCFILTERS => Ok(Self::GetCFilters),
+            CFILTER => Ok(Self::CFilter),
+            GETCFHEADERS => Ok(Self::GetCFHeaders),
+            CFHEADERS => Ok(Self::CFHeaders),
+            GETCFCHECKPT => Ok(Self::GetCFCheckpt),
+            CFCHECKPT => Ok(Self::CFCheckpt),
+            FILTERLOAD => Ok(Self::FilterLoad),
+            FILTERADD => Ok(Self::FilterAdd),
+            FILTERCLEAR => Ok(Self::FilterClear),
+            MERKLEBLOCK => Ok(Self::MerkleBlock),
+            REJECT => Ok(Self::Reject),
             _ => Err(Error::Unexpected(anyhow::anyhow!("Unexpected message"))),
         }
     }
@@ -114,12 +334,29 @@ impl TryFrom<[u8; 12]> for MessageCommand {
 
 impl From<MessageMagicNumber> for [u8; 4] {
     fn from(value: MessageMagicNumber) -> Self {
-        match value {
-            MessageMagicNumber::Main => [0xF9, 0xBE, 0xB4, 0xD9],
-            MessageMagicNumber::Testnet => [0x0B, 0x11, 0x09, 0x07],
-            MessageMagicNumber::Signet => [0x0A, 0x03, 0xCF, 0x40],
-            MessageMagicNumber::Regtest => [0xFA, 0xBF, 0xB5, 0xDA],
-        }
+        value.params().magic
+    }
+}
+
+impl MessageMagicNumber {
+    /// The well-known networks, i.e. every network that can be detected from its magic.
+    pub const KNOWN: [MessageMagicNumber; 5] = [
+        MessageMagicNumber::Main,
+        MessageMagicNumber::Testnet,
+        MessageMagicNumber::Testnet4,
+        MessageMagicNumber::Signet,
+        MessageMagicNumber::Regtest,
+    ];
+}
+
+impl TryFrom<[u8; 4]> for MessageMagicNumber {
+    type Error = Error;
+
+    fn try_from(value: [u8; 4]) -> Result<Self, Self::Error> {
+        MessageMagicNumber::KNOWN
+            .into_iter()
+            .find(|network| network.params().magic == value)
+            .ok_or_else(|| Error::Unexpected(anyhow::anyhow!("Unknown magic: {:02x?}", value)))
     }
 }
 
@@ -132,6 +369,29 @@ impl From<MessageCommand> for [u8; 12] {
             MessageCommand::Verack => [
                 0x76, 0x65, 0x72, 0x61, 0x63, 0x6B, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
             ],
+            MessageCommand::WtxidRelay => WTXIDRELAY,
+            MessageCommand::SendAddrV2 => SENDADDRV2,
+            MessageCommand::SendHeaders => SENDHEADERS,
+            MessageCommand::SendCmpct => SENDCMPCT,
+            MessageCommand::FeeFilter => FEEFILTER,
+            MessageCommand::GetHeaders => GETHEADERS,
+            MessageCommand::Headers => HEADERS,
+            MessageCommand::Inv => INV,
+            MessageCommand::GetData => GETDATA,
+            MessageCommand::NotFound => NOTFOUND,
+            MessageCommand::Tx => TX,
+            MessageCommand::Block => BLOCK,
+            MessageCommand::GetCFilters => GETCFILTERS,
+            MessageCommand::CFilter => CFILTER,
+            MessageCommand::GetCFHeaders => GETCFHEADERS,
+            MessageCommand::CFHeaders => CFHEADERS,
+            MessageCommand::GetCFCheckpt => GETCFCHECKPT,
+            MessageCommand::CFCheckpt => CFCHECKPT,
+            MessageCommand::FilterLoad => FILTERLOAD,
+            MessageCommand::FilterAdd => FILTERADD,
+            MessageCommand::FilterClear => FILTERCLEAR,
+            MessageCommand::MerkleBlock => MERKLEBLOCK,
+            MessageCommand::Reject => REJECT,
         }
     }
 }
@@ -173,5 +433,31 @@ mod test {
         let message_header: MessageHeader = verack_hex[0..24].try_into().unwrap();
         let command: MessageCommand = message_header.command.try_into().unwrap();
         assert_eq!(MessageCommand::Verack, command);
+        let network: MessageMagicNumber = message_header.magin_network_nr.try_into().unwrap();
+        assert_eq!(MessageMagicNumber::Main, network);
+
+        assert!(MessageHeader::try_from(&verack_hex[..23]).is_err());
+    }
+
+    #[test]
+    fn test_build_frame() {
+        let verack = build_frame(&MessageMagicNumber::Main, "verack", &[]).unwrap();
+        let expected: Vec<u8> =
+            Message::Verack(VerackMessageBuilder::new(MessageMagicNumber::Main))
+                .to_network_message()
+                .unwrap();
+        assert_eq!(verack, expected);
+        let header: MessageHeader = verack[0..24].try_into().unwrap();
+        assert_eq!(command_name(&header.command), "verack");
+        assert!(build_frame(&MessageMagicNumber::Main, "thirteen_char", &[]).is_err());
+    }
+
+    #[test]
+    fn test_magic_round_trip() {
+        for network in MessageMagicNumber::KNOWN {
+            let magic: [u8; 4] = network.clone().into();
+            assert_eq!(MessageMagicNumber::try_from(magic).unwrap(), network);
+        }
+        assert!(MessageMagicNumber::try_from([0x53, 0x53, 0x48, 0x2D]).is_err());
     }
 }
@@ -1,6 +1,15 @@
 use crate::error::Error;
 
+pub mod block;
+pub mod bloom;
+pub mod feature;
+pub mod filter;
+pub mod headers;
+pub mod inventory;
+pub mod merkle_block;
 pub mod message;
+pub mod reject;
+pub mod transaction;
 pub mod verack;
 pub mod version;
 
@@ -0,0 +1,93 @@
+use serde::Serialize;
+
+use crate::error::Error;
+
+use super::message::{compact_size, hash_to_hex, PayloadCursor};
+
+/// A `reject` message (BIP61), telling why a message was refused. Bitcoin Core stopped sending
+/// them in version 0.20, other implementations still do.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Reject {
+    /// The command of the message rejected, e.g. `tx`.
+    pub message: String,
+    /// The reason code, e.g. `0x10` for an invalid object.
+    pub code: u8,
+    /// The reason, e.g. `bad-txns-inputs-missingorspent`.
+    pub reason: String,
+    /// The hash of the transaction or block rejected, in internal byte order.
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_hash"
+    )]
+    pub hash: Option<[u8; 32]>,
+}
+
+impl Reject {
+    /// Serializes the payload of the message.
+    pub fn payload(&self) -> Vec<u8> {
+        let mut payload = compact_size(self.message.len() as u64);
+        payload.extend_from_slice(self.message.as_bytes());
+        payload.push(self.code);
+        payload.extend(compact_size(self.reason.len() as u64));
+        payload.extend_from_slice(self.reason.as_bytes());
+        if let Some(hash) = &self.hash {
+            payload.extend_from_slice(hash);
+        }
+        payload
+    }
+}
+
+impl TryFrom<&[u8]> for Reject {
+    type Error = Error;
+
+    fn try_from(payload: &[u8]) -> Result<Self, Self::Error> {
+        let mut cursor = PayloadCursor(payload);
+        let len = cursor.compact_size()?;
+        let message = String::from_utf8_lossy(cursor.take_slice(len)?).into_owned();
+        let code = cursor.take::<1>()?[0];
+        let len = cursor.compact_size()?;
+        let reason = String::from_utf8_lossy(cursor.take_slice(len)?).into_owned();
+        // Only rejections of transactions and blocks carry a hash
+        let hash = cursor.take().ok();
+        Ok(Self {
+            message,
+            code,
+            reason,
+            hash,
+        })
+    }
+}
+
+fn serialize_hash<S>(hash: &Option<[u8; 32]>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    match hash {
+        Some(hash) => serializer.serialize_str(&hash_to_hex(hash)),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reject_round_trip() {
+        let reject = Reject {
+            message: "tx".to_owned(),
+            code: 0x10,
+            reason: "bad-txns-inputs-missingorspent".to_owned(),
+            hash: Some([7; 32]),
+        };
+        assert_eq!(Reject::try_from(&reject.payload()[..]).unwrap(), reject);
+        let version = Reject {
+            message: "version".to_owned(),
+            code: 0x11,
+            reason: "obsolete".to_owned(),
+            hash: None,
+        };
+        assert_eq!(Reject::try_from(&version.payload()[..]).unwrap(), version);
+        assert!(Reject::try_from(&reject.payload()[..3]).is_err());
+    }
+}
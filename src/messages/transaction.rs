@@ -0,0 +1,269 @@
+use crate::error::Error;
+
+use super::message::{compact_size, double_sha256, PayloadCursor};
+
+/// The scale of the weight of the bytes outside of witnesses (BIP141).
+pub const WITNESS_SCALE_FACTOR: usize = 4;
+
+/// A reference to an output of a previous transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OutPoint {
+    /// The txid of the transaction, in internal byte order.
+    pub txid: [u8; 32],
+    /// The index of the output in the transaction.
+    pub vout: u32,
+}
+
+/// An input of a transaction, spending a previous output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxIn {
+    /// The output spent.
+    pub previous_output: OutPoint,
+    /// The script satisfying the conditions of the output, empty for segwit outputs.
+    pub script_sig: Vec<u8>,
+    /// The sequence number, used by relative lock times and replace-by-fee.
+    pub sequence: u32,
+    /// The witness stack, empty for legacy inputs.
+    pub witness: Vec<Vec<u8>>,
+}
+
+/// An output of a transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxOut {
+    /// The amount, in satoshis.
+    pub value: u64,
+    /// The conditions to spend the output.
+    pub script_pubkey: Vec<u8>,
+}
+
+/// A Bitcoin transaction, with the witnesses of its inputs if it has any.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transaction {
+    /// The transaction version.
+    pub version: i32,
+    /// The inputs.
+    pub input: Vec<TxIn>,
+    /// The outputs.
+    pub output: Vec<TxOut>,
+    /// The block height or time before which the transaction cannot be mined.
+    pub lock_time: u32,
+}
+
+impl OutPoint {
+    /// The outpoint spent by the input of a coinbase transaction.
+    pub const NULL: OutPoint = OutPoint {
+        txid: [0; 32],
+        vout: u32::MAX,
+    };
+}
+
+impl Transaction {
+    /// Returns the hash identifying the transaction, which does not cover the witnesses, in
+    /// internal byte order.
+    pub fn txid(&self) -> [u8; 32] {
+        double_sha256(&self.serialize_without_witness())
+    }
+
+    /// Returns the hash of the transaction including its witnesses (BIP141), in internal byte
+    /// order. It is the txid for transactions without witnesses.
+    pub fn wtxid(&self) -> [u8; 32] {
+        double_sha256(&self.serialize())
+    }
+
+    /// Returns whether the transaction is a coinbase: its only input spends the null outpoint.
+    pub fn is_coinbase(&self) -> bool {
+        self.input.len() == 1 && self.input[0].previous_output == OutPoint::NULL
+    }
+
+    /// Returns whether any input has a witness.
+    pub fn has_witness(&self) -> bool {
+        self.input.iter().any(|input| !input.witness.is_empty())
+    }
+
+    /// Serializes the transaction, in the segwit format if it has witnesses.
+    pub fn serialize(&self) -> Vec<u8> {
+        self.encode(self.has_witness())
+    }
+
+    /// Serializes the transaction in the legacy format, as hashed into the txid.
+    pub fn serialize_without_witness(&self) -> Vec<u8> {
+        self.encode(false)
+    }
+
+    /// Returns the size of the serialized transaction, witnesses included.
+    pub fn size(&self) -> usize {
+        self.serialize().len()
+    }
+
+    /// Returns the weight of the transaction (BIP141): 4 weight units for every byte outside
+    /// of the witnesses and 1 for every byte of them.
+    pub fn weight(&self) -> usize {
+        self.serialize_without_witness().len() * (WITNESS_SCALE_FACTOR - 1) + self.size()
+    }
+
+    /// Returns the virtual size of the transaction, its weight divided by 4 and rounded up.
+    pub fn vsize(&self) -> usize {
+        self.weight().div_ceil(WITNESS_SCALE_FACTOR)
+    }
+
+    fn encode(&self, witness: bool) -> Vec<u8> {
+        let mut bytes = self.version.to_le_bytes().to_vec();
+        if witness {
+            // The marker, an empty input list to legacy parsers, then the flag
+            bytes.extend_from_slice(&[0x00, 0x01]);
+        }
+        bytes.extend(compact_size(self.input.len() as u64));
+        for input in &self.input {
+            bytes.extend_from_slice(&input.previous_output.txid);
+            bytes.extend_from_slice(&input.previous_output.vout.to_le_bytes());
+            bytes.extend(compact_size(input.script_sig.len() as u64));
+            bytes.extend_from_slice(&input.script_sig);
+            bytes.extend_from_slice(&input.sequence.to_le_bytes());
+        }
+        bytes.extend(compact_size(self.output.len() as u64));
+        for output in &self.output {
+            bytes.extend_from_slice(&output.value.to_le_bytes());
+            bytes.extend(compact_size(output.script_pubkey.len() as u64));
+            bytes.extend_from_slice(&output.script_pubkey);
+        }
+        if witness {
+            for input in &self.input {
+                bytes.extend(compact_size(input.witness.len() as u64));
+                for item in &input.witness {
+                    bytes.extend(compact_size(item.len() as u64));
+                    bytes.extend_from_slice(item);
+                }
+            }
+        }
+        bytes.extend_from_slice(&self.lock_time.to_le_bytes());
+        bytes
+    }
+
+    /// Reads a transaction from the front of `cursor`, in either format.
+    pub(crate) fn read(cursor: &mut PayloadCursor) -> Result<Self, Error> {
+        let version = i32::from_le_bytes(cursor.take()?);
+        let mut inputs = cursor.compact_size()?;
+        // An empty input list is the segwit marker, followed by the flag
+        let segwit = inputs == 0;
+        if segwit {
+            let flag = cursor.take::<1>()?[0];
+            if flag != 1 {
+                return Err(anyhow::anyhow!("unknown segwit flag {flag}").into());
+            }
+            inputs = cursor.compact_size()?;
+        }
+        let mut input = Vec::new();
+        for _ in 0..inputs {
+            let txid = cursor.take()?;
+            let vout = u32::from_le_bytes(cursor.take()?);
+            let len = cursor.compact_size()?;
+            input.push(TxIn {
+                previous_output: OutPoint { txid, vout },
+                script_sig: cursor.take_slice(len)?.to_vec(),
+                sequence: u32::from_le_bytes(cursor.take()?),
+                witness: Vec::new(),
+            });
+        }
+        let mut output = Vec::new();
+        for _ in 0..cursor.compact_size()? {
+            let value = u64::from_le_bytes(cursor.take()?);
+            let len = cursor.compact_size()?;
+            output.push(TxOut {
+                value,
+                script_pubkey: cursor.take_slice(len)?.to_vec(),
+            });
+        }
+        if segwit {
+            for input in &mut input {
+                for _ in 0..cursor.compact_size()? {
+                    let len = cursor.compact_size()?;
+                    input.witness.push(cursor.take_slice(len)?.to_vec());
+                }
+            }
+            // Like Bitcoin Core, so that every transaction has a single serialization
+            if !input.iter().any(|input| !input.witness.is_empty()) {
+                return Err(anyhow::anyhow!("segwit transaction without witnesses").into());
+            }
+        }
+        Ok(Self {
+            version,
+            input,
+            output,
+            lock_time: u32::from_le_bytes(cursor.take()?),
+        })
+    }
+}
+
+impl TryFrom<&[u8]> for Transaction {
+    type Error = Error;
+
+    /// Parses a transaction, which must span all of `bytes`.
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        let mut cursor = PayloadCursor(bytes);
+        let transaction = Self::read(&mut cursor)?;
+        if !cursor.0.is_empty() {
+            return Err(anyhow::anyhow!("{} bytes after the transaction", cursor.0.len()).into());
+        }
+        Ok(transaction)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::messages::message::hash_to_hex;
+
+    /// The signed transaction of the native P2WPKH example of BIP143.
+    pub(crate) const SEGWIT_TX: &str = "01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac000247304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee635711000000";
+
+    #[test]
+    fn test_segwit_transaction() {
+        let bytes = hex::decode(SEGWIT_TX).unwrap();
+        let transaction = Transaction::try_from(&bytes[..]).unwrap();
+        assert_eq!(transaction.input.len(), 2);
+        assert_eq!(transaction.output.len(), 2);
+        assert_eq!(transaction.output[0].value, 112_340_000);
+        assert_eq!(transaction.lock_time, 17);
+        // The first input is legacy, the second one P2WPKH
+        assert!(transaction.input[0].witness.is_empty());
+        assert_eq!(transaction.input[1].witness.len(), 2);
+        assert!(!transaction.is_coinbase());
+
+        assert_eq!(transaction.serialize(), bytes);
+        assert_eq!(
+            hash_to_hex(&transaction.txid()),
+            "e8151a2af31c368a35053ddd4bdb285a8595c769a3ad83e0fa02314a602d4609"
+        );
+        assert_eq!(
+            hash_to_hex(&transaction.wtxid()),
+            "c36c38370907df2324d9ce9d149d191192f338b37665a82e78e76a12c909b762"
+        );
+        assert_eq!(transaction.size(), 343);
+        assert_eq!(transaction.weight(), 233 * 3 + 343);
+        assert_eq!(transaction.vsize(), 261);
+
+        // Without its witnesses, the same transaction is a legacy one
+        let mut legacy = transaction.clone();
+        legacy.input[1].witness.clear();
+        assert_eq!(legacy.txid(), transaction.txid());
+        assert_eq!(legacy.wtxid(), legacy.txid());
+        assert_eq!(
+            Transaction::try_from(&legacy.serialize()[..]).unwrap(),
+            legacy
+        );
+    }
+
+    #[test]
+    fn test_malformed_transactions() {
+        let bytes = hex::decode(SEGWIT_TX).unwrap();
+        assert!(Transaction::try_from(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Transaction::try_from(&[&bytes[..], &[0]].concat()[..]).is_err());
+        let mut bad_flag = bytes.clone();
+        bad_flag[5] = 2;
+        assert!(Transaction::try_from(&bad_flag[..]).is_err());
+        // The segwit marker and flag with empty witnesses
+        let mut superfluous = bytes[..231].to_vec();
+        superfluous.extend_from_slice(&[0, 0, 0x11, 0, 0, 0]);
+        assert!(Transaction::try_from(&superfluous[..]).is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 
 use serde::{Deserialize, Serialize};
 
@@ -6,12 +6,26 @@ use crate::error::Error;
 
 use super::{
     message::{
-        calc_checksum, htons, MessageCommand, MessageHeader, MessageMagicNumber,
-        SerializedBitcoinMessage,
+        calc_checksum, compact_size, htons, MessageCommand, MessageHeader, MessageMagicNumber,
+        PayloadCursor, SerializedBitcoinMessage,
     },
     ToNetworkMessage,
 };
 
+/// The protocol version announced in our version message.
+pub const PROTOCOL_VERSION: i32 = 70001;
+
+/// Service bit of peers serving the whole block chain.
+pub const NODE_NETWORK: u64 = 1;
+/// Service bit of peers accepting bloom filters (BIP111).
+pub const NODE_BLOOM: u64 = 1 << 2;
+/// Service bit of peers serving blocks and transactions with their witnesses (BIP144).
+pub const NODE_WITNESS: u64 = 1 << 3;
+/// Service bit of peers serving compact block filters (BIP157).
+pub const NODE_COMPACT_FILTERS: u64 = 1 << 6;
+/// Service bit of pruned peers, which serve the last 288 blocks only (BIP159).
+pub const NODE_NETWORK_LIMITED: u64 = 1 << 10;
+
 /// Represents a builder for creating a Version message.
 pub struct VersionMessageBuilder {
     /// The magic number for the Bitcoin network.
@@ -20,6 +34,8 @@ pub struct VersionMessageBuilder {
     pub command: MessageCommand,
     /// The Bitcoin protocol version.
     pub version: i32,
+    /// The services announced to the peer, e.g. `NODE_NETWORK`.
+    pub services: u64,
     /// The timestamp of the message.
     pub timestamp: i64,
     /// The receiving address.
@@ -28,14 +44,16 @@ pub struct VersionMessageBuilder {
     pub addr_from: SocketAddr,
     /// A random nonce.
     pub nonce: u64,
+    /// The user agent announced to the peer.
+    pub user_agent: String,
+    /// Whether the peer should announce transactions to us (BIP37).
+    pub relay: bool,
 }
 
 impl VersionMessageBuilder {
-    /// The User Agent in this case is set to "emil-handshake", which is a predefined string.
-    /// This application does not allow for customization of the User Agent.
-    const UA: [u8; 14] = [
-        0x65, 0x6D, 0x69, 0x6C, 0x2D, 0x68, 0x61, 0x6E, 0x64, 0x73, 0x68, 0x61, 0x6B, 0x65,
-    ];
+    /// The User Agent announced unless another one is set with `with_user_agent`.
+    pub const DEFAULT_USER_AGENT: &'static str = "emil-handshake";
+
     /// Creates a new instance of `VersionMessageBuilder`.
     ///
     /// # Arguments
@@ -53,13 +71,56 @@ impl VersionMessageBuilder {
         Self {
             magic_number,
             command: MessageCommand::Version,
-            version: 70001,
+            version: PROTOCOL_VERSION,
+            services: 0,
             timestamp,
             addr_recv,
             addr_from: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0),
             nonce,
+            user_agent: Self::DEFAULT_USER_AGENT.to_owned(),
+            relay: false,
         }
     }
+
+    /// Sets the protocol version announced to the peer.
+    ///
+    /// # Arguments
+    ///
+    /// * `version` - The protocol version, e.g. `70016`.
+    pub fn with_version(mut self, version: i32) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Sets the services announced to the peer, none unless set.
+    ///
+    /// # Arguments
+    ///
+    /// * `services` - The service bits, e.g. `NODE_NETWORK | NODE_WITNESS`.
+    pub fn with_services(mut self, services: u64) -> Self {
+        self.services = services;
+        self
+    }
+
+    /// Sets the user agent announced to the peer, e.g. `/handshaker:0.1.0/`.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_agent` - The user agent.
+    pub fn with_user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = user_agent.into();
+        self
+    }
+
+    /// Sets whether the peer should announce transactions to us, off unless set.
+    ///
+    /// # Arguments
+    ///
+    /// * `relay` - The relay flag.
+    pub fn with_relay(mut self, relay: bool) -> Self {
+        self.relay = relay;
+        self
+    }
 }
 
 impl TryFrom<VersionMessageBuilder> for SerializedBitcoinMessage {
@@ -71,17 +132,19 @@ impl TryFrom<VersionMessageBuilder> for SerializedBitcoinMessage {
 
         let message = VersionMessage {
             version: value.version,
-            services: 0,
+            services: value.services,
             timestamp: value.timestamp,
             recv_add,
             addr_from,
             nonce: value.nonce,
-            ua_len: VersionMessageBuilder::UA.len() as u8,
-            user_agent: VersionMessageBuilder::UA,
-            start_height: 0,
-            relay: false,
         };
-        let serialized_payload = message.to_network_message()?;
+        let mut serialized_payload = message.to_network_message()?;
+        // The user agent has a variable length, so the rest doesn't fit the struct
+        serialized_payload.extend(compact_size(value.user_agent.len() as u64));
+        serialized_payload.extend_from_slice(value.user_agent.as_bytes());
+        // Start height
+        serialized_payload.extend_from_slice(&0i32.to_le_bytes());
+        serialized_payload.push(value.relay as u8);
         let header = MessageHeader {
             magin_network_nr: value.magic_number.into(),
             command: value.command.into(),
@@ -115,6 +178,80 @@ impl From<SocketAddr> for NetworkAddress {
     }
 }
 
+/// The fields of a version message received from a peer.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct PeerVersion {
+    /// The protocol version of the peer.
+    pub version: i32,
+    /// The services the peer offers.
+    pub services: u64,
+    /// The peer's clock, in seconds since the Unix epoch.
+    pub timestamp: i64,
+    /// Our address as seen by the peer.
+    #[serde(rename = "our_address")]
+    pub addr_recv: SocketAddr,
+    /// The nonce the peer uses to detect connections to itself.
+    #[serde(skip)]
+    pub nonce: u64,
+    /// The user agent of the peer, e.g. `/Satoshi:27.0.0/`.
+    pub user_agent: String,
+    /// The height of the peer's best chain.
+    pub start_height: i32,
+    /// Whether the peer wants transactions relayed to it. Peers that omit the flag relay.
+    pub relay: bool,
+}
+
+impl TryFrom<&[u8]> for PeerVersion {
+    type Error = Error;
+
+    fn try_from(payload: &[u8]) -> Result<Self, Self::Error> {
+        let mut cursor = PayloadCursor(payload);
+        let version = i32::from_le_bytes(cursor.take()?);
+        let services = u64::from_le_bytes(cursor.take()?);
+        let timestamp = i64::from_le_bytes(cursor.take()?);
+        let addr_recv = cursor.network_address()?;
+        let _addr_from = cursor.network_address()?;
+        let nonce = u64::from_le_bytes(cursor.take()?);
+        let ua_len = cursor.compact_size()?;
+        let user_agent = String::from_utf8_lossy(cursor.take_slice(ua_len)?).into_owned();
+        let start_height = i32::from_le_bytes(cursor.take()?);
+        let relay = match cursor.0.first() {
+            Some(relay) => *relay != 0,
+            None => true,
+        };
+        Ok(Self {
+            version,
+            services,
+            timestamp,
+            addr_recv,
+            nonce,
+            user_agent,
+            start_height,
+            relay,
+        })
+    }
+}
+
+/// Reads the network addresses of version messages.
+trait NetworkAddressCursor {
+    /// Reads a network address without timestamp: services, IPv6 (or IPv4-mapped) address
+    /// and port in network byte order.
+    fn network_address(&mut self) -> Result<SocketAddr, Error>;
+}
+
+impl NetworkAddressCursor for PayloadCursor<'_> {
+    fn network_address(&mut self) -> Result<SocketAddr, Error> {
+        let _services: [u8; 8] = self.take()?;
+        let ip = Ipv6Addr::from(self.take::<16>()?);
+        let port = u16::from_be_bytes(self.take()?);
+        let ip = match ip.to_ipv4_mapped() {
+            Some(ip) => IpAddr::V4(ip),
+            None => IpAddr::V6(ip),
+        };
+        Ok(SocketAddr::new(ip, port))
+    }
+}
+
 #[derive(Serialize)]
 #[repr(C)]
 struct VersionMessage {
@@ -124,10 +261,6 @@ struct VersionMessage {
     recv_add: NetworkAddress,
     addr_from: NetworkAddress,
     nonce: u64,
-    ua_len: u8,
-    user_agent: [u8; 14], // Just hardoded, this app doesn't allow to set your own UA
-    start_height: i32,
-    relay: bool,
 }
 
 #[derive(Serialize, Deserialize, Copy, Clone)]
@@ -173,5 +306,29 @@ mod test {
         let btc_message: SerializedBitcoinMessage = version_builder.try_into().unwrap();
         let serialized_message: Vec<u8> = btc_message.to_network_message().unwrap();
         assert_eq!(serialized_message, expected_data);
+
+        let peer_version = PeerVersion::try_from(&expected_data[24..]).unwrap();
+        assert_eq!(peer_version.version, PROTOCOL_VERSION);
+        assert_eq!(peer_version.addr_recv, dest_address);
+        assert_eq!(peer_version.nonce, 0x6517e68c5db32e3b);
+        assert_eq!(peer_version.user_agent, "emil-handshake");
+        assert!(!peer_version.relay);
+        assert!(PeerVersion::try_from(&expected_data[24..100]).is_err());
+    }
+
+    #[test]
+    fn test_custom_user_agent() {
+        let version_builder = VersionMessageBuilder::new(
+            MessageMagicNumber::Main,
+            "127.0.0.1:8333".parse().unwrap(),
+            0,
+            0,
+        )
+        .with_user_agent("/handshaker:0.1.0/")
+        .with_relay(true);
+        let btc_message: SerializedBitcoinMessage = version_builder.try_into().unwrap();
+        let peer_version = PeerVersion::try_from(&btc_message.message[..]).unwrap();
+        assert_eq!(peer_version.user_agent, "/handshaker:0.1.0/");
+        assert!(peer_version.relay);
     }
 }
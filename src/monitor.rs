@@ -0,0 +1,49 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::info;
+
+use crate::{error::Error, handshake::Connection};
+
+/// A message received while monitoring a peer.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ObservedMessage {
+    /// When the message was received.
+    pub at: DateTime<Utc>,
+    /// The command name, e.g. `inv`.
+    pub command: String,
+    /// Length of the payload.
+    pub size: usize,
+}
+
+/// Reads messages from the peer until it disconnects, answering pings so the connection
+/// stays up. Every message, recognized or not, is passed to `observe`.
+///
+/// # Arguments
+///
+/// * `connection` - A connection whose handshake completed.
+/// * `observe` - Called with every message received.
+pub fn monitor(
+    connection: &mut Connection,
+    mut observe: impl FnMut(ObservedMessage),
+) -> Result<(), Error> {
+    loop {
+        match connection.reader.read_message() {
+            Ok(_) => {}
+            Err(Error::PeerDisconnected { .. }) => {
+                info!("peer disconnected");
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        }
+        let command = connection.reader.command_name();
+        if command == "ping" {
+            let nonce = connection.reader.payload().to_vec();
+            connection.send_raw("pong", &nonce)?;
+        }
+        observe(ObservedMessage {
+            at: Utc::now(),
+            command,
+            size: connection.reader.payload().len(),
+        });
+    }
+}
@@ -0,0 +1,347 @@
+use std::cmp::Ordering;
+
+use crate::chain_params::ChainParams;
+
+/// The time the blocks of a difficulty period should take, two weeks.
+pub const POW_TARGET_TIMESPAN: u32 = 14 * 24 * 60 * 60;
+/// The time a block should take, ten minutes.
+pub const POW_TARGET_SPACING: u32 = 10 * 60;
+/// The number of blocks between two retargets.
+pub const DIFFICULTY_ADJUSTMENT_INTERVAL: u32 = POW_TARGET_TIMESPAN / POW_TARGET_SPACING;
+
+/// A 256-bit unsigned integer, the size of hashes and proof-of-work targets.
+///
+/// The limbs are stored most significant first so that the derived ordering compares values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct U256([u64; 4]);
+
+impl U256 {
+    pub const ZERO: U256 = U256([0; 4]);
+
+    /// Reads a hash in internal byte order as a little-endian number.
+    pub fn from_le_bytes(bytes: [u8; 32]) -> Self {
+        let mut limbs = [0u64; 4];
+        for (i, chunk) in bytes.chunks_exact(8).enumerate() {
+            limbs[3 - i] = u64::from_le_bytes(chunk.try_into().expect("chunks of 8 bytes"));
+        }
+        U256(limbs)
+    }
+
+    /// Expands a compact target, as in Bitcoin Core's `arith_uint256::SetCompact`. Returns
+    /// `None` if the target is negative or overflows 256 bits.
+    ///
+    /// # Arguments
+    ///
+    /// * `bits` - The compact target: an exponent byte followed by a 23-bit mantissa and a sign
+    ///   bit.
+    pub fn from_compact(bits: u32) -> Option<Self> {
+        let size = bits >> 24;
+        let word = bits & 0x007F_FFFF;
+        if word != 0 && bits & 0x0080_0000 != 0 {
+            return None;
+        }
+        if word != 0 && (size > 34 || (word > 0xFF && size > 33) || (word > 0xFFFF && size > 32)) {
+            return None;
+        }
+        Some(if size <= 3 {
+            U256::from(u64::from(word >> (8 * (3 - size))))
+        } else {
+            U256::from(u64::from(word)) << (8 * (size - 3))
+        })
+    }
+
+    /// Converts the number to a compact target, as in Bitcoin Core's `GetCompact`, rounding
+    /// down to the 23 most significant bits.
+    pub fn to_compact(self) -> u32 {
+        let mut size = self.bits().div_ceil(8);
+        let mut compact = if size <= 3 {
+            (self.low_u64() << (8 * (3 - size))) as u32
+        } else {
+            (self >> (8 * (size - 3))).low_u64() as u32
+        };
+        // The mantissa is signed, move it right a byte if its top bit is set
+        if compact & 0x0080_0000 != 0 {
+            compact >>= 8;
+            size += 1;
+        }
+        compact | (size << 24)
+    }
+
+    /// Returns the number of bits needed to represent the number.
+    pub fn bits(&self) -> u32 {
+        for (i, limb) in self.0.iter().enumerate() {
+            if *limb != 0 {
+                return 64 * (4 - i as u32) - limb.leading_zeros();
+            }
+        }
+        0
+    }
+
+    /// Returns the lowest 64 bits of the number.
+    pub fn low_u64(&self) -> u64 {
+        self.0[3]
+    }
+
+    /// Multiplies by `rhs`, dropping the bits that overflow.
+    pub fn mul_u64(self, rhs: u64) -> Self {
+        let mut limbs = [0u64; 4];
+        let mut carry = 0u128;
+        for (limb, value) in limbs.iter_mut().zip(self.0).rev() {
+            let product = u128::from(value) * u128::from(rhs) + carry;
+            *limb = product as u64;
+            carry = product >> 64;
+        }
+        U256(limbs)
+    }
+
+    /// Divides by `rhs`, rounding down.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rhs` is zero.
+    pub fn div_u64(self, rhs: u64) -> Self {
+        let mut limbs = [0u64; 4];
+        let mut remainder = 0u128;
+        for (limb, value) in limbs.iter_mut().zip(self.0) {
+            let dividend = (remainder << 64) | u128::from(value);
+            *limb = (dividend / u128::from(rhs)) as u64;
+            remainder = dividend % u128::from(rhs);
+        }
+        U256(limbs)
+    }
+}
+
+impl From<u64> for U256 {
+    fn from(value: u64) -> Self {
+        U256([0, 0, 0, value])
+    }
+}
+
+impl std::ops::Add for U256 {
+    type Output = U256;
+
+    /// Adds, dropping the bits that overflow.
+    fn add(self, rhs: U256) -> U256 {
+        let mut limbs = [0u64; 4];
+        let mut carry = false;
+        for ((limb, a), b) in limbs.iter_mut().zip(self.0).zip(rhs.0).rev() {
+            let (sum, overflow) = a.overflowing_add(b);
+            let (sum, carried) = sum.overflowing_add(u64::from(carry));
+            *limb = sum;
+            carry = overflow || carried;
+        }
+        U256(limbs)
+    }
+}
+
+impl std::ops::Sub for U256 {
+    type Output = U256;
+
+    /// Subtracts, wrapping around below zero.
+    fn sub(self, rhs: U256) -> U256 {
+        self + !rhs + U256::from(1)
+    }
+}
+
+impl std::ops::Div for U256 {
+    type Output = U256;
+
+    /// Divides by `rhs`, rounding down, bit by bit.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rhs` is zero.
+    fn div(self, rhs: U256) -> U256 {
+        assert!(rhs != U256::ZERO, "division by zero");
+        let mut quotient = U256::ZERO;
+        let mut remainder = U256::ZERO;
+        for bit in (0..self.bits()).rev() {
+            remainder = remainder << 1;
+            if (self >> bit).low_u64() & 1 == 1 {
+                remainder.0[3] |= 1;
+            }
+            if remainder >= rhs {
+                remainder = remainder - rhs;
+                quotient.0[3 - bit as usize / 64] |= 1 << (bit % 64);
+            }
+        }
+        quotient
+    }
+}
+
+impl std::ops::Not for U256 {
+    type Output = U256;
+
+    fn not(self) -> U256 {
+        U256(self.0.map(|limb| !limb))
+    }
+}
+
+impl std::ops::Shl<u32> for U256 {
+    type Output = U256;
+
+    fn shl(self, shift: u32) -> U256 {
+        let mut limbs = [0u64; 4];
+        let (words, bits) = ((shift / 64) as usize, shift % 64);
+        for (i, limb) in limbs
+            .iter_mut()
+            .take(4usize.saturating_sub(words))
+            .enumerate()
+        {
+            let source = i + words;
+            *limb = self.0[source] << bits;
+            if bits > 0 && source + 1 < 4 {
+                *limb |= self.0[source + 1] >> (64 - bits);
+            }
+        }
+        U256(limbs)
+    }
+}
+
+impl std::ops::Shr<u32> for U256 {
+    type Output = U256;
+
+    fn shr(self, shift: u32) -> U256 {
+        let mut limbs = [0u64; 4];
+        let (words, bits) = ((shift / 64) as usize, shift % 64);
+        for (source, limb) in limbs.iter_mut().skip(words).enumerate() {
+            *limb = self.0[source] >> bits;
+            if bits > 0 && source > 0 {
+                *limb |= self.0[source - 1] << (64 - bits);
+            }
+        }
+        U256(limbs)
+    }
+}
+
+/// Returns whether `hash` meets the compact target `bits`, which must itself be valid and no
+/// easier than the limit of the network, as in Bitcoin Core's `CheckProofOfWork`.
+///
+/// # Arguments
+///
+/// * `hash` - The block hash, in internal byte order.
+/// * `bits` - The compact target of the block.
+/// * `params` - The parameters of the network.
+pub fn check_proof_of_work(hash: [u8; 32], bits: u32, params: &ChainParams) -> bool {
+    let limit = U256::from_compact(params.pow_limit).unwrap_or_default();
+    match U256::from_compact(bits) {
+        Some(target) if target != U256::ZERO && target <= limit => {
+            U256::from_le_bytes(hash).cmp(&target) != Ordering::Greater
+        }
+        _ => false,
+    }
+}
+
+/// Returns the expected number of hashes needed to mine a block with the compact target
+/// `bits`, as in Bitcoin Core's `GetBlockProof`: 2^256 / (target + 1), 0 for an invalid
+/// target.
+///
+/// # Arguments
+///
+/// * `bits` - The compact target of the block.
+pub fn block_proof(bits: u32) -> U256 {
+    match U256::from_compact(bits) {
+        // 2^256 does not fit, but (2^256 - target - 1) / (target + 1) + 1 does
+        Some(target) if target != U256::ZERO => !target / (target + U256::from(1)) + U256::from(1),
+        _ => U256::ZERO,
+    }
+}
+
+/// Computes the target of the first block of a difficulty period, as in Bitcoin Core's
+/// `CalculateNextWorkRequired`.
+///
+/// # Arguments
+///
+/// * `bits` - The target the period started from: the one of its last block, or of its first
+///   block with BIP94.
+/// * `first_block_time` - The time of the first block of the period.
+/// * `last_block_time` - The time of the last block of the period.
+/// * `params` - The parameters of the network.
+pub fn calculate_next_work_required(
+    bits: u32,
+    first_block_time: u32,
+    last_block_time: u32,
+    params: &ChainParams,
+) -> u32 {
+    if params.pow_no_retargeting {
+        return bits;
+    }
+    // Limit the adjustment to a factor of 4 either way
+    let timespan = (i64::from(last_block_time) - i64::from(first_block_time)).clamp(
+        i64::from(POW_TARGET_TIMESPAN / 4),
+        i64::from(POW_TARGET_TIMESPAN * 4),
+    );
+    let limit = U256::from_compact(params.pow_limit).unwrap_or_default();
+    let target = U256::from_compact(bits)
+        .unwrap_or_default()
+        .mul_u64(timespan as u64)
+        .div_u64(u64::from(POW_TARGET_TIMESPAN));
+    target.min(limit).to_compact()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::chain_params::{MAIN, REGTEST};
+
+    #[test]
+    fn test_compact_round_trip() {
+        let target = U256::from_compact(0x1d00ffff).unwrap();
+        assert_eq!(target, U256::from(0xffff) << 208);
+        assert_eq!(target.to_compact(), 0x1d00ffff);
+        assert_eq!(U256::from_compact(0x01123456).unwrap(), U256::from(0x12));
+        assert_eq!(U256::from_compact(0x04923456), None);
+        assert_eq!(U256::from_compact(0xff123456), None);
+        assert_eq!(U256::from(0x80).to_compact(), 0x02008000);
+        assert_eq!(U256::ZERO.to_compact(), 0);
+        assert_eq!((target >> 208) << 208, target);
+    }
+
+    #[test]
+    fn test_calculate_next_work_required() {
+        // Vectors of Bitcoin Core's pow_tests
+        let cases = [
+            (1261130161, 1262152739, 0x1d00ffff, 0x1d00d86a),
+            (1231006505, 1233061996, 0x1d00ffff, 0x1d00ffff),
+            (1279008237, 1279297671, 0x1c05a3f4, 0x1c0168fd),
+            (1263163443, 1269211443, 0x1c387f6f, 0x1d00e1fd),
+        ];
+        for (first, last, bits, expected) in cases {
+            assert_eq!(
+                calculate_next_work_required(bits, first, last, &MAIN),
+                expected
+            );
+        }
+        assert_eq!(
+            calculate_next_work_required(0x207fffff, 0, 1, &REGTEST),
+            0x207fffff
+        );
+    }
+
+    #[test]
+    fn test_block_proof() {
+        assert_eq!(block_proof(0x1d00ffff), U256::from(0x0001_0001_0001));
+        assert_eq!(block_proof(0x207fffff), U256::from(2));
+        assert_eq!(block_proof(0), U256::ZERO);
+        assert_eq!(block_proof(0x04923456), U256::ZERO);
+        assert_eq!(U256::from(u64::MAX) + U256::from(1), U256::from(1) << 64);
+        assert_eq!(U256::from(5) - U256::from(7), !U256::from(1));
+        assert_eq!(
+            (U256::from(1) << 200) / (U256::from(1) << 100),
+            U256::from(1) << 100
+        );
+    }
+
+    #[test]
+    fn test_check_proof_of_work() {
+        assert!(check_proof_of_work(
+            MAIN.genesis_hash,
+            MAIN.genesis_bits,
+            &MAIN
+        ));
+        assert!(!check_proof_of_work([0xFF; 32], MAIN.genesis_bits, &MAIN));
+        // Easier than the limit
+        assert!(!check_proof_of_work(MAIN.genesis_hash, 0x1e00ffff, &MAIN));
+        assert!(!check_proof_of_work(MAIN.genesis_hash, 0, &MAIN));
+    }
+}
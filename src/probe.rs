@@ -0,0 +1,110 @@
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    time::Duration,
+};
+
+use tracing::{info, info_span};
+
+use crate::{
+    error::Error,
+    messages::{
+        message::{Message, MessageMagicNumber},
+        version::VersionMessageBuilder,
+        ToNetworkMessage,
+    },
+};
+
+/// Result of probing an address for the network it speaks.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ProbeOutcome {
+    /// The peer answered with a frame of a known network.
+    Network(MessageMagicNumber),
+    /// The peer answered, but the first bytes are not the magic of any known network.
+    NotBitcoin([u8; 4]),
+    /// The peer never answered, whichever network was tried.
+    NoResponse,
+}
+
+/// Detects which network the node at `address` speaks.
+///
+/// Bitcoin nodes never speak first and silently drop connections whose first frame has a
+/// foreign magic, so a version message is sent for every known network in turn, each on a
+/// fresh connection. The first 4 bytes the peer sends back decide the outcome.
+///
+/// # Arguments
+///
+/// * `address` - The address to probe.
+/// * `timeout` - Timeout for connecting and for waiting for an answer, per network.
+pub fn probe(address: SocketAddr, timeout: Duration) -> Result<ProbeOutcome, Error> {
+    for network in MessageMagicNumber::KNOWN {
+        let _span = info_span!("probe", %address, network = ?network).entered();
+        info!("probing");
+        let mut stream = TcpStream::connect_timeout(&address, timeout)
+            .map_err(|e| Error::io(address.to_string(), e))?;
+        stream.set_read_timeout(Some(timeout))?;
+
+        let message = Message::Version(VersionMessageBuilder::new(
+            network,
+            address,
+            chrono::offset::Utc::now().timestamp(),
+            rand::random(),
+        ));
+        // A peer that is not a Bitcoin node may close the connection before we're done writing
+        if stream.write_all(&message.to_network_message()?).is_err() {
+            continue;
+        }
+
+        let mut magic = [0u8; 4];
+        match stream.read_exact(&mut magic) {
+            Ok(()) => {
+                return Ok(match MessageMagicNumber::try_from(magic) {
+                    Ok(network) => ProbeOutcome::Network(network),
+                    Err(_) => ProbeOutcome::NotBitcoin(magic),
+                })
+            }
+            Err(e) => info!(error = %e, "no answer"),
+        }
+    }
+    Ok(ProbeOutcome::NoResponse)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{net::TcpListener, thread};
+
+    use super::*;
+
+    /// Spawns a peer that drops connections not speaking `magic` and answers the first one
+    /// that does with `answer`.
+    fn spawn_peer(magic: [u8; 4], answer: &'static [u8]) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut received = [0u8; 4];
+                stream.read_exact(&mut received).unwrap();
+                if received == magic {
+                    stream.write_all(answer).unwrap();
+                    break;
+                }
+            }
+        });
+        address
+    }
+
+    #[test]
+    fn test_probe_detects_network() {
+        let address = spawn_peer([0x0A, 0x03, 0xCF, 0x40], &[0x0A, 0x03, 0xCF, 0x40]);
+        let outcome = probe(address, Duration::from_secs(5)).unwrap();
+        assert_eq!(outcome, ProbeOutcome::Network(MessageMagicNumber::Signet));
+    }
+
+    #[test]
+    fn test_probe_not_bitcoin() {
+        let address = spawn_peer([0xF9, 0xBE, 0xB4, 0xD9], b"SSH-2.0-OpenSSH\r\n");
+        let outcome = probe(address, Duration::from_secs(5)).unwrap();
+        assert_eq!(outcome, ProbeOutcome::NotBitcoin(*b"SSH-"));
+    }
+}
@@ -0,0 +1,166 @@
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{
+    error::Error,
+    messages::{feature::PeerFeatures, message::MessageMagicNumber, version::PeerVersion},
+};
+
+/// A step of the handshake.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    /// The connection to the peer is open.
+    Connected,
+    /// Our version message was sent.
+    VersionSent,
+    /// The peer's version message was received.
+    VersionReceived,
+    /// Our verack message was sent.
+    VerackSent,
+    /// The peer's verack message was received.
+    VerackReceived,
+}
+
+/// When a step of the handshake happened.
+#[derive(Serialize, Debug, Clone)]
+pub struct StepTimestamp {
+    pub step: Step,
+    pub at: DateTime<Utc>,
+}
+
+/// Error of a failed handshake, in a machine-readable form.
+#[derive(Serialize, Debug, Clone)]
+pub struct ErrorReport {
+    /// The kind of error, see `Error::kind`.
+    pub kind: &'static str,
+    /// Human-readable description of the error.
+    pub message: String,
+}
+
+/// One handshake attempted with a peer, see `retry::handshake_with_retry`.
+#[derive(Serialize, Debug, Clone)]
+pub struct Attempt {
+    /// Address of the peer as configured.
+    pub peer: String,
+    /// The number of the attempt on this peer, counting from 1.
+    pub attempt: u32,
+    /// When the attempt started.
+    pub at: DateTime<Utc>,
+    /// Why the attempt failed, `None` if it succeeded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorReport>,
+}
+
+/// Outcome of a handshake with one peer, printed by `--output json`.
+#[derive(Serialize, Debug, Clone)]
+pub struct HandshakeReport {
+    /// Address of the peer as configured, e.g. `94.130.79.4:8333`.
+    pub peer: String,
+    /// The network the peer is expected to be on.
+    pub network: MessageMagicNumber,
+    /// Whether the handshake completed.
+    pub success: bool,
+    /// The lower of our protocol version and the peer's.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub negotiated_version: Option<i32>,
+    /// What the peer announced in its version message.
+    #[serde(flatten)]
+    pub peer_version: Option<PeerVersion>,
+    /// When each step of the handshake happened, in order.
+    pub steps: Vec<StepTimestamp>,
+    /// The features the peer asked for.
+    pub features: PeerFeatures,
+    /// Why the handshake failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorReport>,
+    /// Every handshake attempted across retries and peers, this one included.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub attempts: Vec<Attempt>,
+}
+
+impl HandshakeReport {
+    /// Creates an empty report for a handshake that is about to start.
+    ///
+    /// # Arguments
+    ///
+    /// * `peer` - Address of the peer.
+    /// * `network` - The network the peer is expected to be on.
+    pub fn new(peer: String, network: MessageMagicNumber) -> Self {
+        Self {
+            peer,
+            network,
+            success: false,
+            negotiated_version: None,
+            peer_version: None,
+            steps: Vec::new(),
+            features: PeerFeatures::default(),
+            error: None,
+            attempts: Vec::new(),
+        }
+    }
+
+    /// Records that `step` happened now.
+    pub fn step(&mut self, step: Step) {
+        self.steps.push(StepTimestamp {
+            step,
+            at: Utc::now(),
+        });
+    }
+
+    /// Records the version message of the peer.
+    ///
+    /// # Arguments
+    ///
+    /// * `version` - The version message of the peer.
+    /// * `our_version` - The protocol version we announced.
+    pub fn peer_version(&mut self, version: PeerVersion, our_version: i32) {
+        self.negotiated_version = Some(version.version.min(our_version));
+        self.peer_version = Some(version);
+    }
+
+    /// Records the outcome of the handshake.
+    pub fn finish<T>(&mut self, result: &Result<T, Error>) {
+        self.success = result.is_ok();
+        self.error = result.as_ref().err().map(|e| ErrorReport {
+            kind: e.kind(),
+            message: e.to_string(),
+        });
+    }
+}
+
+impl fmt::Display for HandshakeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.error, &self.peer_version) {
+            (Some(error), _) => write!(f, "{} failed: {}", self.peer, error.message),
+            (None, Some(version)) => write!(
+                f,
+                "{} {} version {} at height {}",
+                self.peer, version.user_agent, version.version, version.start_height
+            ),
+            (None, None) => write!(f, "{} succeeded", self.peer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_failed_report_json() {
+        let mut report =
+            HandshakeReport::new("127.0.0.1:8333".to_owned(), MessageMagicNumber::Main);
+        report.step(Step::Connected);
+        report.finish::<()>(&Err(anyhow::anyhow!("peer went away").into()));
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["network"], "main");
+        assert_eq!(json["success"], false);
+        assert_eq!(json["steps"][0]["step"], "connected");
+        assert_eq!(json["error"]["kind"], "unexpected");
+        assert!(json.get("user_agent").is_none());
+    }
+}
@@ -0,0 +1,238 @@
+use std::{thread, time::Duration};
+
+use chrono::Utc;
+use rand::Rng;
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::{
+    error::Error,
+    handshake::{handshake, ConnectOptions, Connection},
+    messages::message::MessageMagicNumber,
+    report::{Attempt, HandshakeReport},
+};
+
+/// How failed handshakes are retried before failing over to the next peer.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// How many times a peer is retried after a retryable failure, see `Error::is_retryable`.
+    pub retries: u32,
+    /// Delay before the first retry, in milliseconds. It doubles on every further retry.
+    pub initial_backoff_ms: u64,
+    /// Longest delay between two retries, in milliseconds.
+    pub max_backoff_ms: u64,
+    /// Whether delays are randomized between half and all of their value, so peers restarted
+    /// at once are not retried in lockstep.
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 2,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 10_000,
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns how long to wait before retry number `retry`, counting from 1.
+    ///
+    /// # Arguments
+    ///
+    /// * `retry` - The number of the retry about to happen.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u64.saturating_pow(retry.saturating_sub(1));
+        let delay = self
+            .initial_backoff_ms
+            .saturating_mul(factor)
+            .min(self.max_backoff_ms);
+        let delay = if self.jitter && delay > 1 {
+            rand::thread_rng().gen_range(delay / 2..=delay)
+        } else {
+            delay
+        };
+        Duration::from_millis(delay)
+    }
+}
+
+/// A peer to handshake with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    /// Address of the peer as configured, e.g. `94.130.79.4`.
+    pub address: String,
+    /// The host to connect to.
+    pub host: String,
+    /// The port to connect to.
+    pub port: u16,
+}
+
+/// Handshakes with the candidates in order until one succeeds. Retryable failures are
+/// retried on the same candidate as `policy` allows, with exponential backoff, before failing
+/// over to the next one. Other failures fail over at once.
+///
+/// Returns the report of the last handshake attempted, with every attempt summarized in its
+/// `attempts`, and the connection if one succeeded.
+///
+/// # Arguments
+///
+/// * `network` - The network the candidates are expected to be on.
+/// * `candidates` - The peers to try, in order.
+/// * `options` - How to connect to the candidates.
+/// * `policy` - How often and how fast to retry.
+pub fn handshake_with_retry(
+    network: &MessageMagicNumber,
+    candidates: &[Candidate],
+    options: &ConnectOptions,
+    policy: &RetryPolicy,
+) -> (HandshakeReport, Result<Connection, Error>) {
+    let mut attempts = Vec::new();
+    let mut last = None;
+    for candidate in candidates {
+        for attempt in 1..=policy.retries.saturating_add(1) {
+            if attempt > 1 {
+                let delay = policy.backoff(attempt - 1);
+                info!(peer = %candidate.address, ?delay, "retrying");
+                thread::sleep(delay);
+            }
+
+            let at = Utc::now();
+            let mut report = HandshakeReport::new(candidate.address.clone(), network.clone());
+            let result = handshake(
+                network,
+                &candidate.host,
+                candidate.port,
+                options,
+                &mut report,
+            );
+            report.finish(&result);
+            attempts.push(Attempt {
+                peer: candidate.address.clone(),
+                attempt,
+                at,
+                error: report.error.clone(),
+            });
+
+            match result {
+                Ok(connection) => {
+                    report.attempts = attempts;
+                    return (report, Ok(connection));
+                }
+                Err(e) => {
+                    warn!(peer = %candidate.address, attempt, error = %e, "handshake failed");
+                    let retryable = e.is_retryable();
+                    last = Some((report, e));
+                    if !retryable {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    match last {
+        Some((mut report, e)) => {
+            report.attempts = attempts;
+            (report, Err(e))
+        }
+        None => {
+            let mut report = HandshakeReport::new(String::new(), network.clone());
+            let result = Err(anyhow::anyhow!("no peers to connect to").into());
+            report.finish(&result);
+            (report, result)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::listen::listen;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            retries: 5,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 1_000,
+            jitter: false,
+        };
+        let delays: Vec<_> = (1..=5).map(|retry| policy.backoff(retry)).collect();
+        assert_eq!(
+            delays,
+            [100, 200, 400, 800, 1_000].map(Duration::from_millis)
+        );
+
+        let policy = RetryPolicy {
+            jitter: true,
+            ..policy
+        };
+        for _ in 0..100 {
+            let delay = policy.backoff(3);
+            assert!(delay >= Duration::from_millis(200) && delay <= Duration::from_millis(400));
+        }
+    }
+
+    #[test]
+    fn test_failover() {
+        // Nothing listens on the first candidate
+        let dead = TcpListener::bind("127.0.0.1:0").unwrap();
+        let dead_port = dead.local_addr().unwrap().port();
+        drop(dead);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let live_port = listener.local_addr().unwrap().port();
+        let options = ConnectOptions {
+            timeout: Some(Duration::from_secs(5)),
+            ..ConnectOptions::default()
+        };
+        let listen_options = options.clone();
+        thread::spawn(move || {
+            listen(
+                listener,
+                &MessageMagicNumber::Regtest,
+                &listen_options,
+                |_| {},
+            )
+        });
+
+        let candidates = [dead_port, live_port].map(|port| Candidate {
+            address: format!("127.0.0.1:{port}"),
+            host: "127.0.0.1".to_owned(),
+            port,
+        });
+        let policy = RetryPolicy {
+            retries: 1,
+            initial_backoff_ms: 1,
+            ..RetryPolicy::default()
+        };
+        let (report, result) =
+            handshake_with_retry(&MessageMagicNumber::Regtest, &candidates, &options, &policy);
+        assert!(result.is_ok());
+        assert_eq!(report.peer, candidates[1].address);
+        let summary: Vec<_> = report
+            .attempts
+            .iter()
+            .map(|attempt| {
+                (
+                    attempt.peer.as_str(),
+                    attempt.attempt,
+                    attempt.error.as_ref().map(|error| error.kind),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (candidates[0].address.as_str(), 1, Some("connect_refused")),
+                (candidates[0].address.as_str(), 2, Some("connect_refused")),
+                (candidates[1].address.as_str(), 1, None),
+            ]
+        );
+    }
+}
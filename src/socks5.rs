@@ -0,0 +1,266 @@
+use std::{
+    io::{self, Read, Write},
+    net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use crate::config::ProxyConfig;
+
+const SOCKS_VERSION: u8 = 0x05;
+const AUTH_NONE: u8 = 0x00;
+const AUTH_USERNAME_PASSWORD: u8 = 0x02;
+const USERNAME_PASSWORD_VERSION: u8 = 0x01;
+const CMD_CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// Opens a connection to `host:port` through a SOCKS5 proxy (RFC 1928), authenticating with
+/// username/password (RFC 1929) when credentials are configured or stream isolation is on.
+///
+/// # Arguments
+///
+/// * `proxy` - The proxy settings.
+/// * `host` - The destination host: an IP address, a host name or a `.onion` address.
+/// * `port` - The destination port.
+/// * `timeout` - Optional timeout for connecting to the proxy and for the SOCKS negotiation.
+///
+/// The proxy failing to reach the destination is reported like a direct connection would: a
+/// refused connection with `ConnectionRefused`, an expired one or a silent proxy with `TimedOut`.
+pub fn connect(
+    proxy: &ProxyConfig,
+    host: &str,
+    port: u16,
+    timeout: Option<Duration>,
+) -> io::Result<TcpStream> {
+    let proxy_address: SocketAddr = proxy
+        .addr
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut stream = match timeout {
+        Some(timeout) => TcpStream::connect_timeout(&proxy_address, timeout)?,
+        None => TcpStream::connect(proxy_address)?,
+    };
+    stream.set_read_timeout(timeout)?;
+    stream.set_write_timeout(timeout)?;
+    negotiate(&mut stream, proxy, host, port).map_err(|e| match e.kind() {
+        io::ErrorKind::WouldBlock => io::Error::new(io::ErrorKind::TimedOut, "SOCKS5: timed out"),
+        _ => e,
+    })?;
+    Ok(stream)
+}
+
+/// Authenticates with the proxy and asks it to connect to `host:port`.
+fn negotiate(stream: &mut TcpStream, proxy: &ProxyConfig, host: &str, port: u16) -> io::Result<()> {
+    let credentials = match (&proxy.username, &proxy.password) {
+        (Some(username), Some(password)) => Some((username.clone(), password.clone())),
+        // Tor puts streams with different credentials on different circuits
+        _ if proxy.isolate_streams => Some((nanoid::nanoid!(), nanoid::nanoid!())),
+        _ => None,
+    };
+
+    let method = if credentials.is_some() {
+        AUTH_USERNAME_PASSWORD
+    } else {
+        AUTH_NONE
+    };
+    stream.write_all(&[SOCKS_VERSION, 1, method])?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply)?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(protocol_error("proxy is not a SOCKS5 proxy"));
+    }
+    if reply[1] != method {
+        return Err(protocol_error("proxy rejected the authentication method"));
+    }
+
+    if let Some((username, password)) = credentials {
+        let mut request = vec![USERNAME_PASSWORD_VERSION];
+        for field in [username.as_bytes(), password.as_bytes()] {
+            let len = u8::try_from(field.len())
+                .map_err(|_| protocol_error("proxy credentials longer than 255 bytes"))?;
+            request.push(len);
+            request.extend_from_slice(field);
+        }
+        stream.write_all(&request)?;
+        stream.read_exact(&mut reply)?;
+        if reply[1] != 0x00 {
+            return Err(protocol_error("proxy rejected the credentials"));
+        }
+    }
+
+    let mut request = vec![SOCKS_VERSION, CMD_CONNECT, 0x00];
+    match destination_ip(host, port, proxy.remote_dns)? {
+        Some(IpAddr::V4(ip)) => {
+            request.push(ATYP_IPV4);
+            request.extend_from_slice(&ip.octets());
+        }
+        Some(IpAddr::V6(ip)) => {
+            request.push(ATYP_IPV6);
+            request.extend_from_slice(&ip.octets());
+        }
+        None => {
+            let len = u8::try_from(host.len())
+                .map_err(|_| protocol_error("host name longer than 255 bytes"))?;
+            request.push(ATYP_DOMAIN);
+            request.push(len);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request)?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply)?;
+    if reply[1] != 0x00 {
+        return Err(reply_error(reply[1]));
+    }
+    // Skip the address the proxy bound to, we have no use for it
+    let bound_len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len)?;
+            len[0] as usize
+        }
+        _ => return Err(protocol_error("proxy replied with unknown address type")),
+    };
+    let mut bound = vec![0u8; bound_len + 2];
+    stream.read_exact(&mut bound)
+}
+
+/// Returns the IP address to send to the proxy, or `None` if the host name should be sent
+/// for the proxy to resolve. Host names are resolved locally only if remote DNS is off.
+fn destination_ip(host: &str, port: u16, remote_dns: bool) -> io::Result<Option<IpAddr>> {
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(Some(ip));
+    }
+    if remote_dns || host.ends_with(".onion") {
+        return Ok(None);
+    }
+    (host, port)
+        .to_socket_addrs()?
+        .next()
+        .map(|address| Some(address.ip()))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host did not resolve"))
+}
+
+/// Returns the error of a failed CONNECT request, with the kind a direct connection failing
+/// the same way would have.
+fn reply_error(reply: u8) -> io::Error {
+    let message = match reply {
+        0x01 => "general SOCKS server failure",
+        0x02 => "connection not allowed by ruleset",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "TTL expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _ => "unknown SOCKS error",
+    };
+    let kind = match reply {
+        0x05 => io::ErrorKind::ConnectionRefused,
+        0x06 => io::ErrorKind::TimedOut,
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, format!("SOCKS5: {message}"))
+}
+
+fn protocol_error(message: &str) -> io::Error {
+    io::Error::other(format!("SOCKS5: {message}"))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{net::TcpListener, sync::mpsc, thread};
+
+    use super::*;
+
+    /// Username the client authenticated with and the destination it requested.
+    type ProxiedRequest = (Vec<u8>, Vec<u8>);
+
+    /// A SOCKS5 stand-in accepting `connections` clients. It reports the authentication
+    /// it saw and the requested destination, then greets the client with `hello`.
+    fn spawn_proxy(connections: usize) -> (String, mpsc::Receiver<ProxiedRequest>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming().take(connections) {
+                let mut stream = stream.unwrap();
+                let mut greeting = [0u8; 3];
+                stream.read_exact(&mut greeting).unwrap();
+                stream.write_all(&[SOCKS_VERSION, greeting[2]]).unwrap();
+
+                let mut auth = Vec::new();
+                if greeting[2] == AUTH_USERNAME_PASSWORD {
+                    let mut buf = [0u8; 2];
+                    stream.read_exact(&mut buf).unwrap();
+                    let mut username = vec![0u8; buf[1] as usize];
+                    stream.read_exact(&mut username).unwrap();
+                    let mut len = [0u8; 1];
+                    stream.read_exact(&mut len).unwrap();
+                    let mut password = vec![0u8; len[0] as usize];
+                    stream.read_exact(&mut password).unwrap();
+                    auth = username;
+                    stream
+                        .write_all(&[USERNAME_PASSWORD_VERSION, 0x00])
+                        .unwrap();
+                }
+
+                let mut request = [0u8; 5];
+                stream.read_exact(&mut request).unwrap();
+                assert_eq!(request[3], ATYP_DOMAIN);
+                let mut destination = vec![0u8; request[4] as usize + 2];
+                stream.read_exact(&mut destination).unwrap();
+                stream
+                    .write_all(&[SOCKS_VERSION, 0x00, 0x00, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+                    .unwrap();
+                stream.write_all(b"hello").unwrap();
+                sender.send((auth, destination)).unwrap();
+            }
+        });
+        (address, receiver)
+    }
+
+    fn proxy_config(addr: String, isolate_streams: bool) -> ProxyConfig {
+        ProxyConfig {
+            addr,
+            remote_dns: true,
+            isolate_streams,
+            username: None,
+            password: None,
+        }
+    }
+
+    #[test]
+    fn test_connect_onion() {
+        let (address, receiver) = spawn_proxy(1);
+        let proxy = proxy_config(address, false);
+        let host = "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd.onion";
+        let mut stream = connect(&proxy, host, 8333, Some(Duration::from_secs(5))).unwrap();
+
+        let mut hello = [0u8; 5];
+        stream.read_exact(&mut hello).unwrap();
+        assert_eq!(&hello, b"hello");
+        let (auth, destination) = receiver.recv().unwrap();
+        assert!(auth.is_empty());
+        assert_eq!(&destination[..host.len()], host.as_bytes());
+        assert_eq!(&destination[host.len()..], &8333u16.to_be_bytes());
+    }
+
+    #[test]
+    fn test_stream_isolation() {
+        let (address, receiver) = spawn_proxy(2);
+        let proxy = proxy_config(address, true);
+        connect(&proxy, "localhost", 8333, Some(Duration::from_secs(5))).unwrap();
+        connect(&proxy, "localhost", 8333, Some(Duration::from_secs(5))).unwrap();
+
+        let (first, _) = receiver.recv().unwrap();
+        let (second, _) = receiver.recv().unwrap();
+        assert!(!first.is_empty());
+        assert_ne!(first, second);
+    }
+}
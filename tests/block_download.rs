@@ -0,0 +1,110 @@
+mod common;
+
+use common::{connect, mine_block, NETWORK};
+use handshaker::{
+    block_download::{download_block, BlockSummary},
+    error::Error,
+    fake_node::{FakeNode, FakeNodeHandle},
+    messages::{
+        block::Block,
+        inventory::{parse_inventory, MSG_BLOCK, MSG_WITNESS_BLOCK},
+        message::hash_to_hex,
+        transaction::{OutPoint, Transaction, TxIn, TxOut},
+        version::NODE_WITNESS,
+    },
+    run,
+};
+
+/// Mines a regtest block committing to height 200 on top of the genesis block.
+fn mine_coinbase_block() -> Block {
+    mine_block(vec![Transaction {
+        version: 2,
+        input: vec![TxIn {
+            previous_output: OutPoint::NULL,
+            script_sig: vec![0x02, 200, 0x00, 0x51],
+            sequence: u32::MAX,
+            witness: Vec::new(),
+        }],
+        output: vec![TxOut {
+            value: 50 * 100_000_000,
+            script_pubkey: vec![0x51],
+        }],
+        lock_time: 0,
+    }])
+}
+
+/// A node serving `block` to `getdata` by hash, with or without witnesses.
+fn serve(block: Block) -> FakeNodeHandle {
+    let hash = block.header.hash();
+    FakeNode::new(NETWORK)
+        .with_responder(move |command, payload| match command {
+            "getdata" => parse_inventory(payload)
+                .unwrap()
+                .iter()
+                .filter(|item| item.hash == hash)
+                .filter(|item| item.inv_type == MSG_BLOCK || item.inv_type == MSG_WITNESS_BLOCK)
+                .map(|_| ("block".to_owned(), block.serialize()))
+                .collect(),
+            _ => vec![],
+        })
+        .spawn()
+        .unwrap()
+}
+
+fn download_from(node: &FakeNodeHandle, hash: [u8; 32], services: u64) -> Result<Block, Error> {
+    let (_, mut connection) = connect(node);
+    download_block(&mut connection, hash, services)
+}
+
+#[test]
+fn downloads_and_validates_block() {
+    let block = mine_coinbase_block();
+    let node = serve(block.clone());
+
+    let downloaded = download_from(&node, block.header.hash(), NODE_WITNESS).unwrap();
+
+    assert_eq!(downloaded, block);
+    let summary = BlockSummary::new(node.address().to_string(), &downloaded, true);
+    assert_eq!(summary.hash, hash_to_hex(&block.header.hash()));
+    assert_eq!(summary.height, Some(200));
+    assert_eq!(summary.transactions, 1);
+    assert_eq!(summary.size, block.size());
+    assert_eq!(summary.weight, block.size() * 4);
+}
+
+#[test]
+fn rejects_block_not_matching_its_merkle_root() {
+    let mut block = mine_coinbase_block();
+    block.txdata[0].output[0].value += 1;
+    let node = serve(block.clone());
+
+    let error = download_from(&node, block.header.hash(), 0).unwrap_err();
+
+    assert_eq!(error.kind(), "protocol_violation");
+    assert!(error.to_string().contains("merkle root"));
+}
+
+#[test]
+fn reports_block_not_served() {
+    let block = mine_coinbase_block();
+    let node = serve(block);
+
+    let error = run([
+        "handshaker",
+        "--network",
+        "regtest",
+        "--timeout",
+        "1",
+        "--retries",
+        "0",
+        "block",
+        &hash_to_hex(&[7; 32]),
+        &node.address().to_string(),
+    ]
+    .map(String::from)
+    .to_vec())
+    .unwrap_err();
+
+    assert_eq!(error.kind(), "block_not_found");
+    assert_eq!(error.exit_code(), 19);
+}
@@ -0,0 +1,129 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{connect_with, NETWORK};
+use handshaker::{
+    broadcast::{broadcast, BroadcastReport},
+    fake_node::{FakeNode, FakeNodeHandle},
+    handshake::ConnectOptions,
+    messages::{
+        inventory::{inventory_payload, parse_inventory, Inventory, MSG_TX, MSG_WITNESS_TX},
+        message::hash_to_hex,
+        reject::Reject,
+        transaction::{OutPoint, Transaction, TxIn, TxOut},
+    },
+};
+
+fn transaction() -> Transaction {
+    Transaction {
+        version: 2,
+        input: vec![TxIn {
+            previous_output: OutPoint {
+                txid: [1; 32],
+                vout: 0,
+            },
+            script_sig: Vec::new(),
+            sequence: u32::MAX,
+            witness: vec![vec![2; 72], vec![3; 33]],
+        }],
+        output: vec![TxOut {
+            value: 1000,
+            script_pubkey: vec![0x51],
+        }],
+        lock_time: 0,
+    }
+}
+
+/// Handshakes with `node` with relay on, then broadcasts `transaction`.
+fn broadcast_to(
+    node: &FakeNodeHandle,
+    transaction: &Transaction,
+    wait: Duration,
+) -> BroadcastReport {
+    let options = ConnectOptions {
+        relay: true,
+        ..ConnectOptions::default()
+    };
+    let (_, mut connection) = connect_with(node, options);
+    broadcast(&mut connection, transaction, wait).unwrap()
+}
+
+/// Asks for every transaction announced by txid, with its witnesses.
+fn request_announced(command: &str, payload: &[u8]) -> Vec<(String, Vec<u8>)> {
+    match command {
+        "inv" => {
+            let wanted: Vec<Inventory> = parse_inventory(payload)
+                .unwrap()
+                .into_iter()
+                .filter(|item| item.inv_type == MSG_TX)
+                .map(|item| Inventory::new(MSG_WITNESS_TX, item.hash))
+                .collect();
+            vec![("getdata".to_owned(), inventory_payload(&wanted))]
+        }
+        _ => vec![],
+    }
+}
+
+#[test]
+fn serves_requested_transaction() {
+    let node = FakeNode::new(NETWORK)
+        .with_responder(request_announced)
+        .spawn()
+        .unwrap();
+    let transaction = transaction();
+
+    let report = broadcast_to(&node, &transaction, Duration::from_secs(5));
+
+    assert!(report.requested);
+    assert_eq!(report.txid, hash_to_hex(&transaction.txid()));
+    assert_eq!(report.reject, None);
+    assert!(!report.disconnected);
+    assert!(report.into_result().is_ok());
+    let received = node.received();
+    assert!(received.contains(&"inv".to_owned()));
+    assert!(received.contains(&"tx".to_owned()));
+}
+
+#[test]
+fn reports_rejection() {
+    let transaction = transaction();
+    let txid = transaction.txid();
+    let node = FakeNode::new(NETWORK)
+        .with_responder(move |command, payload| match command {
+            "tx" => {
+                let reject = Reject {
+                    message: "tx".to_owned(),
+                    code: 0x10,
+                    reason: "bad-txns-inputs-missingorspent".to_owned(),
+                    hash: Some(txid),
+                };
+                vec![("reject".to_owned(), reject.payload())]
+            }
+            _ => request_announced(command, payload),
+        })
+        .spawn()
+        .unwrap();
+
+    let report = broadcast_to(&node, &transaction, Duration::from_secs(5));
+
+    assert!(report.requested);
+    assert_eq!(
+        report.reject.as_ref().map(|reject| reject.reason.as_str()),
+        Some("bad-txns-inputs-missingorspent")
+    );
+    let error = report.into_result().unwrap_err();
+    assert_eq!(error.kind(), "transaction_rejected");
+    assert_eq!(error.exit_code(), 18);
+}
+
+#[test]
+fn gives_up_when_not_requested() {
+    let node = FakeNode::new(NETWORK).spawn().unwrap();
+
+    let report = broadcast_to(&node, &transaction(), Duration::from_secs(1));
+
+    assert!(!report.requested);
+    assert!(!report.disconnected);
+    assert!(!node.received().contains(&"tx".to_owned()));
+}
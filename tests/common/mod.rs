@@ -0,0 +1,100 @@
+//! Fixtures shared by the integration tests: regtest miners and connections to fake nodes.
+// Each test crate uses a part of the fixtures only
+#![allow(dead_code)]
+
+use std::time::Duration;
+
+use handshaker::{
+    chain_params::REGTEST,
+    fake_node::FakeNodeHandle,
+    handshake::{handshake, ConnectOptions, Connection},
+    messages::{
+        block::{merkle_root, Block, BlockHeader},
+        message::{compact_size, MessageMagicNumber},
+        transaction::Transaction,
+    },
+    pow::check_proof_of_work,
+    report::HandshakeReport,
+};
+
+pub const NETWORK: MessageMagicNumber = MessageMagicNumber::Regtest;
+
+/// Mines a header on top of `parent` with the easiest regtest target.
+pub fn mine_header(parent: [u8; 32], merkle_root: [u8; 32], time: u32) -> BlockHeader {
+    let mut header = BlockHeader {
+        version: 4,
+        prev_blockhash: parent,
+        merkle_root,
+        time,
+        bits: REGTEST.pow_limit,
+        nonce: 0,
+    };
+    while !check_proof_of_work(header.hash(), header.bits, &REGTEST) {
+        header.nonce += 1;
+    }
+    header
+}
+
+/// Mines `count` regtest headers on top of `parent`, a second apart from `start_time`.
+pub fn mine_branch(parent: [u8; 32], start_time: u32, count: u32) -> Vec<BlockHeader> {
+    let mut headers: Vec<BlockHeader> = Vec::new();
+    for i in 0..count {
+        let parent = headers.last().map_or(parent, BlockHeader::hash);
+        headers.push(mine_header(parent, [0; 32], start_time + i + 1));
+    }
+    headers
+}
+
+/// Mines `count` regtest headers on top of the genesis block.
+pub fn mine_chain(count: u32) -> Vec<BlockHeader> {
+    mine_branch(REGTEST.genesis_hash, REGTEST.genesis_time, count)
+}
+
+/// Mines a regtest block of `txdata` on top of the genesis block.
+pub fn mine_block(txdata: Vec<Transaction>) -> Block {
+    let (root, _) = merkle_root(txdata.iter().map(Transaction::txid).collect());
+    Block {
+        header: mine_header(REGTEST.genesis_hash, root, REGTEST.genesis_time + 1),
+        txdata,
+    }
+}
+
+/// Returns the payload of a `headers` message carrying `headers`.
+pub fn headers_payload(headers: &[BlockHeader]) -> Vec<u8> {
+    let mut payload = compact_size(headers.len() as u64);
+    for header in headers {
+        payload.extend_from_slice(&header.serialize());
+        // No transactions
+        payload.push(0);
+    }
+    payload
+}
+
+/// Handshakes with `node` with a 1 second timeout.
+pub fn connect(node: &FakeNodeHandle) -> (HandshakeReport, Connection) {
+    connect_with(node, ConnectOptions::default())
+}
+
+/// Handshakes with `node` with `options`, and a 1 second timeout unless they set one.
+pub fn connect_with(
+    node: &FakeNodeHandle,
+    options: ConnectOptions,
+) -> (HandshakeReport, Connection) {
+    let options = ConnectOptions {
+        timeout: options.timeout.or(Some(Duration::from_secs(1))),
+        ..options
+    };
+    let address = node.address();
+    let mut report = HandshakeReport::new(address.to_string(), NETWORK);
+    let connection =
+        handshake(&NETWORK, "127.0.0.1", address.port(), &options, &mut report).unwrap();
+    (report, connection)
+}
+
+/// Returns the services the peer announced in the version message recorded in `report`.
+pub fn services(report: &HandshakeReport) -> u64 {
+    report
+        .peer_version
+        .as_ref()
+        .map_or(0, |version| version.services)
+}
@@ -0,0 +1,158 @@
+mod common;
+
+use std::collections::HashMap;
+
+use common::{connect, headers_payload, mine_chain, services, NETWORK};
+use handshaker::{
+    block_filter::BlockFilter,
+    chain_params::REGTEST,
+    compact_filters::{scan_filters, FilterScanReport},
+    error::Error,
+    fake_node::FakeNode,
+    messages::{
+        block::BlockHeader,
+        filter::{CFCheckpt, CFHeaders, CFilter, GetCFCheckpt, GetCFilters, FILTER_TYPE_BASIC},
+        message::hash_to_hex,
+        version::NODE_COMPACT_FILTERS,
+    },
+};
+
+/// The scriptPubKey paid in the block at `height`.
+fn script(height: u32) -> Vec<u8> {
+    [&[0x00, 0x14][..], &[height as u8; 20]].concat()
+}
+
+/// A node serving `headers` and the filters of their blocks, each paying `script` of
+/// its height. `tamper` may change the filters served.
+fn serve(
+    headers: Vec<BlockHeader>,
+    services: u64,
+    tamper: impl Fn(u32, &mut Vec<u8>) + Send + Sync + 'static,
+) -> FakeNode {
+    let hashes: Vec<[u8; 32]> = std::iter::once(REGTEST.genesis_hash)
+        .chain(headers.iter().map(BlockHeader::hash))
+        .collect();
+    let heights: HashMap<[u8; 32], u32> = (0..)
+        .zip(&hashes)
+        .map(|(height, hash)| (*hash, height))
+        .collect();
+    let filters: Vec<BlockFilter> = (0..)
+        .zip(&hashes)
+        .map(|(height, hash)| BlockFilter::build(hash, &[script(height)]))
+        .collect();
+    let mut filter_headers = Vec::new();
+    let mut previous = [0; 32];
+    for filter in &filters {
+        previous = filter.header(&previous);
+        filter_headers.push(previous);
+    }
+    FakeNode::new(NETWORK)
+        .with_services(services)
+        .with_responder(move |command, payload| match command {
+            "getheaders" => {
+                let start = payload[5..payload.len() - 32]
+                    .chunks_exact(32)
+                    .find_map(|hash| heights.get(hash))
+                    .copied()
+                    .unwrap_or(0) as usize;
+                let batch = &headers[start..headers.len().min(start + 2000)];
+                vec![("headers".to_owned(), headers_payload(batch))]
+            }
+            "getcfcheckpt" => {
+                let request = GetCFCheckpt::try_from(payload).unwrap();
+                let stop = heights[&request.stop_hash] as usize;
+                let checkpoint = CFCheckpt {
+                    filter_type: FILTER_TYPE_BASIC,
+                    stop_hash: request.stop_hash,
+                    filter_headers: (1000..=stop)
+                        .step_by(1000)
+                        .map(|height| filter_headers[height])
+                        .collect(),
+                };
+                vec![("cfcheckpt".to_owned(), checkpoint.payload())]
+            }
+            "getcfheaders" => {
+                let request = GetCFilters::try_from(payload).unwrap();
+                let start = request.start_height as usize;
+                let stop = heights[&request.stop_hash] as usize;
+                let cfheaders = CFHeaders {
+                    filter_type: FILTER_TYPE_BASIC,
+                    stop_hash: request.stop_hash,
+                    previous_filter_header: start
+                        .checked_sub(1)
+                        .map_or([0; 32], |previous| filter_headers[previous]),
+                    filter_hashes: filters[start..=stop]
+                        .iter()
+                        .map(BlockFilter::hash)
+                        .collect(),
+                };
+                vec![("cfheaders".to_owned(), cfheaders.payload())]
+            }
+            "getcfilters" => {
+                let request = GetCFilters::try_from(payload).unwrap();
+                let stop = heights[&request.stop_hash];
+                (request.start_height..=stop)
+                    .map(|height| {
+                        let mut filter = filters[height as usize].content.clone();
+                        tamper(height, &mut filter);
+                        let cfilter = CFilter {
+                            filter_type: FILTER_TYPE_BASIC,
+                            block_hash: hashes[height as usize],
+                            filter,
+                        };
+                        ("cfilter".to_owned(), cfilter.payload())
+                    })
+                    .collect()
+            }
+            _ => vec![],
+        })
+}
+
+fn scan(node: FakeNode, scripts: &[Vec<u8>], start_height: u32) -> Result<FilterScanReport, Error> {
+    let node = node.spawn().unwrap();
+    let (report, mut connection) = connect(&node);
+    scan_filters(&mut connection, services(&report), scripts, start_height)
+}
+
+#[test]
+fn verifies_filters_and_matches_scripts() {
+    let headers = mine_chain(2100);
+    let matched = hash_to_hex(&headers[2049].hash());
+    let node = serve(headers, NODE_COMPACT_FILTERS, |_, _| {});
+
+    let report = scan(node, &[script(2050), vec![0x51]], 1500).unwrap();
+
+    assert_eq!(report.height, 2100);
+    assert_eq!(report.filters_checked, 601);
+    // The script repeats every 256 blocks
+    let heights: Vec<u32> = report.matches.iter().map(|m| m.height).collect();
+    assert_eq!(heights, [1538, 1794, 2050]);
+    assert_eq!(report.matches[2].hash, matched);
+}
+
+#[test]
+fn rejects_filter_not_matching_its_header() {
+    let headers = mine_chain(20);
+    let node = serve(headers, NODE_COMPACT_FILTERS, |height, filter| {
+        if height == 15 {
+            filter[1] ^= 1;
+        }
+    });
+
+    let error = scan(node, &[script(3)], 0).unwrap_err();
+
+    assert_eq!(error.kind(), "protocol_violation");
+    assert!(error
+        .to_string()
+        .contains("does not match its filter header"));
+}
+
+#[test]
+fn requires_compact_filters_service() {
+    let node = serve(mine_chain(1), 0, |_, _| {});
+
+    let error = scan(node, &[script(1)], 0).unwrap_err();
+
+    assert_eq!(error.kind(), "missing_service");
+    assert_eq!(error.exit_code(), 20);
+}
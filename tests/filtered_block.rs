@@ -0,0 +1,229 @@
+mod common;
+
+use std::sync::Mutex;
+
+use common::{connect, mine_block, services, NETWORK};
+use handshaker::{
+    error::Error,
+    fake_node::{FakeNode, FakeNodeHandle},
+    filtered_block::{
+        add_to_filter, clear_filter, download_filtered_block, load_filter, FilteredBlockReport,
+    },
+    handshake::Connection,
+    messages::{
+        block::Block,
+        bloom::{BloomFilter, BLOOM_UPDATE_NONE},
+        inventory::{parse_inventory, MSG_FILTERED_BLOCK},
+        merkle_block::{MerkleBlock, PartialMerkleTree},
+        message::hash_to_hex,
+        transaction::{OutPoint, Transaction, TxIn, TxOut},
+        version::NODE_BLOOM,
+    },
+    run,
+};
+
+/// A transaction paying to the P2WPKH script of `key_hash`.
+fn payment(key_hash: [u8; 20]) -> Transaction {
+    Transaction {
+        version: 2,
+        input: vec![TxIn {
+            previous_output: OutPoint {
+                txid: [key_hash[0]; 32],
+                vout: 0,
+            },
+            script_sig: Vec::new(),
+            sequence: u32::MAX,
+            witness: Vec::new(),
+        }],
+        output: vec![TxOut {
+            value: 100_000,
+            script_pubkey: [&[0x00, 0x14][..], &key_hash].concat(),
+        }],
+        lock_time: 0,
+    }
+}
+
+/// Mines a regtest block of five payments, to the key hashes `[1; 20]` to `[5; 20]`.
+fn mine_payments() -> Block {
+    mine_block((1..=5).map(|i| payment([i; 20])).collect())
+}
+
+/// A node serving `block` as a `merkleblock` filtered by the filter loaded, followed by the
+/// matching transactions. Without a filter it ignores the request, like Bitcoin Core.
+/// `tamper` may change the tree served.
+fn serve(
+    block: Block,
+    services: u64,
+    tamper: impl Fn(&mut PartialMerkleTree) + Send + Sync + 'static,
+) -> FakeNodeHandle {
+    let hash = block.header.hash();
+    let filter: Mutex<Option<BloomFilter>> = Mutex::new(None);
+    FakeNode::new(NETWORK)
+        .with_services(services)
+        .with_responder(move |command, payload| {
+            let mut filter = filter.lock().unwrap();
+            match command {
+                "filterload" => *filter = Some(BloomFilter::try_from(payload).unwrap()),
+                // A one byte length, then the element
+                "filteradd" => filter.as_mut().unwrap().insert(&payload[1..]),
+                "filterclear" => *filter = None,
+                "getdata" => {
+                    let requested = parse_inventory(payload)
+                        .unwrap()
+                        .iter()
+                        .any(|item| item.inv_type == MSG_FILTERED_BLOCK && item.hash == hash);
+                    if let (true, Some(filter)) = (requested, filter.as_ref()) {
+                        return filtered(&block, filter, &tamper);
+                    }
+                }
+                _ => {}
+            }
+            vec![]
+        })
+        .spawn()
+        .unwrap()
+}
+
+/// Returns the `merkleblock` of `block` filtered by `filter` and the matching transactions.
+fn filtered(
+    block: &Block,
+    filter: &BloomFilter,
+    tamper: impl Fn(&mut PartialMerkleTree),
+) -> Vec<(String, Vec<u8>)> {
+    let matches: Vec<bool> = block
+        .txdata
+        .iter()
+        .map(|tx| {
+            filter.contains(&tx.txid())
+                || tx
+                    .output
+                    .iter()
+                    .any(|output| filter.contains(&output.script_pubkey[2..]))
+        })
+        .collect();
+    let txids: Vec<[u8; 32]> = block.txdata.iter().map(Transaction::txid).collect();
+    let mut tree = PartialMerkleTree::new(&txids, &matches);
+    tamper(&mut tree);
+    let merkle_block = MerkleBlock {
+        header: block.header,
+        tree,
+    };
+    std::iter::once(("merkleblock".to_owned(), merkle_block.payload()))
+        .chain(
+            block
+                .txdata
+                .iter()
+                .zip(&matches)
+                .filter(|(_, matched)| **matched)
+                .map(|(tx, _)| ("tx".to_owned(), tx.serialize())),
+        )
+        .collect()
+}
+
+/// Handshakes with `node` and loads a filter of `elements` into it.
+fn load(node: &FakeNodeHandle, elements: &[&[u8]]) -> Result<Connection, Error> {
+    let (report, mut connection) = connect(node);
+    let mut filter = BloomFilter::new(elements.len(), 0.000_001, 7, BLOOM_UPDATE_NONE);
+    for element in elements {
+        filter.insert(element);
+    }
+    load_filter(&mut connection, services(&report), &filter)?;
+    Ok(connection)
+}
+
+fn matched(report: &FilteredBlockReport) -> Vec<(u32, String, bool)> {
+    report
+        .matched
+        .iter()
+        .map(|tx| (tx.index, tx.txid.clone(), tx.received))
+        .collect()
+}
+
+#[test]
+fn downloads_filtered_block_and_matching_transactions() {
+    let block = mine_payments();
+    let node = serve(block.clone(), NODE_BLOOM, |_| {});
+    let mut connection = load(&node, &[&[2; 20]]).unwrap();
+    add_to_filter(&mut connection, &[5; 20]).unwrap();
+
+    let report = download_filtered_block(&mut connection, block.header.hash()).unwrap();
+
+    assert_eq!(report.hash, hash_to_hex(&block.header.hash()));
+    assert_eq!(report.total_transactions, 5);
+    assert_eq!(
+        matched(&report),
+        [
+            (1, hash_to_hex(&block.txdata[1].txid()), true),
+            (4, hash_to_hex(&block.txdata[4].txid()), true),
+        ]
+    );
+    let received = node.received();
+    assert!(received.contains(&"filterload".to_owned()));
+    assert!(received.contains(&"filteradd".to_owned()));
+}
+
+#[test]
+fn stops_serving_filtered_blocks_once_cleared() {
+    let block = mine_payments();
+    let node = serve(block.clone(), NODE_BLOOM, |_| {});
+    let mut connection = load(&node, &[&[3; 20]]).unwrap();
+    let report = download_filtered_block(&mut connection, block.header.hash()).unwrap();
+    assert_eq!(report.matched.len(), 1);
+
+    clear_filter(&mut connection).unwrap();
+    let error = download_filtered_block(&mut connection, block.header.hash()).unwrap_err();
+
+    assert_eq!(error.kind(), "block_not_found");
+    assert!(node.received().contains(&"filterclear".to_owned()));
+}
+
+#[test]
+fn rejects_tree_not_matching_the_merkle_root() {
+    let block = mine_payments();
+    let node = serve(block.clone(), NODE_BLOOM, |tree| tree.hashes[0][0] ^= 1);
+    let mut connection = load(&node, &[&[3; 20]]).unwrap();
+
+    let error = download_filtered_block(&mut connection, block.header.hash()).unwrap_err();
+
+    assert_eq!(error.kind(), "protocol_violation");
+    assert!(error.to_string().contains("merkle root"));
+}
+
+#[test]
+fn requires_bloom_service() {
+    let node = serve(mine_payments(), 0, |_| {});
+
+    let error = load(&node, &[&[3; 20]]).err().unwrap();
+
+    assert_eq!(error.kind(), "missing_service");
+    assert_eq!(error.exit_code(), 20);
+    assert!(!node.received().contains(&"filterload".to_owned()));
+}
+
+#[test]
+fn reports_block_not_served() {
+    let node = serve(mine_payments(), NODE_BLOOM, |_| {});
+
+    let error = run([
+        "handshaker",
+        "--network",
+        "regtest",
+        "--timeout",
+        "1",
+        "--retries",
+        "0",
+        "merkleblock",
+        &hash_to_hex(&[7; 32]),
+        &node.address().to_string(),
+        "--element",
+        &hex::encode([3; 20]),
+        "--add",
+        &hex::encode([4; 20]),
+    ]
+    .map(String::from)
+    .to_vec())
+    .unwrap_err();
+
+    assert_eq!(error.kind(), "block_not_found");
+    assert_eq!(error.exit_code(), 19);
+}
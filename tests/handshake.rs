@@ -0,0 +1,283 @@
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    thread,
+    time::Duration,
+};
+
+use handshaker::{
+    config::ProxyConfig,
+    error::Error,
+    fake_node::{Behavior, FakeNode, FakeNodeHandle},
+    handshake::{handshake, ConnectOptions},
+    messages::message::MessageMagicNumber,
+    report::HandshakeReport,
+    run,
+};
+
+const NETWORK: MessageMagicNumber = MessageMagicNumber::Regtest;
+
+fn spawn(behavior: Behavior) -> FakeNodeHandle {
+    FakeNode::new(NETWORK)
+        .with_behavior(behavior)
+        .spawn()
+        .unwrap()
+}
+
+/// Handshakes with `node` through the library, with a 1 second timeout.
+fn handshake_with(node: &FakeNodeHandle) -> (HandshakeReport, Result<(), Error>) {
+    let options = ConnectOptions {
+        timeout: Some(Duration::from_secs(1)),
+        ..ConnectOptions::default()
+    };
+    let address = node.address();
+    let mut report = HandshakeReport::new(address.to_string(), NETWORK);
+    let result = handshake(&NETWORK, "127.0.0.1", address.port(), &options, &mut report);
+    report.finish(&result);
+    (report, result.map(|_| ()))
+}
+
+/// Handshakes with `node`, negotiating features.
+fn negotiate_with(node: &FakeNodeHandle) -> (HandshakeReport, Result<(), Error>) {
+    let options = ConnectOptions {
+        timeout: Some(Duration::from_secs(1)),
+        announce_features: true,
+        ..ConnectOptions::default()
+    };
+    let address = node.address();
+    let mut report = HandshakeReport::new(address.to_string(), NETWORK);
+    let result = handshake(&NETWORK, "127.0.0.1", address.port(), &options, &mut report);
+    report.finish(&result);
+    (report, result.map(|_| ()))
+}
+
+/// Runs the CLI against `node`, without retries and with a 1 second timeout.
+fn run_against(node: &FakeNodeHandle) -> Result<(), Error> {
+    run([
+        "handshaker",
+        "--network",
+        "regtest",
+        "--timeout",
+        "1",
+        "--retries",
+        "0",
+        "handshake",
+        &node.address().to_string(),
+    ]
+    .map(String::from)
+    .to_vec())
+}
+
+#[test]
+fn honest_node() {
+    let node = FakeNode::new(NETWORK)
+        .with_user_agent("/honest:1.0/")
+        .spawn()
+        .unwrap();
+    let (report, result) = handshake_with(&node);
+    result.unwrap();
+    assert!(report.success);
+    assert_eq!(report.peer_version.unwrap().user_agent, "/honest:1.0/");
+    assert_eq!(report.steps.len(), 5);
+
+    run_against(&node).unwrap();
+    assert_eq!(
+        node.received()
+            .iter()
+            .filter(|command| *command == "version")
+            .count(),
+        2
+    );
+}
+
+#[test]
+fn slow_node_within_timeout() {
+    let node = spawn(Behavior::Slow(Duration::from_millis(100)));
+    handshake_with(&node).1.unwrap();
+}
+
+#[test]
+fn slow_node_times_out() {
+    let node = spawn(Behavior::Slow(Duration::from_secs(2)));
+    let (report, result) = handshake_with(&node);
+    assert_eq!(result.unwrap_err().kind(), "timeout");
+    assert_eq!(report.error.unwrap().kind, "timeout");
+}
+
+#[test]
+fn silent_node() {
+    let node = spawn(Behavior::Silent);
+    let error = run_against(&node).unwrap_err();
+    assert_eq!(error.kind(), "timeout");
+    assert_eq!(error.exit_code(), 11);
+    assert_eq!(node.received(), ["version"]);
+}
+
+#[test]
+fn misordered_node() {
+    let node = spawn(Behavior::Misordered);
+    let error = run_against(&node).unwrap_err();
+    assert_eq!(error.kind(), "protocol_violation");
+    assert_eq!(error.exit_code(), 15);
+}
+
+#[test]
+fn wrong_magic_node() {
+    let node = spawn(Behavior::WrongMagic);
+    let error = run_against(&node).unwrap_err();
+    assert_eq!(error.kind(), "wrong_network");
+    assert_eq!(error.exit_code(), 14);
+    assert!(error.to_string().contains(&node.address().to_string()));
+}
+
+#[test]
+fn bad_checksum_node() {
+    let node = spawn(Behavior::BadChecksum);
+    let error = run_against(&node).unwrap_err();
+    assert_eq!(error.kind(), "bad_checksum");
+    assert_eq!(error.exit_code(), 13);
+}
+
+#[test]
+fn obsolete_version_rejected() {
+    let node = FakeNode::new(NETWORK).with_version(209).spawn().unwrap();
+    let error = run_against(&node).unwrap_err();
+    assert_eq!(error.kind(), "protocol_violation");
+    assert!(error
+        .to_string()
+        .ends_with("protocol version 209 is below the minimum 31800"));
+    assert!(!node.received().contains(&"verack".to_owned()));
+}
+
+#[test]
+fn features_negotiated() {
+    let node = FakeNode::new(NETWORK).with_features().spawn().unwrap();
+    let (report, result) = negotiate_with(&node);
+    result.unwrap();
+    assert_eq!(report.negotiated_version, Some(70016));
+    let features = report.features;
+    assert!(features.wtxid_relay && features.addr_v2 && features.send_headers);
+    assert_eq!(features.compact_blocks[0].version, 2);
+    assert_eq!(features.fee_filter, Some(1000));
+
+    // wtxidrelay and sendaddrv2 come before our verack, the others after it
+    assert_eq!(
+        node.received(),
+        [
+            "version",
+            "wtxidrelay",
+            "sendaddrv2",
+            "verack",
+            "sendheaders",
+            "sendcmpct",
+            "feefilter",
+            "ping"
+        ]
+    );
+}
+
+#[test]
+fn features_not_announced_to_old_peers() {
+    let node = spawn(Behavior::Honest);
+    let (report, result) = negotiate_with(&node);
+    result.unwrap();
+    assert_eq!(report.negotiated_version, Some(70001));
+    assert_eq!(report.features, Default::default());
+    assert!(!node.received().contains(&"wtxidrelay".to_owned()));
+}
+
+#[test]
+fn late_wtxidrelay_rejected() {
+    let node = spawn(Behavior::LateWtxidRelay);
+    let (_, result) = negotiate_with(&node);
+    let error = result.unwrap_err();
+    assert_eq!(error.kind(), "protocol_violation");
+    assert!(error.to_string().ends_with("wtxidrelay after verack"));
+}
+
+#[test]
+fn monitor_answers_pings() {
+    let node = FakeNode::new(NETWORK)
+        .with_responder(|command, _| match command {
+            // Makes the node ping us
+            "sendheaders" => vec![("ping".to_owned(), 7u64.to_le_bytes().to_vec())],
+            _ => vec![],
+        })
+        .spawn()
+        .unwrap();
+    let address = node.address();
+    let options = ConnectOptions {
+        timeout: Some(Duration::from_secs(1)),
+        ..ConnectOptions::default()
+    };
+    let mut report = HandshakeReport::new(address.to_string(), NETWORK);
+    let mut connection =
+        handshake(&NETWORK, "127.0.0.1", address.port(), &options, &mut report).unwrap();
+    connection.send_raw("sendheaders", &[]).unwrap();
+
+    let mut observed = Vec::new();
+    let result =
+        handshaker::monitor::monitor(&mut connection, |message| observed.push(message.command));
+    // The node never disconnects, the timeout ends monitoring
+    assert_eq!(result.unwrap_err().kind(), "timeout");
+    assert_eq!(observed, ["ping"]);
+    assert!(node.received().contains(&"pong".to_owned()));
+}
+
+/// A SOCKS5 stand-in that connects every client to `target`, whatever destination it asks for.
+fn spawn_forwarding_proxy(target: SocketAddr) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for client in listener.incoming() {
+            let mut client = client.unwrap();
+            let mut greeting = [0u8; 3];
+            client.read_exact(&mut greeting).unwrap();
+            client.write_all(&[0x05, 0x00]).unwrap();
+            // Version, command, reserved, domain address type and length, domain and port
+            let mut request = [0u8; 5];
+            client.read_exact(&mut request).unwrap();
+            let mut destination = vec![0u8; request[4] as usize + 2];
+            client.read_exact(&mut destination).unwrap();
+            client
+                .write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+                .unwrap();
+
+            let upstream = TcpStream::connect(target).unwrap();
+            let (mut from_client, mut to_upstream) = (client.try_clone().unwrap(), upstream);
+            let (mut from_upstream, mut to_client) = (to_upstream.try_clone().unwrap(), client);
+            thread::spawn(move || io::copy(&mut from_client, &mut to_upstream));
+            thread::spawn(move || io::copy(&mut from_upstream, &mut to_client));
+        }
+    });
+    address
+}
+
+#[test]
+fn errors_name_the_onion_host() {
+    let node = spawn(Behavior::Misordered);
+    let proxy = spawn_forwarding_proxy(node.address());
+    let options = ConnectOptions {
+        proxy: Some(ProxyConfig {
+            addr: proxy.to_string(),
+            remote_dns: true,
+            isolate_streams: false,
+            username: None,
+            password: None,
+        }),
+        timeout: Some(Duration::from_secs(1)),
+        ..ConnectOptions::default()
+    };
+    let host = "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd.onion";
+    let mut report = HandshakeReport::new(format!("{host}:8333"), NETWORK);
+
+    let error = handshake(&NETWORK, host, 8333, &options, &mut report)
+        .err()
+        .unwrap();
+
+    assert_eq!(error.kind(), "protocol_violation");
+    assert_eq!(
+        error.to_string(),
+        format!("protocol violation by {host}:8333: verack before version")
+    );
+}
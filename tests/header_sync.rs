@@ -0,0 +1,149 @@
+mod common;
+
+use std::collections::HashMap;
+
+use common::{connect, headers_payload, mine_branch, mine_chain, NETWORK};
+use handshaker::{
+    chain_params::REGTEST,
+    error::Error,
+    fake_node::FakeNode,
+    header_sync::{download_headers, sync_headers, HeaderChain},
+    messages::{block::BlockHeader, message::hash_to_hex},
+    pow::check_proof_of_work,
+    run,
+};
+
+/// Spawns a node serving `headers` in answer to `getheaders`, 2000 at a time like Bitcoin Core.
+fn serve(headers: Vec<BlockHeader>) -> FakeNode {
+    let heights: HashMap<[u8; 32], usize> = std::iter::once(REGTEST.genesis_hash)
+        .chain(headers.iter().map(BlockHeader::hash))
+        .enumerate()
+        .map(|(height, hash)| (hash, height))
+        .collect();
+    FakeNode::new(NETWORK).with_responder(move |command, payload| {
+        if command != "getheaders" {
+            return vec![];
+        }
+        // Version, a locator of less than 253 hashes, then the stop hash
+        let start = payload[5..payload.len() - 32]
+            .chunks_exact(32)
+            .find_map(|hash| heights.get(hash))
+            .copied()
+            .unwrap_or(0);
+        let batch = &headers[start..headers.len().min(start + 2000)];
+        vec![("headers".to_owned(), headers_payload(batch))]
+    })
+}
+
+#[test]
+fn syncs_and_validates_headers() {
+    let headers = mine_chain(2500);
+    let tip = hash_to_hex(&headers[2499].hash());
+    let node = serve(headers).spawn().unwrap();
+    let (report, mut connection) = connect(&node);
+    let claimed_height = report.peer_version.map(|version| version.start_height);
+
+    let synced = sync_headers(&mut connection, claimed_height).unwrap();
+    assert_eq!(synced.height, 2500);
+    assert_eq!(synced.hash, tip);
+    assert_eq!(synced.headers_received, 2500);
+    assert_eq!(synced.claim_verified, Some(true));
+    // Two full batches: 2000 headers, then the remaining 500
+    assert_eq!(
+        node.received()
+            .iter()
+            .filter(|command| *command == "getheaders")
+            .count(),
+        2
+    );
+}
+
+#[test]
+fn rejects_headers_without_proof_of_work() {
+    let mut headers = mine_chain(10);
+    headers[5].nonce += 1;
+    while check_proof_of_work(headers[5].hash(), headers[5].bits, &REGTEST) {
+        headers[5].nonce += 1;
+    }
+    let node = serve(headers).spawn().unwrap();
+    let error = run([
+        "handshaker",
+        "--network",
+        "regtest",
+        "--timeout",
+        "1",
+        "--retries",
+        "0",
+        "headers",
+        &node.address().to_string(),
+    ]
+    .map(String::from)
+    .to_vec())
+    .unwrap_err();
+    assert_eq!(error.kind(), "protocol_violation");
+    assert!(error
+        .to_string()
+        .contains("at height 6 does not meet its target"));
+}
+
+/// Syncs `headers` from a node, which then answers a locator ending at their tip with `fork`,
+/// and returns the chain and the result of syncing again.
+fn sync_then_fork(
+    headers: Vec<BlockHeader>,
+    fork: Vec<BlockHeader>,
+) -> (HeaderChain, Result<usize, Error>) {
+    let tip = headers.last().unwrap().hash();
+    let node = FakeNode::new(NETWORK)
+        .with_responder(move |command, payload| {
+            if command != "getheaders" {
+                return vec![];
+            }
+            let batch = if payload[5..37] == tip {
+                &fork
+            } else {
+                &headers
+            };
+            vec![("headers".to_owned(), headers_payload(batch))]
+        })
+        .spawn()
+        .unwrap();
+    let (_, mut connection) = connect(&node);
+    let mut chain = HeaderChain::new(REGTEST);
+    download_headers(&mut connection, &mut chain).unwrap();
+    let result = download_headers(&mut connection, &mut chain);
+    (chain, result)
+}
+
+#[test]
+fn keeps_tip_when_fork_is_invalid() {
+    let headers = mine_chain(20);
+    let tip = headers[19].hash();
+    let mut fork = mine_branch(headers[4].hash(), headers[4].time + 100, 30);
+    fork[25].nonce += 1;
+    while check_proof_of_work(fork[25].hash(), fork[25].bits, &REGTEST) {
+        fork[25].nonce += 1;
+    }
+
+    let (chain, result) = sync_then_fork(headers, fork);
+
+    let error = result.unwrap_err();
+    assert_eq!(error.kind(), "protocol_violation");
+    assert!(error
+        .to_string()
+        .contains("at height 31 does not meet its target"));
+    assert_eq!(chain.height(), 20);
+    assert_eq!(chain.tip(), tip);
+}
+
+#[test]
+fn keeps_tip_when_fork_has_less_work() {
+    let headers = mine_chain(20);
+    let tip = headers[19].hash();
+    let fork = mine_branch(headers[4].hash(), headers[4].time + 100, 10);
+
+    let (chain, result) = sync_then_fork(headers, fork);
+
+    assert_eq!(result.unwrap(), 10);
+    assert_eq!(chain.height(), 20);
+    assert_eq!(chain.tip(), tip);
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use common::{connect_with, NETWORK};
+use handshaker::{
+    fake_node::FakeNode,
+    handshake::ConnectOptions,
+    mempool::{watch_mempool, SeenTransactions},
+    messages::{
+        inventory::{
+            inventory_payload, parse_inventory, Inventory, MSG_BLOCK, MSG_TX, MSG_WITNESS_TX,
+            MSG_WTX,
+        },
+        message::hash_to_hex,
+        transaction::{OutPoint, Transaction, TxIn, TxOut},
+    },
+};
+
+fn transaction(value: u64) -> Transaction {
+    Transaction {
+        version: 2,
+        input: vec![TxIn {
+            previous_output: OutPoint {
+                txid: [1; 32],
+                vout: 0,
+            },
+            script_sig: Vec::new(),
+            sequence: u32::MAX,
+            witness: vec![vec![2; 72], vec![3; 33]],
+        }],
+        output: vec![TxOut {
+            value,
+            script_pubkey: vec![0x51],
+        }],
+        lock_time: 0,
+    }
+}
+
+#[test]
+fn fetches_announced_transactions_once() {
+    let by_wtxid = transaction(1000);
+    let by_txid = transaction(2000);
+    let announcement = inventory_payload(&[
+        Inventory::new(MSG_WTX, by_wtxid.wtxid()),
+        Inventory::new(MSG_TX, by_txid.txid()),
+        Inventory::new(MSG_BLOCK, [9; 32]),
+    ]);
+    let served = [
+        (Inventory::new(MSG_WTX, by_wtxid.wtxid()), by_wtxid.clone()),
+        (
+            Inventory::new(MSG_WITNESS_TX, by_txid.txid()),
+            by_txid.clone(),
+        ),
+    ];
+    let node = FakeNode::new(NETWORK)
+        .with_announcements(vec![("inv".to_owned(), announcement)])
+        .with_responder(move |command, payload| match command {
+            "getdata" => parse_inventory(payload)
+                .unwrap()
+                .iter()
+                .filter_map(|item| served.iter().find(|(served, _)| served == item))
+                .map(|(_, transaction)| ("tx".to_owned(), transaction.serialize()))
+                .collect(),
+            _ => vec![],
+        })
+        .spawn()
+        .unwrap();
+
+    let mut seen = SeenTransactions::new(100);
+    let mut observed = Vec::new();
+    // The node announces the same transactions on every connection
+    for _ in 0..2 {
+        let options = ConnectOptions {
+            relay: true,
+            ..ConnectOptions::default()
+        };
+        let (_, mut connection) = connect_with(&node, options);
+        let result = watch_mempool(&mut connection, &mut seen, |transaction| {
+            observed.push(transaction)
+        });
+        assert_eq!(result.unwrap_err().kind(), "timeout");
+    }
+
+    let txids: Vec<&str> = observed.iter().map(|tx| tx.txid.as_str()).collect();
+    assert_eq!(
+        txids,
+        [hash_to_hex(&by_wtxid.txid()), hash_to_hex(&by_txid.txid())]
+    );
+    assert_eq!(observed[1].value, 2000);
+    assert_eq!(observed[1].hex, hex::encode(by_txid.serialize()));
+    // Nothing is requested on the second connection
+    assert_eq!(
+        node.received()
+            .iter()
+            .filter(|command| *command == "getdata")
+            .count(),
+        1
+    );
+}